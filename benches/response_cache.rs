@@ -0,0 +1,97 @@
+//! Compares cached and uncached GET dispatch. Run with `cargo bench --bench response_cache`.
+
+use futures::{Future, TryStreamExt, executor::block_on};
+use pathlink::Link;
+use std::{hint::black_box, pin::Pin, str::FromStr, time::Instant};
+use tc_error::{TCError, TCResult};
+use tc_ir::{Claim, HandleGet, NetworkTime, Transaction, TxnHeader, TxnId};
+use tc_value::Value;
+use tc_wasm::{WasmTransaction, try_dispatch_get_bytes, try_dispatch_get_cached_bytes};
+use umask::Mode;
+
+const ITERATIONS: u32 = 10_000;
+
+#[derive(Clone)]
+struct BenchTxn {
+    header: TxnHeader,
+}
+
+impl Transaction for BenchTxn {
+    fn id(&self) -> TxnId {
+        self.header.id()
+    }
+
+    fn timestamp(&self) -> NetworkTime {
+        self.header.timestamp()
+    }
+
+    fn claim(&self) -> &Claim {
+        self.header.claim()
+    }
+}
+
+impl WasmTransaction for BenchTxn {
+    fn from_wasm_header(header: TxnHeader) -> TCResult<Self> {
+        Ok(Self { header })
+    }
+}
+
+/// Simulates an expensive pure handler.
+struct SlowHandler;
+
+impl HandleGet<BenchTxn> for SlowHandler {
+    type Request = Value;
+    type RequestContext = ();
+    type Response = Value;
+    type Error = TCError;
+    type Fut<'a> = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+    fn get<'a>(&'a self, _txn: &'a BenchTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+        Ok(Box::pin(async move {
+            let digest = (0..10_000u64).fold(0u64, |acc, n| acc.wrapping_mul(31).wrapping_add(n));
+            Ok(Value::String(format!("{request:?}:{digest}")))
+        }))
+    }
+}
+
+fn header_bytes() -> Vec<u8> {
+    let claim = Claim::new(Link::from_str("/lib").expect("claim link"), Mode::all());
+    let id = TxnId::from_parts(NetworkTime::from_nanos(1), 7);
+    let header = TxnHeader::new(id, NetworkTime::from_nanos(1), claim);
+    let stream = destream_json::encode(header).expect("header json");
+    block_on(stream.try_fold(Vec::new(), |mut acc, chunk| async move {
+        acc.extend_from_slice(&chunk);
+        Ok(acc)
+    }))
+    .expect("header json")
+}
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+
+    println!("{name}: {:?} per call", start.elapsed() / ITERATIONS);
+}
+
+fn main() {
+    let header = header_bytes();
+    let body = br#""bench""#;
+
+    bench("uncached", || {
+        let response =
+            try_dispatch_get_bytes::<_, BenchTxn, Value, Value>(&SlowHandler, &header, body);
+        black_box(response.expect("response"));
+    });
+
+    bench("cached", || {
+        let response = try_dispatch_get_cached_bytes::<_, BenchTxn, Value, Value>(
+            "/bench",
+            &SlowHandler,
+            &header,
+            body,
+        );
+        black_box(response.expect("response"));
+    });
+}
@@ -0,0 +1,37 @@
+//! Compares the scalar request fast path with the JSON decoders.
+//! Run with `cargo bench --bench scalar_decode`.
+
+use std::{hint::black_box, time::Instant};
+use tc_value::{Number, Value};
+use tc_wasm::WasmRequest;
+
+const ITERATIONS: u32 = 100_000;
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+
+    println!("{name}: {:?} per call", start.elapsed() / ITERATIONS);
+}
+
+fn main() {
+    let body = b"1234567890";
+
+    bench("u64 (fast path)", || {
+        black_box(u64::decode(black_box(body)).expect("u64"));
+    });
+
+    bench("Number (fast path)", || {
+        black_box(Number::decode(black_box(body)).expect("number"));
+    });
+
+    bench("serde_json", || {
+        black_box(serde_json::from_slice::<u64>(black_box(body)).expect("u64"));
+    });
+
+    bench("Value (destream_json)", || {
+        black_box(Value::decode(black_box(body)).expect("value"));
+    });
+}
@@ -0,0 +1,34 @@
+//! Compares std and SIMD UTF-8 validation of a 1 MiB text body.
+//! Run with `cargo bench --bench utf8_validation --features simd`.
+
+use std::{hint::black_box, time::Instant};
+use tc_wasm::WasmRequest;
+
+const ITERATIONS: u32 = 200;
+const BODY_LEN: usize = 1 << 20;
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+
+    println!("{name}: {:?} per call", start.elapsed() / ITERATIONS);
+}
+
+fn main() {
+    let body = "héllo wörld ".repeat(BODY_LEN / "héllo wörld ".len());
+    let body = body.as_bytes();
+
+    bench("std", || {
+        black_box(std::str::from_utf8(black_box(body)).expect("utf-8"));
+    });
+
+    bench("simd", || {
+        black_box(simdutf8::basic::from_utf8(black_box(body)).expect("utf-8"));
+    });
+
+    bench("String::decode", || {
+        black_box(String::decode(black_box(body)).expect("text"));
+    });
+}
@@ -20,7 +20,7 @@ mod wasm_example {
         TxnId, tc_library_routes,
     };
     use tc_value::Value;
-    use tc_wasm::{RouteExport, WasmTransaction, dispatch_get, manifest_bytes};
+    use tc_wasm::{RouteExport, WasmTransaction, dispatch_get};
 
     #[derive(Clone)]
     struct ExampleTxn {
@@ -96,7 +96,11 @@ mod wasm_example {
     static LIBRARY: Lazy<HelloLibrary> = Lazy::new(|| hello_library().expect("library"));
     static HELLO_HANDLER: Lazy<HelloHandler> = Lazy::new(|| HelloHandler);
 
-    const ROUTES: &[RouteExport] = &[RouteExport::new("/hello", "hello")];
+    const ROUTES: &[RouteExport] = tc_wasm::route_exports![
+        "/hello" => hello
+            .with_example_request(r#""Ada""#)
+            .with_example_response(r#""Hello, Ada!""#),
+    ];
 
     #[unsafe(no_mangle)]
     pub extern "C" fn alloc(len: i32) -> i32 {
@@ -110,7 +114,33 @@ mod wasm_example {
 
     #[unsafe(no_mangle)]
     pub extern "C" fn tc_library_entry() -> i64 {
-        tc_wasm::leak_bytes(manifest_bytes(&*LIBRARY, ROUTES))
+        tc_wasm::library_entry(&*LIBRARY, ROUTES, Some(tc_wasm::MANIFEST_STREAM_EXPORT))
+    }
+
+    /// Serves the manifest in chunks if it's too large for `tc_library_entry` (`0` on error).
+    #[unsafe(no_mangle)]
+    pub extern "C" fn tc_library_entry_stream() -> i64 {
+        tc_wasm::manifest_stream(&*LIBRARY, ROUTES).map_or(0, i64::from)
+    }
+
+    #[unsafe(no_mangle)]
+    pub extern "C" fn tc_stream_next(handle: i32) -> i64 {
+        tc_wasm::stream_next(handle)
+    }
+
+    #[unsafe(no_mangle)]
+    pub extern "C" fn tc_stream_close(handle: i32) {
+        tc_wasm::stream_close(handle)
+    }
+
+    #[unsafe(no_mangle)]
+    pub extern "C" fn tc_diagnostics() -> i64 {
+        tc_wasm::diagnostics()
+    }
+
+    #[unsafe(no_mangle)]
+    pub extern "C" fn tc_route_options(path_ptr: i32, path_len: i32) -> i64 {
+        tc_wasm::route_options(ROUTES, path_ptr, path_len)
     }
 
     #[unsafe(no_mangle)]
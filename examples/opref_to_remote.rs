@@ -16,11 +16,14 @@ mod wasm_example {
     use std::str::FromStr;
     use tc_error::TCResult;
     use tc_ir::{
-        Claim, Dir, HandleGet, LibrarySchema, NetworkTime, OpRef, Scalar, StaticLibrary, Subject,
-        Transaction, TxnHeader, TxnId,
+        Claim, Dir, HandleGet, NetworkTime, OpRef, Scalar, StaticLibrary, Subject, Transaction,
+        TxnHeader, TxnId,
     };
     use tc_value::Value;
-    use tc_wasm::{RouteExport, WasmTransaction, dispatch_get, manifest_bytes};
+    use tc_wasm::{
+        DependencyRange, LibrarySchemaBuilder, RouteExport, WasmTransaction, dispatch_get,
+        manifest_bytes,
+    };
 
     const A_ROOT: &str = "/lib/example-devco/a/0.1.0";
     const B_ROOT: &str = "/lib/example-devco/example/0.1.0";
@@ -100,21 +103,21 @@ mod wasm_example {
         }
     }
 
-    fn library() -> TCResult<Library> {
-        let schema = LibrarySchema::new(
-            Link::from_str(A_ROOT).expect("schema link"),
-            "0.1.0",
-            vec![Link::from_str(B_ROOT).expect("dependency link")],
-        );
-        Ok(StaticLibrary::new(schema, Dir::new()))
+    fn library() -> TCResult<(Library, Vec<DependencyRange>)> {
+        let (schema, ranges) = LibrarySchemaBuilder::new(A_ROOT, "0.1.0")?
+            .dependency_in_range(B_ROOT, "^0.1")?
+            .build_with_ranges();
+
+        Ok((StaticLibrary::new(schema, Dir::new()), ranges))
     }
 
-    static LIBRARY: Lazy<Library> = Lazy::new(|| library().expect("library"));
+    static LIBRARY: Lazy<(Library, Vec<DependencyRange>)> =
+        Lazy::new(|| library().expect("library"));
     static FROM_B_HANDLER: Lazy<FromBHandler> = Lazy::new(|| FromBHandler);
     static AUTH_CONTEXT_HANDLER: Lazy<AuthContextHandler> = Lazy::new(|| AuthContextHandler);
-    const ROUTES: &[RouteExport] = &[
-        RouteExport::new("/from_b", "from_b"),
-        RouteExport::new("/auth_context", "auth_context"),
+    const ROUTES: &[RouteExport] = tc_wasm::route_exports![
+        "/from_b" => from_b,
+        "/auth_context" => auth_context,
     ];
 
     #[unsafe(no_mangle)]
@@ -129,7 +132,12 @@ mod wasm_example {
 
     #[unsafe(no_mangle)]
     pub extern "C" fn tc_library_entry() -> i64 {
-        tc_wasm::leak_bytes(manifest_bytes(&*LIBRARY, ROUTES))
+        tc_wasm::leak_bytes(manifest_bytes(&LIBRARY.0, ROUTES).expect("manifest"))
+    }
+
+    #[unsafe(no_mangle)]
+    pub extern "C" fn tc_check_dependencies(resolved_ptr: i32, resolved_len: i32) -> i64 {
+        tc_wasm::check_dependencies(&LIBRARY.1, resolved_ptr, resolved_len)
     }
 
     #[unsafe(no_mangle)]
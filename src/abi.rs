@@ -1,36 +1,612 @@
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
 use bytes::Bytes;
 use destream::{
     de,
     en::{self, EncodeMap, EncodeSeq},
 };
-use futures::{TryStreamExt, executor::block_on, stream};
-use std::{io, mem, slice};
-use tc_error::{TCError, TCResult};
-use tc_ir::{Library, LibrarySchema, OpRef, TCRef, Transaction, TxnHeader};
-use tc_value::Value;
+use futures::{TryStreamExt, stream};
+use pathlink::Link;
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet},
+    io,
+    time::Duration,
+};
+use tc_error::{ErrorKind, TCError, TCResult};
+use tc_ir::{Claim, Library, LibrarySchema, OpRef, TCRef, Transaction, TxnHeader};
+use tc_value::{Int, Number, UInt, Value};
+use umask::Mode;
+
+use crate::codec::{
+    Codec, begin_call_codecs, encode_response, request_body, response_codec, strip_codecs,
+};
+use crate::correlation::{begin_call_txn_id, take_call_txn_id, with_txn_id};
+use crate::executor::block_on;
+use crate::host::{self, HostImport};
+use crate::integrity::with_integrity_hash;
+use crate::memory;
+use crate::timing::{
+    ServerTimer, begin_call_timing, server_timing_enabled, take_handler_ns, with_server_timing,
+};
+use crate::validate::{FieldError, clear_field_errors, take_field_errors};
+
+/// The version of the host ABI this crate implements, bumped on any incompatible change to
+/// the export signatures, header fields or response framing.
+pub const ABI_VERSION: u32 = 1;
+
+/// The default maximum length of a single string inside a request (1 MiB).
+pub const DEFAULT_MAX_STRING_LEN: usize = 1 << 20;
+
+/// The default maximum size of an encoded response (16 MiB).
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 16 << 20;
+
+/// The default maximum size of the manifest returned by [`library_entry`] (1 MiB).
+pub const DEFAULT_MAX_MANIFEST_BYTES: usize = 1 << 20;
+
+/// The conventional name of the export which returns a [`manifest_stream`] handle.
+pub const MANIFEST_STREAM_EXPORT: &str = "tc_library_entry_stream";
+
+thread_local! {
+    static REDACT_ERRORS: Cell<bool> = const { Cell::new(false) };
+    static PROBLEM_JSON: Cell<bool> = const { Cell::new(false) };
+    static DUPLICATE_KEYS: Cell<DuplicateKeys> = const { Cell::new(DuplicateKeys::Reject) };
+    static MAX_STRING_LEN: Cell<usize> = const { Cell::new(DEFAULT_MAX_STRING_LEN) };
+    static MAX_RESPONSE_BYTES: Cell<usize> = const { Cell::new(DEFAULT_MAX_RESPONSE_BYTES) };
+    static MAX_MANIFEST_BYTES: Cell<usize> = const { Cell::new(DEFAULT_MAX_MANIFEST_BYTES) };
+    static MAX_REQUEST_AGE: Cell<Option<MaxRequestAge>> = const { Cell::new(None) };
+    static CLAIM_SCOPE: RefCell<Option<Link>> = const { RefCell::new(None) };
+    static READ_ONLY: Cell<bool> = const { Cell::new(false) };
+    static ROUTE_PATH: Cell<Option<&'static str>> = const { Cell::new(None) };
+    static DEFAULT_TIMEOUT: Cell<Option<Timeout>> = const { Cell::new(None) };
+    static DEADLINE: Cell<Option<Deadline>> = const { Cell::new(None) };
+}
+
+const TRACK_LEAKS: bool = cfg!(any(
+    debug_assertions,
+    test,
+    feature = "leak-tracking",
+    feature = "testing"
+));
+
+thread_local! {
+    static OUTSTANDING_BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+/// When enabled, error responses carry only a generic message for the error code, and (with
+/// the `log-redacted-errors` feature) the detailed message is written to the host log
+/// instead. Disabled by default.
+pub fn set_error_redaction(enabled: bool) {
+    REDACT_ERRORS.with(|redact| redact.set(enabled));
+}
+
+/// When enabled, error responses are RFC 7807 problem details,
+/// `{"type": "about:blank", "title": "Not Found", "status": 404, "detail": "<message>"}`,
+/// for generic HTTP clients, instead of the compact `{"error": "<message>", "retryable": <bool>}`
+/// which TinyChain hosts expect. Disabled by default.
+pub fn set_problem_json(enabled: bool) {
+    PROBLEM_JSON.with(|problem_json| problem_json.set(enabled));
+}
+
+/// How to treat a JSON object in a request (or header) which repeats a key.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DuplicateKeys {
+    /// Reject the request as a bad request, since different parsers may disagree about which
+    /// value wins (the default).
+    #[default]
+    Reject,
+    /// Keep the last value for each key.
+    LastWins,
+}
+
+/// Set the [`DuplicateKeys`] policy for decoding JSON objects.
+pub fn set_duplicate_key_policy(policy: DuplicateKeys) {
+    DUPLICATE_KEYS.with(|current| current.set(policy));
+}
+
+/// Set the maximum length, in encoded bytes, of any single string inside a JSON request.
+/// Requests with a longer string are rejected before they're decoded.
+pub fn set_max_string_len(max_len: usize) {
+    MAX_STRING_LEN.with(|max| max.set(max_len));
+}
+
+/// Set the maximum size of an encoded response. A dispatch whose response exceeds this
+/// limit returns a small error payload instead of handing the full buffer to the host.
+pub fn set_max_response_bytes(max_bytes: usize) {
+    MAX_RESPONSE_BYTES.with(|max| max.set(max_bytes));
+}
+
+/// Set the maximum size of the manifest returned by [`library_entry`]. A larger manifest is
+/// replaced with a small error manifest which directs the host to [`MANIFEST_STREAM_EXPORT`].
+pub fn set_max_manifest_bytes(max_bytes: usize) {
+    MAX_MANIFEST_BYTES.with(|max| max.set(max_bytes));
+}
+
+/// Set the time budget of a route dispatched by a `dispatch_*_scoped` function which doesn't
+/// declare its own via [`RouteExport::with_timeout_ms`]. Disabled (`None`) by default.
+pub fn set_default_timeout(timeout: Option<Duration>) {
+    let timeout = timeout.map(|timeout| (timeout, host_nanos as fn() -> u64));
+    DEFAULT_TIMEOUT.with(|default| default.set(timeout));
+}
+
+/// Whether a setting which reads the host's clock is enabled: server timing, a maximum request
+/// age or a default timeout.
+pub(crate) fn uses_host_clock() -> bool {
+    server_timing_enabled()
+        || MAX_REQUEST_AGE.with(Cell::get).is_some()
+        || DEFAULT_TIMEOUT.with(Cell::get).is_some()
+}
+
+/// A time budget with the clock to enforce it by. The clock is only referenced where a budget
+/// is set, so a library which never sets one doesn't import `tc_now`.
+type Timeout = (Duration, fn() -> u64);
+
+fn host_nanos() -> u64 {
+    host::now().as_nanos()
+}
+
+/// Reject requests whose transaction timestamp is older than `max_age` relative to
+/// [`host::now`], before running the handler. Disabled (`None`) by default.
+pub fn set_max_request_age(max_age: Option<Duration>) {
+    let max_age = max_age.map(|max_age| (max_age, (|| host::now().as_nanos()) as fn() -> u64));
+    MAX_REQUEST_AGE.with(|max| max.set(max_age));
+}
+
+/// The maximum request age with the clock to measure it by. The clock is only referenced by
+/// [`set_max_request_age`], so a library which never sets one doesn't import `tc_now`.
+type MaxRequestAge = (Duration, fn() -> u64);
+
+/// Reject requests whose claim is for an unrelated library, i.e. whose link neither covers
+/// nor falls within `scope` (typically the [`crate::library_root`] of the library's schema),
+/// as forbidden before running the handler. Disabled (`None`) by default.
+pub fn set_claim_scope(scope: Option<Link>) {
+    CLAIM_SCOPE.with(|current| *current.borrow_mut() = scope);
+}
+
+/// Declare the whole library read-only (e.g. a mirror or cache), so every PUT, POST and
+/// DELETE is rejected as forbidden before its handler runs, and the manifest advertises
+/// `"read_only": true`. Disabled by default.
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.with(|current| current.set(read_only));
+}
+
+/// Whether the library has been declared read-only via [`set_read_only`].
+pub fn is_read_only() -> bool {
+    READ_ONLY.with(Cell::get)
+}
+
+fn check_writable(method: Method) -> TCResult<()> {
+    if method != Method::Get && is_read_only() {
+        Err(TCError::forbidden("read-only library"))
+    } else {
+        Ok(())
+    }
+}
 
 /// Routes exported by a WASM library (path -> wasm export name).
 #[derive(Clone, Copy)]
 pub struct RouteExport {
     pub path: &'static str,
     pub export: &'static str,
+    method: Option<Method>,
+    /// The mode (e.g. [`crate::claim::WRITE`]) the caller's claim must include, if any.
+    required_mode: Option<Mode>,
+    /// The paths of other routes which the host must mount before this one.
+    depends_on: &'static [&'static str],
+    /// The host imports this route's handler calls.
+    imports: &'static [HostImport],
+    /// An example request body, as JSON, for documentation tools.
+    example_request: Option<&'static str>,
+    /// An example response body, as JSON, for documentation tools.
+    example_response: Option<&'static str>,
+    /// This route's time budget in milliseconds, overriding the default timeout, with the
+    /// clock to enforce it by.
+    timeout_ms: Option<(u64, fn() -> u64)>,
 }
 
 impl RouteExport {
     pub const fn new(path: &'static str, export: &'static str) -> Self {
-        Self { path, export }
+        Self {
+            path,
+            export,
+            method: None,
+            required_mode: None,
+            depends_on: &[],
+            imports: &[],
+            example_request: None,
+            example_response: None,
+            timeout_ms: None,
+        }
+    }
+
+    /// Declare the paths of other routes in the same library which must be mounted first.
+    pub const fn with_dependencies(mut self, depends_on: &'static [&'static str]) -> Self {
+        self.depends_on = depends_on;
+        self
+    }
+
+    /// Declare the host imports this route's handler calls (e.g. [`HostImport::Log`]), which
+    /// the manifest and [`crate::diagnostics_for`] list for the host to check.
+    pub const fn with_imports(mut self, imports: &'static [HostImport]) -> Self {
+        self.imports = imports;
+        self
+    }
+
+    /// Require the caller's claim to include every bit of `mode`, which the `_scoped`
+    /// dispatch functions check before invoking the handler.
+    pub const fn with_required_mode(mut self, mode: Mode) -> Self {
+        self.required_mode = Some(mode);
+        self
+    }
+
+    /// The mode the caller's claim must include (no bits, unless one was declared).
+    pub fn required_mode(&self) -> Mode {
+        Mode::from(self.required_bits())
+    }
+
+    fn required_bits(&self) -> u32 {
+        self.required_mode.map_or(0, u32::from)
+    }
+
+    /// Check that `claim` grants every bit of this route's required mode.
+    pub fn authorize(&self, claim: &Claim) -> TCResult<()> {
+        let granted = u32::from(claim.mode());
+        let required = self.required_bits();
+        if granted & required == required {
+            Ok(())
+        } else {
+            Err(TCError::forbidden(format!(
+                "{} requires mode {:o} but the claim grants {:o}",
+                self.path, required, granted
+            )))
+        }
+    }
+
+    /// Declare the single method this route's export serves.
+    pub const fn with_method(mut self, method: Method) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    /// The single method this route's export serves, if declared.
+    pub const fn method(&self) -> Option<Method> {
+        self.method
+    }
+
+    /// Give this route a time budget of `timeout_ms` milliseconds, overriding the default set
+    /// via [`set_default_timeout`] (e.g. a longer one for a slow analytics route).
+    pub const fn with_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.timeout_ms = Some((timeout_ms, host_nanos));
+        self
+    }
+
+    /// This route's time budget: its own, if declared, or else the default.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.budget().map(|(timeout, _)| timeout)
+    }
+
+    fn budget(&self) -> Option<Timeout> {
+        match self.timeout_ms {
+            Some((timeout_ms, now)) => Some((Duration::from_millis(timeout_ms), now)),
+            None => DEFAULT_TIMEOUT.with(Cell::get),
+        }
+    }
+
+    /// Attach an example request body (as JSON) to this route's manifest entry.
+    pub const fn with_example_request(mut self, json: &'static str) -> Self {
+        self.example_request = Some(json);
+        self
+    }
+
+    /// Attach an example response body (as JSON) to this route's manifest entry.
+    pub const fn with_example_response(mut self, json: &'static str) -> Self {
+        self.example_response = Some(json);
+        self
+    }
+
+    /// The names of the host imports this route declares, as they appear in the manifest.
+    pub fn import_names(&self) -> Vec<&'static str> {
+        self.imports.iter().map(|import| import.name()).collect()
+    }
+
+    /// Check that `path` is absolute, `export` is a valid identifier and any examples are
+    /// valid JSON, so a typo can't produce a manifest the host would silently mishandle.
+    pub fn validate(&self) -> TCResult<()> {
+        if !self.path.starts_with('/') {
+            return Err(TCError::bad_request(format!(
+                "route path {:?} must be absolute",
+                self.path
+            )));
+        }
+
+        if self
+            .path
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err(TCError::bad_request(format!(
+                "route path {:?} contains whitespace",
+                self.path
+            )));
+        }
+
+        let mut chars = self.export.chars();
+        let valid_export = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if !valid_export {
+            return Err(TCError::bad_request(format!(
+                "export name {:?} for route {} is not a valid identifier",
+                self.export, self.path
+            )));
+        }
+
+        for (kind, example) in [
+            ("request", self.example_request),
+            ("response", self.example_response),
+        ] {
+            if let Some(example) = example {
+                serde_json::from_str::<serde::de::IgnoredAny>(example).map_err(|err| {
+                    TCError::bad_request(format!(
+                        "example {kind} for route {} is not valid JSON: {err}",
+                        self.path
+                    ))
+                })?;
+            }
+        }
+
+        Ok(())
     }
 }
 
 impl<'en> en::IntoStream<'en> for RouteExport {
     fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
-        let mut map = encoder.encode_map(Some(2))?;
+        let len = 2
+            + usize::from(self.required_bits() != 0)
+            + usize::from(!self.depends_on.is_empty())
+            + usize::from(!self.imports.is_empty())
+            + usize::from(self.example_request.is_some())
+            + usize::from(self.example_response.is_some())
+            + usize::from(self.timeout_ms.is_some());
+
+        let mut map = encoder.encode_map(Some(len))?;
         map.encode_entry("path", self.path)?;
         map.encode_entry("export", self.export)?;
+        if self.required_bits() != 0 {
+            map.encode_entry("required_mode", self.required_bits())?;
+        }
+        if !self.depends_on.is_empty() {
+            map.encode_entry("depends_on", self.depends_on.to_vec())?;
+        }
+        if !self.imports.is_empty() {
+            map.encode_entry("imports", self.import_names())?;
+        }
+        if let Some(example) = self.example_request {
+            map.encode_entry("example_request", example)?;
+        }
+        if let Some(example) = self.example_response {
+            map.encode_entry("example_response", example)?;
+        }
+        if let Some((timeout_ms, _)) = self.timeout_ms {
+            map.encode_entry("timeout_ms", timeout_ms)?;
+        }
         map.end()
     }
 }
 
+/// [`RouteExport::validate`] every route, and check that no two routes share an export name
+/// (which would collide at the symbol level) or serve the same method of the same path, and
+/// that their dependencies can be ordered (see [`route_init_order`]).
+pub fn validate_routes(routes: &[RouteExport]) -> TCResult<()> {
+    let mut exports = BTreeSet::new();
+    let mut paths: BTreeMap<&str, Vec<Option<Method>>> = BTreeMap::new();
+
+    for route in routes {
+        route.validate()?;
+
+        if !exports.insert(route.export) {
+            return Err(TCError::bad_request(format!(
+                "duplicate export {}",
+                route.export
+            )));
+        }
+
+        let methods = paths.entry(route.path).or_default();
+        let overlaps = |method: &Option<Method>| match (method, &route.method) {
+            (Some(served), Some(method)) => served == method,
+            _ => true,
+        };
+
+        if methods.iter().any(overlaps) {
+            return Err(TCError::bad_request(format!(
+                "duplicate route path {}",
+                route.path
+            )));
+        }
+
+        methods.push(route.method);
+    }
+
+    route_init_order(routes).map(|_| ())
+}
+
+/// Order `routes` so that every route comes after the routes it depends on, for the host to
+/// mount them in. Fails if a route depends on an unknown path or the dependencies form a cycle.
+pub fn route_init_order(routes: &[RouteExport]) -> TCResult<Vec<&'static str>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Visit {
+        InProgress,
+        Done,
+    }
+
+    fn visit(
+        path: &'static str,
+        routes: &[RouteExport],
+        visits: &mut BTreeMap<&'static str, Visit>,
+        stack: &mut Vec<&'static str>,
+        order: &mut Vec<&'static str>,
+    ) -> TCResult<()> {
+        match visits.get(path) {
+            Some(Visit::Done) => return Ok(()),
+            Some(Visit::InProgress) => {
+                stack.push(path);
+                let start = stack.iter().position(|p| *p == path).unwrap_or_default();
+                return Err(TCError::bad_request(format!(
+                    "route dependency cycle: {}",
+                    stack[start..].join(" -> ")
+                )));
+            }
+            None => {}
+        }
+
+        let route = routes
+            .iter()
+            .find(|route| route.path == path)
+            .ok_or_else(|| TCError::bad_request(format!("unknown route dependency {path}")))?;
+
+        visits.insert(path, Visit::InProgress);
+        stack.push(path);
+
+        for dependency in route.depends_on {
+            visit(*dependency, routes, visits, stack, order)?;
+        }
+
+        stack.pop();
+        visits.insert(path, Visit::Done);
+        order.push(path);
+        Ok(())
+    }
+
+    let mut visits = BTreeMap::new();
+    let mut order = Vec::with_capacity(routes.len());
+
+    for route in routes {
+        visit(route.path, routes, &mut visits, &mut Vec::new(), &mut order)?;
+    }
+
+    Ok(order)
+}
+
+/// A structured description of an exported route, for clients and codegen tools.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct RouteInfo {
+    pub path: String,
+    pub export: String,
+    pub method: Option<Method>,
+    pub required_mode: Mode,
+    pub depends_on: Vec<String>,
+    pub imports: Vec<HostImport>,
+    pub example_request: Option<String>,
+    pub example_response: Option<String>,
+    pub timeout_ms: Option<u64>,
+    pub version: String,
+}
+
+/// The in-process counterpart of [`manifest_bytes`]: the library's routes as a typed list.
+pub fn library_routes<L: Library>(library: &L, routes: &[RouteExport]) -> Vec<RouteInfo> {
+    let version = library.schema().version();
+
+    routes
+        .iter()
+        .map(|route| RouteInfo {
+            path: route.path.to_string(),
+            export: route.export.to_string(),
+            method: route.method,
+            required_mode: route.required_mode(),
+            depends_on: route
+                .depends_on
+                .iter()
+                .map(|path| path.to_string())
+                .collect(),
+            imports: route.imports.to_vec(),
+            example_request: route.example_request.map(String::from),
+            example_response: route.example_response.map(String::from),
+            timeout_ms: route.timeout_ms.map(|(timeout_ms, _)| timeout_ms),
+            version: version.to_string(),
+        })
+        .collect()
+}
+
+/// The path of the route being dispatched by a `dispatch_*_scoped` function, so a handler
+/// mounted at several paths can tell which one it was called through. `None` outside a
+/// scoped dispatch.
+pub fn route_path() -> Option<&'static str> {
+    ROUTE_PATH.with(Cell::get)
+}
+
+/// Sets the current [`route_path`] until dropped, restoring the previous one.
+struct RouteGuard {
+    previous: Option<&'static str>,
+}
+
+impl RouteGuard {
+    fn enter(path: &'static str) -> Self {
+        let previous = ROUTE_PATH.with(|current| current.replace(Some(path)));
+        Self { previous }
+    }
+}
+
+impl Drop for RouteGuard {
+    fn drop(&mut self) {
+        ROUTE_PATH.with(|current| current.set(self.previous));
+    }
+}
+
+/// Fail with a timeout error if the route being dispatched has used up its time budget (see
+/// [`RouteExport::timeout`]). WASM can't be preempted, so a long-running handler should call
+/// this (or [`crate::CancellationToken::check`], which does) between units of work; the
+/// budget is also checked once the handler returns.
+pub fn check_deadline() -> TCResult<()> {
+    let Some(deadline) = DEADLINE.with(Cell::get) else {
+        return Ok(());
+    };
+
+    if (deadline.now)() > deadline.at {
+        Err(TCError::timeout(format!(
+            "{} exceeded its timeout of {} ms",
+            deadline.path, deadline.timeout_ms
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Deadline {
+    at: u64,
+    now: fn() -> u64,
+    path: &'static str,
+    timeout_ms: u128,
+}
+
+/// Sets the current [`Deadline`] (if the route has a budget) until dropped.
+struct DeadlineGuard {
+    previous: Option<Deadline>,
+}
+
+impl DeadlineGuard {
+    fn enter(route: &RouteExport) -> Self {
+        let deadline = route.budget().map(|(timeout, now)| Deadline {
+            at: now().saturating_add(u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX)),
+            now,
+            path: route.path,
+            timeout_ms: timeout.as_millis(),
+        });
+
+        let previous = DEADLINE.with(|current| current.replace(deadline));
+        Self { previous }
+    }
+}
+
+impl Drop for DeadlineGuard {
+    fn drop(&mut self) {
+        DEADLINE.with(|current| current.set(self.previous));
+    }
+}
+
 pub trait WasmTransaction: Transaction + Sized {
     fn from_wasm_header(header: TxnHeader) -> TCResult<Self>;
 }
@@ -39,6 +615,19 @@ pub trait WasmRequest: Sized {
     fn decode(bytes: &[u8]) -> TCResult<Self>;
 }
 
+/// A request which distinguishes an absent body (`None`) from an empty one.
+pub trait WasmRequestOpt: Sized {
+    fn decode_opt(bytes: Option<&[u8]>) -> TCResult<Self>;
+}
+
+/// An absent body decodes as `None`; an empty body decodes as `Some` of the default
+/// for `T` (e.g. `Value::None`).
+impl<T: WasmRequest> WasmRequestOpt for Option<T> {
+    fn decode_opt(bytes: Option<&[u8]>) -> TCResult<Self> {
+        bytes.map(T::decode).transpose()
+    }
+}
+
 pub trait WasmResponse {
     fn encode(self) -> TCResult<Vec<u8>>;
 }
@@ -51,148 +640,568 @@ impl WasmRequest for String {
 
         match try_decode_json_slice((), bytes) {
             Ok(value) => Ok(value),
-            Err(_) => String::from_utf8(bytes.to_vec())
-                .map_err(|err| TCError::bad_request(format!("invalid utf-8 string: {err}"))),
+            Err(_) => {
+                let max = MAX_STRING_LEN.with(Cell::get);
+                if bytes.len() > max {
+                    return Err(string_too_long(max));
+                }
+
+                decode_utf8(bytes).map(str::to_string)
+            }
         }
     }
 }
 
+/// Inputs at least this long are validated with `simdutf8` when the `simd` feature is enabled.
+pub const SIMD_UTF8_THRESHOLD: usize = 4096;
+
+pub(crate) fn decode_utf8(bytes: &[u8]) -> TCResult<&str> {
+    #[cfg(feature = "simd")]
+    if bytes.len() >= SIMD_UTF8_THRESHOLD {
+        return simdutf8::compat::from_utf8(bytes)
+            .map_err(|err| TCError::bad_request(format!("invalid utf-8 string: {err}")));
+    }
+
+    std::str::from_utf8(bytes)
+        .map_err(|err| TCError::bad_request(format!("invalid utf-8 string: {err}")))
+}
+
 impl WasmRequest for Value {
     fn decode(bytes: &[u8]) -> TCResult<Self> {
         if bytes.is_empty() {
             return Ok(Value::None);
         }
 
+        if contains(bytes, BYTES_MARKER_KEY) {
+            let json = decode_serde_json(bytes)?;
+            return value_from_json(json);
+        }
+
         try_decode_json_slice((), bytes).map_err(TCError::bad_request)
     }
 }
 
-impl WasmResponse for String {
-    fn encode(self) -> TCResult<Vec<u8>> {
-        encode_json_bytes(self)
+/// `Value::Bytes` travels as a single-entry map with this key and a base64 string value,
+/// e.g. `{"$bytes": "3q2+7w=="}`, since JSON has no byte type.
+pub const BYTES_MARKER: &str = "$bytes";
+
+const BYTES_MARKER_KEY: &[u8] = b"\"$bytes\"";
+
+pub(crate) fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+/// Decode JSON which contains at least one [`BYTES_MARKER`]. Tuples are traversed here, the
+/// entries of any other object are re-encoded without their markers (see [`without_markers`])
+/// and anything else is handed back to the `destream` decoder.
+fn value_from_json(json: serde_json::Value) -> TCResult<Value> {
+    match json {
+        serde_json::Value::Array(items) => {
+            let items = items
+                .into_iter()
+                .map(value_from_json)
+                .collect::<TCResult<Vec<Value>>>()?;
+
+            Ok(Value::Tuple(items.into()))
+        }
+        serde_json::Value::Object(map) if map.len() == 1 && map.contains_key(BYTES_MARKER) => {
+            let encoded = map[BYTES_MARKER].as_str().ok_or_else(|| {
+                TCError::bad_request(format!("{BYTES_MARKER} must be a base64 string"))
+            })?;
+
+            let bytes = BASE64
+                .decode(encoded)
+                .map_err(|err| TCError::bad_request(format!("invalid {BYTES_MARKER}: {err}")))?;
+
+            Ok(Value::Bytes(bytes.into()))
+        }
+        serde_json::Value::Object(map) => {
+            let map = map
+                .into_iter()
+                .map(|(key, value)| without_markers(value).map(|value| (key, value)))
+                .collect::<TCResult<serde_json::Map<_, _>>>()?;
+
+            let bytes = serde_json::to_vec(&map).map_err(TCError::bad_request)?;
+            try_decode_json_slice((), &bytes).map_err(TCError::bad_request)
+        }
+        other => {
+            let bytes = serde_json::to_vec(&other).map_err(TCError::bad_request)?;
+            try_decode_json_slice((), &bytes).map_err(TCError::bad_request)
+        }
     }
 }
 
-impl WasmResponse for Value {
-    fn encode(self) -> TCResult<Vec<u8>> {
-        encode_json_bytes(self)
+/// Replace the [`BYTES_MARKER`]s nested in `json` (e.g. in the arguments of a link) with the
+/// `destream` encoding of the bytes they carry, so the enclosing object can be decoded.
+fn without_markers(json: serde_json::Value) -> TCResult<serde_json::Value> {
+    if !has_marker(&json) {
+        return Ok(json);
     }
+
+    let value = value_from_json(json)?;
+    let bytes = encode_json_bytes(value)?;
+    serde_json::from_slice(&bytes).map_err(TCError::internal)
 }
 
-impl WasmResponse for () {
-    fn encode(self) -> TCResult<Vec<u8>> {
-        encode_json_bytes(())
+fn has_marker(json: &serde_json::Value) -> bool {
+    match json {
+        serde_json::Value::Array(items) => items.iter().any(has_marker),
+        serde_json::Value::Object(map) => {
+            map.contains_key(BYTES_MARKER) || map.values().any(has_marker)
+        }
+        _ => false,
     }
 }
 
-impl WasmResponse for OpRef {
-    fn encode(self) -> TCResult<Vec<u8>> {
-        encode_json_bytes(self)
+/// Encodes a [`Value`] with every `Value::Bytes` replaced by a [`BYTES_MARKER`] map.
+struct Base64Value(Value);
+
+impl<'en> en::IntoStream<'en> for Base64Value {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        match self.0 {
+            Value::Bytes(bytes) => {
+                let mut map = encoder.encode_map(Some(1))?;
+                map.encode_entry(BYTES_MARKER, BASE64.encode(&*bytes))?;
+                map.end()
+            }
+            Value::Tuple(items) => {
+                let mut seq = encoder.encode_seq(Some(items.len()))?;
+                for item in items {
+                    seq.encode_element(Base64Value(item))?;
+                }
+                seq.end()
+            }
+            other => other.into_stream(encoder),
+        }
     }
 }
 
-impl WasmResponse for TCRef {
+/// A `String` is always valid UTF-8, so encoding it never needs to recover from bad input.
+/// Use [`LossyString`] when the text originates from raw host bytes.
+impl WasmResponse for String {
     fn encode(self) -> TCResult<Vec<u8>> {
         encode_json_bytes(self)
     }
 }
 
-pub fn manifest_bytes<L: Library>(library: &L, routes: &[RouteExport]) -> Vec<u8> {
-    let payload = ManifestPayload {
-        schema: library.schema().clone(),
-        routes: routes.to_vec(),
-    };
+/// A text response built from raw bytes which may not be valid UTF-8.
+///
+/// Invalid sequences are replaced with `U+FFFD` (as in [`String::from_utf8_lossy`])
+/// instead of failing the call.
+pub struct LossyString(Vec<u8>);
 
-    encode_json_bytes(payload).expect("manifest json")
+impl LossyString {
+    pub fn new(bytes: impl Into<Vec<u8>>) -> Self {
+        Self(bytes.into())
+    }
 }
 
-pub fn alloc(len: i32) -> i32 {
-    if len <= 0 {
-        return 0;
+impl From<Vec<u8>> for LossyString {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
     }
-
-    let mut buffer = vec![0_u8; len as usize];
-    let ptr = buffer.as_mut_ptr() as i32;
-    mem::forget(buffer);
-    ptr
 }
 
-pub fn free(ptr: i32, len: i32) {
-    if ptr == 0 || len <= 0 {
-        return;
+impl From<&[u8]> for LossyString {
+    fn from(bytes: &[u8]) -> Self {
+        Self(bytes.to_vec())
     }
+}
 
-    unsafe {
-        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
-            ptr as *mut u8,
-            len as usize,
-        )));
+impl WasmResponse for LossyString {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        encode_json_bytes(String::from_utf8_lossy(&self.0).into_owned())
     }
 }
 
-fn pack_wasm_pair(ptr: i32, len: i32) -> i64 {
-    let ptr = ptr as u32 as u64;
-    let len = len as u32 as u64;
-    ((len << 32) | ptr) as i64
+impl WasmResponse for Value {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        encode_json_bytes(Base64Value(self))
+    }
 }
 
-pub fn leak_bytes(bytes: Vec<u8>) -> i64 {
-    if bytes.is_empty() {
-        return 0;
+/// Numbers use tc-value's own encoding, so a number body decodes just as it would within a
+/// [`Value`], and a `Number` response is encoded as tc-value encodes it.
+impl WasmRequest for Number {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        match fast_int(bytes) {
+            Some(FastInt::UInt(n)) => Ok(Number::UInt(UInt::U64(n))),
+            Some(FastInt::Int(n)) => Ok(Number::Int(Int::I64(n))),
+            None => decode_number(bytes),
+        }
     }
-
-    let boxed = bytes.into_boxed_slice();
-    let len = boxed.len() as i32;
-    let ptr = Box::into_raw(boxed) as *mut u8 as i32;
-    pack_wasm_pair(ptr, len)
 }
 
-struct ManifestPayload {
-    schema: LibrarySchema,
-    routes: Vec<RouteExport>,
+fn decode_number(bytes: &[u8]) -> TCResult<Number> {
+    match try_decode_json_slice((), bytes).map_err(TCError::bad_request)? {
+        Value::Number(number) => Ok(number),
+        other => Err(TCError::bad_request(format!(
+            "expected a number but found {other:?}"
+        ))),
+    }
 }
 
-impl<'en> en::IntoStream<'en> for ManifestPayload {
-    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
-        let mut map = encoder.encode_map(Some(2))?;
-        map.encode_entry("schema", self.schema)?;
-        map.encode_entry(
-            "routes",
-            ManifestRoutes {
-                routes: self.routes,
-            },
-        )?;
-        map.end()
+impl WasmRequest for u64 {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        match fast_int(bytes) {
+            Some(FastInt::UInt(n)) => Ok(n),
+            _ => decode_serde_json(bytes),
+        }
     }
 }
 
-struct ManifestRoutes {
-    routes: Vec<RouteExport>,
+impl WasmRequest for i64 {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        match fast_int(bytes) {
+            Some(FastInt::Int(n)) => Ok(n),
+            Some(FastInt::UInt(n)) if n <= i64::MAX as u64 => Ok(n as i64),
+            _ => decode_serde_json(bytes),
+        }
+    }
 }
 
-impl<'en> en::IntoStream<'en> for ManifestRoutes {
-    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
-        let mut seq = encoder.encode_seq(Some(self.routes.len()))?;
-        for route in self.routes {
-            seq.encode_element(route)?;
+impl WasmRequest for bool {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        match bytes.trim_ascii() {
+            b"true" => Ok(true),
+            b"false" => Ok(false),
+            _ => decode_serde_json(bytes),
         }
-        seq.end()
     }
 }
 
-struct ErrorPayload {
-    message: String,
+/// Requests at most this long are parsed without the JSON decoder when they hold a plain
+/// integer (or, for `bool`, a literal `true` or `false`).
+pub const SCALAR_FAST_PATH_MAX_LEN: usize = 32;
+
+enum FastInt {
+    UInt(u64),
+    Int(i64),
 }
 
-impl<'en> en::IntoStream<'en> for ErrorPayload {
-    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
-        let mut map = encoder.encode_map(Some(1))?;
-        map.encode_entry("error", self.message)?;
-        map.end()
+/// Parse a short, well-formed JSON integer directly. Anything else, including a leading zero
+/// or `-0` (which JSON decoders treat as a float), is left to the JSON decoder.
+fn fast_int(bytes: &[u8]) -> Option<FastInt> {
+    if bytes.len() > SCALAR_FAST_PATH_MAX_LEN {
+        return None;
+    }
+
+    let text = std::str::from_utf8(bytes.trim_ascii()).ok()?;
+    let digits = text.strip_prefix('-').unwrap_or(text);
+
+    let well_formed = !digits.is_empty()
+        && digits.bytes().all(|byte| byte.is_ascii_digit())
+        && (digits == "0" || !digits.starts_with('0'));
+
+    if !well_formed {
+        None
+    } else if text.starts_with('-') {
+        text.parse().ok().filter(|n| *n != 0).map(FastInt::Int)
+    } else {
+        text.parse().ok().map(FastInt::UInt)
     }
 }
 
-fn encode_json_bytes<T>(value: T) -> TCResult<Vec<u8>>
+impl WasmResponse for Number {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        encode_json_bytes(Value::Number(self))
+    }
+}
+
+impl WasmResponse for () {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        encode_json_bytes(())
+    }
+}
+
+impl WasmResponse for OpRef {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        encode_json_bytes(self)
+    }
+}
+
+impl WasmResponse for TCRef {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        encode_json_bytes(self)
+    }
+}
+
+/// Encode the manifest returned by a library's `tc_library_entry` export.
+/// Every route is checked with [`RouteExport::validate`] and [`route_init_order`] first.
+pub fn manifest_bytes<L: Library>(library: &L, routes: &[RouteExport]) -> TCResult<Vec<u8>> {
+    validate_routes(routes)?;
+
+    let payload = ManifestPayload {
+        schema: library.schema().clone(),
+        routes: routes.to_vec(),
+        read_only: is_read_only(),
+    };
+
+    encode_json_bytes(payload)
+}
+
+/// Like [`manifest_bytes`], but returns a stream handle (see [`crate::stream`]) so that a
+/// library with a very large number of routes never buffers the whole manifest.
+///
+/// The chunks concatenate to the same JSON document as [`manifest_bytes`]: the first is the
+/// prefix `{"schema": ..., "routes": [`, each of the next holds exactly one route (preceded by
+/// a `,` after the first), and the last is the suffix `]}` (or `],"read_only":true}`).
+pub fn manifest_stream<L: Library>(library: &L, routes: &'static [RouteExport]) -> TCResult<i32> {
+    validate_routes(routes)?;
+
+    let mut prefix = br#"{"schema":"#.to_vec();
+    prefix.extend(encode_json_bytes(library.schema().clone())?);
+    prefix.extend_from_slice(br#","routes":["#);
+
+    let routes = routes.iter().enumerate().map(|(i, route)| {
+        let mut chunk = if i == 0 { Vec::new() } else { b",".to_vec() };
+        chunk.extend(encode_json_bytes(*route)?);
+        Ok(chunk)
+    });
+
+    let chunks = std::iter::once(Ok(prefix))
+        .chain(routes)
+        .chain(std::iter::once(Ok(if is_read_only() {
+            br#"],"read_only":true}"#.to_vec()
+        } else {
+            b"]}".to_vec()
+        })));
+
+    Ok(crate::stream::open_stream(chunks))
+}
+
+struct OversizedManifest {
+    size: usize,
+    max: usize,
+    stream_export: String,
+}
+
+impl<'en> en::IntoStream<'en> for OversizedManifest {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let message = format!(
+            "manifest of {} bytes exceeds the maximum of {} bytes",
+            self.size, self.max
+        );
+
+        let mut map = encoder.encode_map(Some(3))?;
+        map.encode_entry("error", message)?;
+        map.encode_entry("manifest_bytes", self.size)?;
+        map.encode_entry("stream_export", self.stream_export)?;
+        map.end()
+    }
+}
+
+/// Like [`manifest_bytes`], but if the manifest is larger than the maximum set via
+/// [`set_max_manifest_bytes`], return the error manifest
+/// `{"error", "manifest_bytes", "stream_export": <stream_export>}` instead, so the host can
+/// fetch the manifest in chunks from the library's [`manifest_stream`] export (conventionally
+/// [`MANIFEST_STREAM_EXPORT`]). A library which doesn't export one passes `None`, and an
+/// oversized manifest is then a plain error.
+pub fn library_entry_bytes<L: Library>(
+    library: &L,
+    routes: &[RouteExport],
+    stream_export: Option<&str>,
+) -> TCResult<Vec<u8>> {
+    let manifest = manifest_bytes(library, routes)?;
+    let max = MAX_MANIFEST_BYTES.with(Cell::get);
+
+    if manifest.len() <= max {
+        return Ok(manifest);
+    }
+
+    let size = manifest.len();
+    match stream_export {
+        Some(stream_export) => encode_json_bytes(OversizedManifest {
+            size,
+            max,
+            stream_export: stream_export.to_string(),
+        }),
+        None => Err(TCError::internal(format!(
+            "manifest of {size} bytes exceeds the maximum of {max} bytes"
+        ))),
+    }
+}
+
+/// Implements the `tc_library_entry` export (see [`library_entry_bytes`]).
+pub fn library_entry<L: Library>(
+    library: &L,
+    routes: &[RouteExport],
+    stream_export: Option<&str>,
+) -> i64 {
+    leak_bytes(library_entry_bytes(library, routes, stream_export).unwrap_or_else(encode_error))
+}
+
+pub fn alloc(len: i32) -> i32 {
+    if len <= 0 {
+        return 0;
+    }
+
+    let ptr = memory::into_ptr(vec![0_u8; len as usize].into_boxed_slice());
+    track_leaked(len as usize);
+    ptr
+}
+
+pub fn free(ptr: i32, len: i32) {
+    if ptr == 0 || len <= 0 {
+        return;
+    }
+
+    drop(unsafe { memory::take(ptr, len as usize) });
+    track_freed(len as usize);
+}
+
+/// Pack a buffer's pointer and length into the `i64` returned by every export: the pointer
+/// occupies the low 32 bits and the length the high 32 bits, both zero-extended. The TinyChain
+/// host unpacks the pair the same way, so this layout is part of the ABI.
+pub fn pack_ptr_len(ptr: i32, len: i32) -> i64 {
+    let ptr = ptr as u32 as u64;
+    let len = len as u32 as u64;
+    ((len << 32) | ptr) as i64
+}
+
+/// The inverse of [`pack_ptr_len`].
+pub fn unpack_ptr_len(packed: i64) -> (i32, i32) {
+    let packed = packed as u64;
+    (packed as u32 as i32, (packed >> 32) as u32 as i32)
+}
+
+pub fn leak_bytes(bytes: Vec<u8>) -> i64 {
+    if bytes.is_empty() {
+        return 0;
+    }
+
+    let len = bytes.len() as i32;
+    let ptr = memory::into_ptr(bytes.into_boxed_slice());
+    track_leaked(len as usize);
+    pack_ptr_len(ptr, len)
+}
+
+/// The total size of the buffers handed to the host by [`alloc`] or [`leak_bytes`] which
+/// have not yet been released via [`free`]. Only tracked in debug builds or with the
+/// `leak-tracking` or `testing` feature enabled; otherwise always zero.
+pub fn outstanding_bytes() -> usize {
+    OUTSTANDING_BYTES.with(Cell::get)
+}
+
+fn track_leaked(len: usize) {
+    if TRACK_LEAKS {
+        OUTSTANDING_BYTES.with(|outstanding| outstanding.set(outstanding.get() + len));
+    }
+}
+
+pub(crate) fn track_freed(len: usize) {
+    if TRACK_LEAKS {
+        OUTSTANDING_BYTES
+            .with(|outstanding| outstanding.set(outstanding.get().saturating_sub(len)));
+    }
+}
+
+struct ManifestPayload {
+    schema: LibrarySchema,
+    routes: Vec<RouteExport>,
+    read_only: bool,
+}
+
+impl<'en> en::IntoStream<'en> for ManifestPayload {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(if self.read_only { 3 } else { 2 }))?;
+        map.encode_entry("schema", self.schema)?;
+        map.encode_entry(
+            "routes",
+            ManifestRoutes {
+                routes: self.routes,
+            },
+        )?;
+
+        if self.read_only {
+            map.encode_entry("read_only", true)?;
+        }
+
+        map.end()
+    }
+}
+
+struct ManifestRoutes {
+    routes: Vec<RouteExport>,
+}
+
+impl<'en> en::IntoStream<'en> for ManifestRoutes {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut seq = encoder.encode_seq(Some(self.routes.len()))?;
+        for route in self.routes {
+            seq.encode_element(route)?;
+        }
+        seq.end()
+    }
+}
+
+struct ErrorPayload {
+    message: String,
+    retryable: bool,
+    fields: Option<Vec<FieldError>>,
+}
+
+impl<'en> en::IntoStream<'en> for ErrorPayload {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(2 + usize::from(self.fields.is_some())))?;
+        map.encode_entry("error", self.message)?;
+        map.encode_entry("retryable", self.retryable)?;
+        if let Some(fields) = self.fields {
+            map.encode_entry("fields", fields)?;
+        }
+        map.end()
+    }
+}
+
+struct ProblemDetails {
+    status: u16,
+    title: &'static str,
+    detail: String,
+    retryable: bool,
+    fields: Option<Vec<FieldError>>,
+}
+
+impl<'en> en::IntoStream<'en> for ProblemDetails {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(5 + usize::from(self.fields.is_some())))?;
+        map.encode_entry("type", "about:blank")?;
+        map.encode_entry("title", self.title)?;
+        map.encode_entry("status", self.status)?;
+        map.encode_entry("detail", self.detail)?;
+        map.encode_entry("retryable", self.retryable)?;
+        if let Some(fields) = self.fields {
+            map.encode_entry("fields", fields)?;
+        }
+        map.end()
+    }
+}
+
+/// Whether a call which failed with `err` may succeed if retried unchanged, i.e. whether the
+/// error is transient (a timeout or an unavailable dependency) rather than permanent.
+pub fn is_retryable(err: &TCError) -> bool {
+    matches!(err.code(), ErrorKind::Timeout | ErrorKind::Unavailable)
+}
+
+/// The HTTP status and reason phrase for an error of the given kind.
+fn problem_status(kind: &ErrorKind) -> (u16, &'static str) {
+    match kind {
+        ErrorKind::BadRequest => (400, "Bad Request"),
+        ErrorKind::Unauthorized => (401, "Unauthorized"),
+        ErrorKind::Forbidden => (403, "Forbidden"),
+        ErrorKind::NotFound => (404, "Not Found"),
+        ErrorKind::MethodNotAllowed => (405, "Method Not Allowed"),
+        ErrorKind::Timeout => (408, "Request Timeout"),
+        ErrorKind::Conflict => (409, "Conflict"),
+        ErrorKind::NotImplemented => (501, "Not Implemented"),
+        ErrorKind::BadGateway => (502, "Bad Gateway"),
+        ErrorKind::Unavailable => (503, "Service Unavailable"),
+        _ => (500, "Internal Server Error"),
+    }
+}
+
+pub(crate) fn encode_json_bytes<T>(value: T) -> TCResult<Vec<u8>>
 where
     T: for<'en> en::IntoStream<'en>,
 {
@@ -205,53 +1214,522 @@ where
     .map_err(|err| TCError::bad_request(err.to_string()))
 }
 
-fn decode_json_bytes<T>(context: T::Context, bytes: Vec<u8>) -> TCResult<T>
+/// Encode `value` as canonical JSON: no insignificant whitespace and every object's keys in
+/// lexicographic order, so that equal values always produce identical bytes (e.g. to hash).
+pub fn canonical_json_bytes<T>(value: T) -> TCResult<Vec<u8>>
 where
-    T: de::FromStream,
+    T: for<'en> en::IntoStream<'en>,
 {
-    let stream = stream::iter(vec![Ok::<Bytes, io::Error>(Bytes::from(bytes))]);
-    block_on(destream_json::try_decode(context, stream))
-        .map_err(|err| TCError::bad_request(err.to_string()))
+    canonical_json(&encode_json_bytes(value)?)
+}
+
+/// Re-encode already-encoded JSON canonically, as in [`canonical_json_bytes`].
+pub fn canonical_json(json: &[u8]) -> TCResult<Vec<u8>> {
+    let json: serde_json::Value = serde_json::from_slice(json).map_err(TCError::internal)?;
+    serde_json::to_vec(&canonicalize(json)).map_err(TCError::internal)
+}
+
+pub(crate) fn canonicalize(json: serde_json::Value) -> serde_json::Value {
+    match json {
+        serde_json::Value::Array(items) => items.into_iter().map(canonicalize).collect(),
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(l, _), (r, _)| l.cmp(r));
+
+            entries
+                .into_iter()
+                .map(|(key, value)| (key, canonicalize(value)))
+                .collect()
+        }
+        other => other,
+    }
 }
 
+/// Decode `bytes`, e.g. a body borrowed from the host via [`borrow_bytes`]. The decoder reads
+/// an owned stream, so this takes the one copy of the body on its way to the decoded value.
 fn try_decode_json_slice<T>(context: T::Context, bytes: &[u8]) -> Result<T, String>
 where
     T: de::FromStream,
 {
+    check_string_lengths(bytes).map_err(|err| err.to_string())?;
+    check_duplicate_keys(bytes).map_err(|err| err.to_string())?;
+
     let stream = stream::iter(vec![Ok::<Bytes, io::Error>(Bytes::copy_from_slice(bytes))]);
     block_on(destream_json::try_decode(context, stream)).map_err(|err| err.to_string())
 }
 
-fn read_bytes(ptr: i32, len: i32) -> Vec<u8> {
+pub(crate) fn decode_serde_json<T>(bytes: &[u8]) -> TCResult<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    check_string_lengths(bytes)?;
+
+    if DUPLICATE_KEYS.with(Cell::get) == DuplicateKeys::LastWins || !bytes.contains(&b'{') {
+        return serde_json::from_slice(bytes).map_err(TCError::bad_request);
+    }
+
+    let UniqueKeys(json) = serde_json::from_slice(bytes).map_err(TCError::bad_request)?;
+    serde_json::from_value(json).map_err(TCError::bad_request)
+}
+
+/// Under [`DuplicateKeys::Reject`], fail if any object in `bytes` repeats a key. This is for
+/// input decoded via `destream`, whose map visitors belong to the decoded type, so it scans
+/// the raw JSON (like [`check_string_lengths`]) instead of parsing it. Malformed JSON is left
+/// for the decoder to report.
+fn check_duplicate_keys(bytes: &[u8]) -> TCResult<()> {
+    if DUPLICATE_KEYS.with(Cell::get) == DuplicateKeys::LastWins || !bytes.contains(&b'{') {
+        return Ok(());
+    }
+
+    // the keys seen so far in each open object, or `None` for an open array
+    let mut open: Vec<Option<BTreeSet<String>>> = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => open.push(Some(BTreeSet::new())),
+            b'[' => open.push(None),
+            b'}' | b']' => {
+                open.pop();
+            }
+            b'"' => {
+                let Some(end) = string_end(bytes, i + 1) else {
+                    return Ok(());
+                };
+
+                // a string is a key if the next token is a colon
+                let is_key = bytes[end + 1..]
+                    .iter()
+                    .find(|byte| !byte.is_ascii_whitespace())
+                    == Some(&b':');
+
+                if let Some(Some(keys)) = open.last_mut().filter(|_| is_key) {
+                    let Ok(key) = serde_json::from_slice::<String>(&bytes[i..=end]) else {
+                        return Ok(());
+                    };
+
+                    if keys.contains(&key) {
+                        return Err(TCError::bad_request(format!("duplicate key: {key}")));
+                    }
+
+                    keys.insert(key);
+                }
+
+                i = end;
+            }
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    Ok(())
+}
+
+/// The index of the quote which closes the JSON string whose contents begin at `start`.
+fn string_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut escaped = false;
+    for (i, &byte) in bytes.iter().enumerate().skip(start) {
+        if escaped {
+            escaped = false;
+        } else if byte == b'\\' {
+            escaped = true;
+        } else if byte == b'"' {
+            return Some(i);
+        }
+    }
+
+    None
+}
+
+/// Decodes any JSON document, like `serde_json::Value`, but fails on the first object which
+/// repeats a key, so the check costs no more than the decode itself.
+struct UniqueKeys(serde_json::Value);
+
+impl<'de> serde::Deserialize<'de> for UniqueKeys {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(UniqueKeysVisitor)
+    }
+}
+
+struct UniqueKeysVisitor;
+
+impl<'de> serde::de::Visitor<'de> for UniqueKeysVisitor {
+    type Value = UniqueKeys;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<UniqueKeys, E> {
+        Ok(UniqueKeys(value.into()))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<UniqueKeys, E> {
+        Ok(UniqueKeys(value.into()))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<UniqueKeys, E> {
+        Ok(UniqueKeys(value.into()))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<UniqueKeys, E> {
+        Ok(UniqueKeys(value.into()))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<UniqueKeys, E> {
+        Ok(UniqueKeys(value.into()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<UniqueKeys, E> {
+        Ok(UniqueKeys(value.into()))
+    }
+
+    fn visit_unit<E>(self) -> Result<UniqueKeys, E> {
+        Ok(UniqueKeys(serde_json::Value::Null))
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<UniqueKeys, A::Error> {
+        let mut items = Vec::new();
+        while let Some(UniqueKeys(item)) = seq.next_element()? {
+            items.push(item);
+        }
+
+        Ok(UniqueKeys(serde_json::Value::Array(items)))
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<UniqueKeys, A::Error> {
+        let mut entries = serde_json::Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if entries.contains_key(&key) {
+                return Err(serde::de::Error::custom(format!("duplicate key: {key}")));
+            }
+
+            let UniqueKeys(value) = map.next_value()?;
+            entries.insert(key, value);
+        }
+
+        Ok(UniqueKeys(serde_json::Value::Object(entries)))
+    }
+}
+
+/// Scan raw JSON for a string longer than the configured maximum, without allocating,
+/// so an oversized string is rejected before the decoder buffers it.
+fn check_string_lengths(bytes: &[u8]) -> TCResult<()> {
+    let max = MAX_STRING_LEN.with(Cell::get);
+    if bytes.len() <= max {
+        return Ok(());
+    }
+
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut len = 0;
+
+    for &byte in bytes {
+        if !in_string {
+            if byte == b'"' {
+                in_string = true;
+                len = 0;
+            }
+
+            continue;
+        }
+
+        if escaped {
+            escaped = false;
+        } else if byte == b'\\' {
+            escaped = true;
+        } else if byte == b'"' {
+            in_string = false;
+            continue;
+        }
+
+        len += 1;
+        if len > max {
+            return Err(string_too_long(max));
+        }
+    }
+
+    Ok(())
+}
+
+fn string_too_long(max: usize) -> TCError {
+    TCError::bad_request(format!(
+        "request string exceeds the maximum length of {max} bytes"
+    ))
+}
+
+/// Borrow `len` bytes of linear memory at `ptr` without copying them.
+///
+/// # Safety
+/// The host must keep the buffer alive and unmodified for the duration of the call,
+/// and the returned slice must not outlive the call.
+pub(crate) unsafe fn borrow_bytes<'a>(ptr: i32, len: i32) -> &'a [u8] {
     if ptr == 0 || len <= 0 {
-        return Vec::new();
+        return &[];
     }
 
-    unsafe { slice::from_raw_parts(ptr as *const u8, len as usize).to_vec() }
+    unsafe { memory::borrow(ptr, len as usize) }
 }
 
-fn decode_header_bytes(bytes: &[u8]) -> TCResult<TxnHeader> {
+/// Remove the string field `key` (if any) from a header, so the rest of the header decodes as
+/// a `TxnHeader`. A `null` field is treated as absent.
+pub(crate) fn take_header_field<'a>(
+    header_bytes: &'a [u8],
+    key: &str,
+) -> TCResult<(Option<String>, Cow<'a, [u8]>)> {
+    if !contains(header_bytes, format!("\"{key}\"").as_bytes()) {
+        return Ok((None, Cow::Borrowed(header_bytes)));
+    }
+
+    let mut header: serde_json::Value = decode_serde_json(header_bytes)?;
+    let Some(field) = header.as_object_mut().and_then(|header| header.remove(key)) else {
+        return Ok((None, Cow::Borrowed(header_bytes)));
+    };
+
+    let field = match field {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(field) => Some(field),
+        _ => return Err(TCError::bad_request(format!("{key} must be a string"))),
+    };
+
+    let header_bytes = serde_json::to_vec(&header).map_err(TCError::bad_request)?;
+    Ok((field, Cow::Owned(header_bytes)))
+}
+
+pub(crate) fn decode_header_bytes(bytes: &[u8]) -> TCResult<TxnHeader> {
+    clear_field_errors();
+
     if bytes.is_empty() {
         return Err(TCError::bad_request("missing transaction header"));
     }
 
-    decode_json_bytes((), bytes.to_vec())
+    #[cfg(any(feature = "tracing", feature = "testing", test))]
+    host::begin_call_trace();
+
+    #[cfg(any(feature = "alloc-limit", test))]
+    crate::alloc_limit::begin_call_allocations();
+
+    let (bytes, codecs) = strip_codecs(bytes)?;
+    begin_call_codecs(codecs);
+
+    begin_call_timing();
+
+    let header = try_decode_json_slice((), &bytes).map_err(TCError::bad_request)?;
+    check_request_age(&header)?;
+    check_claim_scope(&header)?;
+    begin_call_txn_id(&header)?;
+    Ok(header)
 }
 
-fn encode_error(err: TCError) -> Vec<u8> {
-    encode_json_bytes(ErrorPayload {
-        message: err.to_string(),
+fn check_claim_scope(header: &TxnHeader) -> TCResult<()> {
+    CLAIM_SCOPE.with(|scope| {
+        let Some(scope) = &*scope.borrow() else {
+            return Ok(());
+        };
+
+        let claimed = header.claim().link().to_string();
+        let scope = scope.to_string();
+
+        if is_path_prefix(&claimed, &scope) || is_path_prefix(&scope, &claimed) {
+            Ok(())
+        } else {
+            Err(TCError::forbidden(format!(
+                "a claim on {claimed} does not apply to {scope}"
+            )))
+        }
     })
-    .unwrap_or_else(|_| br#"{"error":"internal"}"#.to_vec())
+}
+
+/// Whether `path` is `prefix` itself or a descendant of it.
+fn is_path_prefix(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn check_request_age(header: &TxnHeader) -> TCResult<()> {
+    let Some((max_age, now)) = MAX_REQUEST_AGE.with(Cell::get) else {
+        return Ok(());
+    };
+
+    let now = now();
+    let age = Duration::from_nanos(now.saturating_sub(header.timestamp().as_nanos()));
+    if age > max_age {
+        Err(TCError::unauthorized("request expired"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Like [`leak_bytes`], but fails instead of leaking a buffer larger than the configured
+/// maximum response size.
+pub fn try_leak_bytes(bytes: Vec<u8>) -> TCResult<i64> {
+    check_response_size(bytes).map(leak_bytes)
+}
+
+pub(crate) fn respond(result: TCResult<Vec<u8>>) -> i64 {
+    leak_bytes(response_bytes(result))
+}
+
+pub(crate) fn response_bytes(result: TCResult<Vec<u8>>) -> Vec<u8> {
+    #[cfg(any(feature = "alloc-limit", test))]
+    let result = crate::alloc_limit::check_call_allocations().and(result);
+
+    let payload = match frame_response(result)
+        .and_then(encode_response)
+        .and_then(check_response_size)
+    {
+        Ok(bytes) => bytes,
+        Err(err) => encode_error(err),
+    };
+
+    match with_integrity_hash(payload) {
+        Ok(bytes) => bytes,
+        // frame the error too, since the host expects a frame; an error payload always fits
+        Err(err) => with_integrity_hash(encode_error(err)).expect("framed error payload"),
+    }
+}
+
+/// Wrap a successful response in the frames the call asked for: a `timed` frame (see
+/// [`crate::set_server_timing`]) within a `correlated` one (see
+/// [`crate::set_txn_id_envelope`]). Every [`WasmResponse`] encodes JSON, so the frames can
+/// enclose any response which the call's response codec leaves as JSON.
+fn frame_response(result: TCResult<Vec<u8>>) -> TCResult<Vec<u8>> {
+    let handler_ns = take_handler_ns();
+    let txn_id = take_call_txn_id();
+
+    match result {
+        Ok(body) if response_codec() == Codec::Json => {
+            Ok(with_txn_id(txn_id, with_server_timing(handler_ns, body)))
+        }
+        other => other,
+    }
+}
+
+fn check_response_size(bytes: Vec<u8>) -> TCResult<Vec<u8>> {
+    let max = MAX_RESPONSE_BYTES.with(Cell::get);
+    if bytes.len() > max {
+        Err(TCError::internal(format!(
+            "response of {} bytes exceeds the maximum of {max} bytes",
+            bytes.len()
+        )))
+    } else {
+        Ok(bytes)
+    }
+}
+
+pub(crate) fn encode_error(err: TCError) -> Vec<u8> {
+    let code = err.code().to_string();
+    let retryable = is_retryable(&err);
+    let redact = REDACT_ERRORS.with(Cell::get);
+    // take the field errors even when redacting, so they can't attach to a later error
+    let fields = take_field_errors(&err).filter(|_| !redact);
+    let message = if redact {
+        // only import `tc_log` into libraries which ask for redacted errors to be logged
+        #[cfg(any(feature = "log-redacted-errors", test))]
+        host::log(host::LogLevel::Error, &err.to_string());
+
+        code.clone()
+    } else {
+        err.to_string()
+    };
+
+    let encoded = if PROBLEM_JSON.with(Cell::get) {
+        let (status, title) = problem_status(&err.code());
+        encode_json_bytes(ProblemDetails {
+            status,
+            title,
+            detail: message,
+            retryable,
+            fields,
+        })
+    } else {
+        encode_json_bytes(ErrorPayload {
+            message,
+            retryable,
+            fields,
+        })
+    };
+
+    encoded.unwrap_or_else(|_| br#"{"error":"internal","retryable":false}"#.to_vec())
+}
+
+/// Statically assert that each export matches the dispatch ABI:
+/// `extern "C" fn(header_ptr: i32, header_len: i32, body_ptr: i32, body_len: i32) -> i64`.
+///
+/// ```
+/// pub extern "C" fn hello(header_ptr: i32, header_len: i32, body_ptr: i32, body_len: i32) -> i64 {
+///     0
+/// }
+///
+/// tc_wasm::assert_export_signature!(hello);
+/// ```
+///
+/// A mismatched parameter type is a compile error:
+///
+/// ```compile_fail
+/// pub extern "C" fn hello(header_ptr: i64, header_len: i32, body_ptr: i32, body_len: i32) -> i64 {
+///     0
+/// }
+///
+/// tc_wasm::assert_export_signature!(hello);
+/// ```
+#[macro_export]
+macro_rules! assert_export_signature {
+    ($($export:path),+ $(,)?) => {
+        $(const _: extern "C" fn(i32, i32, i32, i32) -> i64 = $export;)+
+    };
+}
+
+/// Declare a library's [`RouteExport`]s by naming the export functions themselves, so that
+/// a route whose export is missing (or doesn't match the dispatch ABI) is a build error rather
+/// than a symbol the host fails to find at load time. The functions must still be
+/// `#[unsafe(no_mangle)]`. Each route may be followed by [`RouteExport`] builder calls.
+///
+/// ```
+/// use tc_wasm::{Method, RouteExport};
+///
+/// pub extern "C" fn hello(header_ptr: i32, header_len: i32, body_ptr: i32, body_len: i32) -> i64 {
+///     0
+/// }
+///
+/// const ROUTES: &[RouteExport] = tc_wasm::route_exports![
+///     "/hello" => hello.with_method(Method::Get),
+/// ];
+///
+/// assert_eq!(ROUTES[0].export, "hello");
+/// ```
+///
+/// A route without a matching export is a compile error:
+///
+/// ```compile_fail
+/// use tc_wasm::RouteExport;
+///
+/// const ROUTES: &[RouteExport] = tc_wasm::route_exports!["/hello" => hello];
+/// ```
+#[macro_export]
+macro_rules! route_exports {
+    ($($path:literal => $export:ident $(.$builder:ident($($arg:expr),* $(,)?))*),+ $(,)?) => {{
+        $crate::assert_export_signature!($($export),+);
+        &[$($crate::RouteExport::new($path, stringify!($export))$(.$builder($($arg),*))*),+]
+    }};
 }
 
 macro_rules! define_dispatch {
     (
         $dispatch_fn:ident,
-        $try_dispatch_fn:ident,
+        $dispatch_async_fn:ident,
+        $try_dispatch_async_fn:ident,
         $try_dispatch_bytes_fn:ident,
+        $dispatch_decoded_fn:ident,
+        $dispatch_opt_fn:ident,
+        $try_dispatch_opt_bytes_fn:ident,
+        $dispatch_scoped_fn:ident,
+        $try_dispatch_scoped_bytes_fn:ident,
         $handler_trait:ident,
         $handler_method:ident,
+        $method:ident,
     ) => {
         pub fn $dispatch_fn<H, Txn, Req, Res>(
             handler: &H,
@@ -272,20 +1750,180 @@ macro_rules! define_dispatch {
             Req: WasmRequest,
             Res: WasmResponse,
         {
-            let result = $try_dispatch_fn(handler, header_ptr, header_len, body_ptr, body_len);
-            match result {
-                Ok(bytes) => leak_bytes(bytes),
-                Err(err) => leak_bytes(encode_error(err)),
-            }
+            let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+            let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+            leak_bytes(block_on($dispatch_async_fn(
+                handler,
+                header_bytes,
+                body_bytes,
+            )))
+        }
+
+        /// The plain dispatch function as a future which resolves to the response (or error)
+        /// payload, for a host which drives many library instances from an async runtime
+        /// instead of blocking on each call.
+        pub async fn $dispatch_async_fn<H, Txn, Req, Res>(
+            handler: &H,
+            header_bytes: &[u8],
+            body_bytes: &[u8],
+        ) -> Vec<u8>
+        where
+            Txn: WasmTransaction,
+            H: tc_ir::$handler_trait<
+                    Txn,
+                    Request = Req,
+                    RequestContext = (),
+                    Response = Res,
+                    Error = TCError,
+                >,
+            Req: WasmRequest,
+            Res: WasmResponse,
+        {
+            response_bytes($try_dispatch_async_fn(handler, header_bytes, body_bytes).await)
+        }
+
+        async fn $try_dispatch_async_fn<H, Txn, Req, Res>(
+            handler: &H,
+            header_bytes: &[u8],
+            body_bytes: &[u8],
+        ) -> TCResult<Vec<u8>>
+        where
+            Txn: WasmTransaction,
+            H: tc_ir::$handler_trait<
+                    Txn,
+                    Request = Req,
+                    RequestContext = (),
+                    Response = Res,
+                    Error = TCError,
+                >,
+            Req: WasmRequest,
+            Res: WasmResponse,
+        {
+            check_writable(Method::$method)?;
+            let header = decode_header_bytes(header_bytes)?;
+            let txn = Txn::from_wasm_header(header)?;
+            let request = Req::decode(&request_body(body_bytes)?)?;
+            let timer = ServerTimer::start();
+            let response = handler.$handler_method(&txn, request)?.await?;
+            timer.stop();
+            response.encode()
+        }
+
+        /// Dispatch an already-read header and body, returning the encoded response.
+        pub fn $try_dispatch_bytes_fn<H, Txn, Req, Res>(
+            handler: &H,
+            header_bytes: &[u8],
+            body_bytes: &[u8],
+        ) -> TCResult<Vec<u8>>
+        where
+            Txn: WasmTransaction,
+            H: tc_ir::$handler_trait<
+                    Txn,
+                    Request = Req,
+                    RequestContext = (),
+                    Response = Res,
+                    Error = TCError,
+                >,
+            Req: WasmRequest,
+            Res: WasmResponse,
+        {
+            block_on($try_dispatch_async_fn(handler, header_bytes, body_bytes))
+        }
+
+        /// Run the handler on an already-decoded request, bypassing the byte-level ABI.
+        /// Useful for fuzzing or property-testing handler logic without the JSON codec.
+        pub fn $dispatch_decoded_fn<H, Txn, Req, Res>(
+            handler: &H,
+            txn: &Txn,
+            request: Req,
+        ) -> TCResult<Res>
+        where
+            Txn: Transaction,
+            H: tc_ir::$handler_trait<
+                    Txn,
+                    Request = Req,
+                    RequestContext = (),
+                    Response = Res,
+                    Error = TCError,
+                >,
+        {
+            check_writable(Method::$method)?;
+            let fut = handler.$handler_method(txn, request)?;
+            block_on(fut)
         }
 
-        fn $try_dispatch_fn<H, Txn, Req, Res>(
+        /// Like the plain dispatch function, but tells the request decoder whether the host
+        /// sent a body at all (`body_ptr != 0`), so an absent body can be told apart from
+        /// an empty one.
+        pub fn $dispatch_opt_fn<H, Txn, Req, Res>(
             handler: &H,
             header_ptr: i32,
             header_len: i32,
             body_ptr: i32,
             body_len: i32,
+        ) -> i64
+        where
+            Txn: WasmTransaction,
+            H: tc_ir::$handler_trait<
+                    Txn,
+                    Request = Req,
+                    RequestContext = (),
+                    Response = Res,
+                    Error = TCError,
+                >,
+            Req: WasmRequestOpt,
+            Res: WasmResponse,
+        {
+            let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+            let body_bytes = (body_ptr != 0).then(|| unsafe { borrow_bytes(body_ptr, body_len) });
+            respond($try_dispatch_opt_bytes_fn(
+                handler,
+                header_bytes,
+                body_bytes,
+            ))
+        }
+
+        /// Dispatch an already-read header and optional body, returning the encoded response.
+        pub fn $try_dispatch_opt_bytes_fn<H, Txn, Req, Res>(
+            handler: &H,
+            header_bytes: &[u8],
+            body_bytes: Option<&[u8]>,
         ) -> TCResult<Vec<u8>>
+        where
+            Txn: WasmTransaction,
+            H: tc_ir::$handler_trait<
+                    Txn,
+                    Request = Req,
+                    RequestContext = (),
+                    Response = Res,
+                    Error = TCError,
+                >,
+            Req: WasmRequestOpt,
+            Res: WasmResponse,
+        {
+            let header = decode_header_bytes(header_bytes)?;
+            let txn = Txn::from_wasm_header(header)?;
+            let body = body_bytes.map(request_body).transpose()?;
+            let request = Req::decode_opt(body.as_deref())?;
+            let timer = ServerTimer::start();
+            let response = $dispatch_decoded_fn(handler, &txn, request)?;
+            timer.stop();
+            response.encode()
+        }
+
+        /// Like the plain dispatch function, but rejects a caller whose claim lacks the
+        /// route's [`RouteExport::required_mode`] before the body is decoded, makes the
+        /// route's path available to the handler via [`route_path`], and fails with a
+        /// timeout error if the handler overruns the route's time budget (see
+        /// [`check_deadline`]).
+        pub fn $dispatch_scoped_fn<H, Txn, Req, Res>(
+            route: &RouteExport,
+            handler: &H,
+            header_ptr: i32,
+            header_len: i32,
+            body_ptr: i32,
+            body_len: i32,
+        ) -> i64
         where
             Txn: WasmTransaction,
             H: tc_ir::$handler_trait<
@@ -298,12 +1936,19 @@ macro_rules! define_dispatch {
             Req: WasmRequest,
             Res: WasmResponse,
         {
-            let header_bytes = read_bytes(header_ptr, header_len);
-            let body_bytes = read_bytes(body_ptr, body_len);
-            $try_dispatch_bytes_fn(handler, &header_bytes, &body_bytes)
+            let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+            let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+            respond($try_dispatch_scoped_bytes_fn(
+                route,
+                handler,
+                header_bytes,
+                body_bytes,
+            ))
         }
 
-        fn $try_dispatch_bytes_fn<H, Txn, Req, Res>(
+        /// Dispatch an already-read header and body, checking the route's required mode.
+        pub fn $try_dispatch_scoped_bytes_fn<H, Txn, Req, Res>(
+            route: &RouteExport,
             handler: &H,
             header_bytes: &[u8],
             body_bytes: &[u8],
@@ -321,10 +1966,15 @@ macro_rules! define_dispatch {
             Res: WasmResponse,
         {
             let header = decode_header_bytes(header_bytes)?;
+            route.authorize(header.claim())?;
             let txn = Txn::from_wasm_header(header)?;
-            let request = Req::decode(body_bytes)?;
-            let fut = handler.$handler_method(&txn, request)?;
-            let response = block_on(fut)?;
+            let request = Req::decode(&request_body(body_bytes)?)?;
+            let _route = RouteGuard::enter(route.path);
+            let _deadline = DeadlineGuard::enter(route);
+            let timer = ServerTimer::start();
+            let response = $dispatch_decoded_fn(handler, &txn, request)?;
+            check_deadline()?;
+            timer.stop();
             response.encode()
         }
     };
@@ -332,71 +1982,324 @@ macro_rules! define_dispatch {
 
 define_dispatch!(
     dispatch_get,
-    try_dispatch_get,
+    dispatch_get_async,
+    try_dispatch_get_async,
     try_dispatch_get_bytes,
+    dispatch_get_decoded,
+    dispatch_get_opt,
+    try_dispatch_get_opt_bytes,
+    dispatch_get_scoped,
+    try_dispatch_get_scoped_bytes,
     HandleGet,
     get,
+    Get,
 );
 
 define_dispatch!(
     dispatch_put,
-    try_dispatch_put,
+    dispatch_put_async,
+    try_dispatch_put_async,
     try_dispatch_put_bytes,
+    dispatch_put_decoded,
+    dispatch_put_opt,
+    try_dispatch_put_opt_bytes,
+    dispatch_put_scoped,
+    try_dispatch_put_scoped_bytes,
     HandlePut,
     put,
+    Put,
 );
 
 define_dispatch!(
     dispatch_post,
-    try_dispatch_post,
+    dispatch_post_async,
+    try_dispatch_post_async,
     try_dispatch_post_bytes,
+    dispatch_post_decoded,
+    dispatch_post_opt,
+    try_dispatch_post_opt_bytes,
+    dispatch_post_scoped,
+    try_dispatch_post_scoped_bytes,
     HandlePost,
     post,
+    Post,
 );
 
 define_dispatch!(
     dispatch_delete,
-    try_dispatch_delete,
+    dispatch_delete_async,
+    try_dispatch_delete_async,
     try_dispatch_delete_bytes,
+    dispatch_delete_decoded,
+    dispatch_delete_opt,
+    try_dispatch_delete_opt_bytes,
+    dispatch_delete_scoped,
+    try_dispatch_delete_scoped_bytes,
     HandleDelete,
     delete,
+    Delete,
 );
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// The request method of a call routed through [`dispatch`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(i32)]
+pub enum Method {
+    Get = 0,
+    Put = 1,
+    Post = 2,
+    Delete = 3,
+}
 
-    use futures::Future;
-    use pathlink::Link;
-    use std::{pin::Pin, str::FromStr};
-    use tc_ir::{Claim, NetworkTime, TxnHeader, TxnId};
-    use umask::Mode;
+impl TryFrom<i32> for Method {
+    type Error = TCError;
 
-    #[derive(Clone)]
-    struct FakeTxn {
-        header: TxnHeader,
+    fn try_from(method: i32) -> TCResult<Self> {
+        match method {
+            0 => Ok(Self::Get),
+            1 => Ok(Self::Put),
+            2 => Ok(Self::Post),
+            3 => Ok(Self::Delete),
+            other => Err(TCError::bad_request(format!("unknown method: {other}"))),
+        }
     }
+}
 
-    impl tc_ir::Transaction for FakeTxn {
-        fn id(&self) -> TxnId {
-            self.header.id()
-        }
+impl std::str::FromStr for Method {
+    type Err = TCError;
 
-        fn timestamp(&self) -> NetworkTime {
-            self.header.timestamp()
+    fn from_str(method: &str) -> TCResult<Self> {
+        match method.to_ascii_uppercase().as_str() {
+            "GET" => Ok(Self::Get),
+            "PUT" => Ok(Self::Put),
+            "POST" => Ok(Self::Post),
+            "DELETE" => Ok(Self::Delete),
+            _ => Err(TCError::bad_request(format!("unknown method: {method}"))),
         }
+    }
+}
 
-        fn claim(&self) -> &Claim {
-            self.header.claim()
-        }
+impl std::fmt::Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Get => "GET",
+            Self::Put => "PUT",
+            Self::Post => "POST",
+            Self::Delete => "DELETE",
+        })
     }
+}
 
-    impl WasmTransaction for FakeTxn {
-        fn from_wasm_header(header: TxnHeader) -> TCResult<Self> {
-            Ok(Self { header })
-        }
+/// A handler which serves several methods through a single [`dispatch`] export.
+///
+/// Every method rejects the call by default; override the ones the handler supports by
+/// forwarding to the matching `try_dispatch_*_bytes` function, e.g.:
+///
+/// ```ignore
+/// impl WasmHandler<ExampleTxn> for HelloHandler {
+///     fn get(&self, header: &[u8], body: &[u8]) -> TCResult<Vec<u8>> {
+///         tc_wasm::try_dispatch_get_bytes::<_, ExampleTxn, Value, Value>(self, header, body)
+///     }
+/// }
+/// ```
+pub trait WasmHandler<Txn: WasmTransaction> {
+    fn get(&self, _header_bytes: &[u8], _body_bytes: &[u8]) -> TCResult<Vec<u8>> {
+        Err(TCError::method_not_allowed(Method::Get, "this handler"))
+    }
+
+    fn put(&self, _header_bytes: &[u8], _body_bytes: &[u8]) -> TCResult<Vec<u8>> {
+        Err(TCError::method_not_allowed(Method::Put, "this handler"))
+    }
+
+    fn post(&self, _header_bytes: &[u8], _body_bytes: &[u8]) -> TCResult<Vec<u8>> {
+        Err(TCError::method_not_allowed(Method::Post, "this handler"))
+    }
+
+    fn delete(&self, _header_bytes: &[u8], _body_bytes: &[u8]) -> TCResult<Vec<u8>> {
+        Err(TCError::method_not_allowed(Method::Delete, "this handler"))
+    }
+}
+
+/// Dispatch a call for any [`Method`] to a single export, so a module doesn't need one
+/// export per method. `method` is the `i32` discriminant of [`Method`].
+pub fn dispatch<H, Txn>(
+    method: i32,
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: WasmHandler<Txn>,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(
+        Method::try_from(method)
+            .and_then(|method| try_dispatch_bytes(method, handler, header_bytes, body_bytes)),
+    )
+}
+
+/// Dispatch an already-read header and body to the handler method for `method`.
+pub fn try_dispatch_bytes<H, Txn>(
+    method: Method,
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: WasmHandler<Txn>,
+{
+    check_writable(method)?;
+
+    match method {
+        Method::Get => handler.get(header_bytes, body_bytes),
+        Method::Put => handler.put(header_bytes, body_bytes),
+        Method::Post => handler.post(header_bytes, body_bytes),
+        Method::Delete => handler.delete(header_bytes, body_bytes),
+    }
+}
+
+/// Handles calls to paths for which the library exports no route (see [`dispatch_fallback`]),
+/// e.g. to route dynamically by forwarding to a `try_dispatch_*_bytes` function, or to return
+/// a custom not found error. Rejects every path as not found by default.
+pub trait FallbackHandler {
+    fn handle(&self, path: &str, _header_bytes: &[u8], _body_bytes: &[u8]) -> TCResult<Vec<u8>> {
+        Err(TCError::not_found(path))
+    }
+}
+
+/// Implements the `tc_fallback` export, which the host calls with the attempted path when no
+/// route in the manifest matches it:
+/// `tc_fallback(path_ptr, path_len, header_ptr, header_len, body_ptr, body_len) -> i64`.
+pub fn dispatch_fallback<H: FallbackHandler>(
+    handler: &H,
+    path_ptr: i32,
+    path_len: i32,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64 {
+    let path_bytes = unsafe { borrow_bytes(path_ptr, path_len) };
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_fallback_bytes(
+        handler,
+        path_bytes,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read path, header and body to a [`FallbackHandler`].
+pub fn try_dispatch_fallback_bytes<H: FallbackHandler>(
+    handler: &H,
+    path_bytes: &[u8],
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>> {
+    let path = decode_utf8(path_bytes)?;
+    if !path.starts_with('/') {
+        return Err(TCError::bad_request(format!(
+            "fallback path {path:?} must be absolute"
+        )));
+    }
+
+    handler.handle(path, header_bytes, body_bytes)
+}
+
+/// A bearer token forwarded by the host in the `token` field of the transaction header.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuthToken(String);
+
+impl AuthToken {
+    pub fn as_str(&self) -> &str {
+        &self.0
     }
 
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+/// A request paired with the caller's [`AuthToken`], if the host provided one.
+pub struct Authed<Req> {
+    pub token: Option<AuthToken>,
+    pub request: Req,
+}
+
+/// Like [`dispatch_get`], but also passes the header's auth token to the handler so it can
+/// attach the token to downstream `OpRef`s.
+pub fn dispatch_get_authed<H, Txn, Req, Res>(
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<
+            Txn,
+            Request = Authed<Req>,
+            RequestContext = (),
+            Response = Res,
+            Error = TCError,
+        >,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_authed_bytes(
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+fn try_dispatch_get_authed_bytes<H, Txn, Req, Res>(
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<
+            Txn,
+            Request = Authed<Req>,
+            RequestContext = (),
+            Response = Res,
+            Error = TCError,
+        >,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let (token, header_bytes) = take_header_field(header_bytes, "token")?;
+    let token = token.map(AuthToken);
+    let header = decode_header_bytes(&header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+    let request = Req::decode(&request_body(body_bytes)?)?;
+    dispatch_get_decoded(handler, &txn, Authed { token, request })?.encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use proptest::prelude::*;
+    use std::pin::Pin;
+    use std::str::FromStr;
+    use tc_value::Float;
+
+    use crate::test_support::{
+        FakeTxn, fake_txn, txn_header_bytes, txn_header_bytes_at, txn_header_bytes_for,
+    };
+
     struct VerbHandler;
 
     impl tc_ir::HandlePut<FakeTxn> for VerbHandler {
@@ -420,41 +2323,118 @@ mod tests {
         type Fut<'a> =
             Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
 
-        fn post<'a>(
-            &'a self,
-            _txn: &'a FakeTxn,
-            request: Self::Request,
-        ) -> TCResult<Self::Fut<'a>> {
-            Ok(Box::pin(async move {
-                Ok(Value::String(format!("post:{request:?}")))
-            }))
+        fn post<'a>(
+            &'a self,
+            _txn: &'a FakeTxn,
+            request: Self::Request,
+        ) -> TCResult<Self::Fut<'a>> {
+            Ok(Box::pin(async move {
+                Ok(Value::String(format!("post:{request:?}")))
+            }))
+        }
+    }
+
+    impl tc_ir::HandleDelete<FakeTxn> for VerbHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn delete<'a>(
+            &'a self,
+            _txn: &'a FakeTxn,
+            request: Self::Request,
+        ) -> TCResult<Self::Fut<'a>> {
+            Ok(Box::pin(async move {
+                Ok(Value::String(format!("delete:{request:?}")))
+            }))
+        }
+    }
+
+    struct HelloHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for HelloHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let response = match request {
+                Value::String(name) => Value::String(format!("Hello, {name}!")),
+                _ => Value::String("Hello, World!".to_string()),
+            };
+            Ok(Box::pin(async move { Ok(response) }))
+        }
+    }
+
+    impl tc_ir::HandlePut<FakeTxn> for HelloHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn put<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            Ok(Box::pin(async move {
+                Ok(Value::String(format!("stored {request:?}")))
+            }))
+        }
+    }
+
+    impl WasmHandler<FakeTxn> for HelloHandler {
+        fn get(&self, header_bytes: &[u8], body_bytes: &[u8]) -> TCResult<Vec<u8>> {
+            try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(self, header_bytes, body_bytes)
+        }
+
+        fn put(&self, header_bytes: &[u8], body_bytes: &[u8]) -> TCResult<Vec<u8>> {
+            try_dispatch_put_bytes::<_, FakeTxn, Value, Value>(self, header_bytes, body_bytes)
+        }
+    }
+
+    struct OptHandler;
+
+    impl tc_ir::HandlePut<FakeTxn> for OptHandler {
+        type Request = Option<Value>;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn put<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let response = match request {
+                None => "absent".to_string(),
+                Some(value) => format!("present:{value:?}"),
+            };
+
+            Ok(Box::pin(async move { Ok(Value::String(response)) }))
         }
     }
 
-    impl tc_ir::HandleDelete<FakeTxn> for VerbHandler {
-        type Request = Value;
+    struct AuthedHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for AuthedHandler {
+        type Request = Authed<Value>;
         type RequestContext = ();
         type Response = Value;
         type Error = TCError;
         type Fut<'a> =
             Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
 
-        fn delete<'a>(
-            &'a self,
-            _txn: &'a FakeTxn,
-            request: Self::Request,
-        ) -> TCResult<Self::Fut<'a>> {
-            Ok(Box::pin(async move {
-                Ok(Value::String(format!("delete:{request:?}")))
-            }))
-        }
-    }
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let token = request
+                .token
+                .map(AuthToken::into_string)
+                .unwrap_or_else(|| "anonymous".to_string());
 
-    fn txn_header_bytes() -> Vec<u8> {
-        let claim = Claim::new(Link::from_str("/lib").expect("claim link"), Mode::all());
-        let id = TxnId::from_parts(NetworkTime::from_nanos(1), 7);
-        let header = TxnHeader::new(id, NetworkTime::from_nanos(1), claim);
-        encode_json_bytes(header).expect("header json")
+            Ok(Box::pin(async move { Ok(Value::String(token)) }))
+        }
     }
 
     #[test]
@@ -510,4 +2490,1067 @@ mod tests {
         let response: Value = try_decode_json_slice((), &response_bytes).expect("decode response");
         assert_eq!(response, Value::String(format!("delete:{request:?}")));
     }
+
+    #[test]
+    fn lossy_string_replaces_invalid_utf8() {
+        let bytes = b"caf\xc3\xa9 \xff\xfe ok".to_vec();
+        let response_bytes = LossyString::from(bytes).encode().expect("lossy response");
+
+        let response: String = try_decode_json_slice((), &response_bytes).expect("decode response");
+        assert_eq!(response, "caf\u{e9} \u{fffd}\u{fffd} ok");
+    }
+
+    #[test]
+    fn number_round_trip_preserves_type() {
+        let numbers = [
+            Number::UInt(UInt::U64(7)),
+            Number::Int(Int::I64(-42)),
+            Number::Float(Float::F64(1.5)),
+        ];
+
+        for number in numbers {
+            let bytes = number.encode().expect("number json");
+            let decoded = Number::decode(&bytes).expect("decode number");
+
+            match (number, decoded) {
+                (Number::UInt(UInt::U64(l)), Number::UInt(UInt::U64(r))) => assert_eq!(l, r),
+                (Number::Int(Int::I64(l)), Number::Int(Int::I64(r))) => assert_eq!(l, r),
+                (Number::Float(Float::F64(l)), Number::Float(Float::F64(r))) => assert_eq!(l, r),
+                (expected, actual) => panic!("expected {expected:?} but found {actual:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn dispatch_get_decoded_always_greets() {
+        let txn = fake_txn();
+        let requests = [
+            Value::String(String::new()),
+            Value::String("World".to_string()),
+            Value::String("\u{1F980} with spaces, commas & \"quotes\"".to_string()),
+            Value::String("x".repeat(4096)),
+            Value::from(42u64),
+            Value::None,
+        ];
+
+        for request in requests {
+            let response = dispatch_get_decoded(&HelloHandler, &txn, request).expect("greeting");
+            match response {
+                Value::String(greeting) => assert!(greeting.starts_with("Hello, ")),
+                other => panic!("expected a greeting but found {other:?}"),
+            }
+        }
+    }
+
+    /// Holds an `Rc` across an await point, so its future is `!Send`.
+    struct LocalHandler(std::rc::Rc<String>);
+
+    impl tc_ir::HandleGet<FakeTxn> for LocalHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, _request: Value) -> TCResult<Self::Fut<'a>> {
+            let greeting = self.0.clone();
+            Ok(Box::pin(async move {
+                futures::future::ready(()).await;
+                Ok(Value::from(greeting.as_str()))
+            }))
+        }
+    }
+
+    #[test]
+    fn dispatches_handlers_with_non_send_futures() {
+        let handler = LocalHandler(std::rc::Rc::new("local".to_string()));
+        let response =
+            try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(&handler, &txn_header_bytes(), &[])
+                .expect("local response");
+
+        assert_eq!(
+            Value::decode(&response).expect("response"),
+            Value::from("local")
+        );
+    }
+
+    fn error_message(bytes: &[u8]) -> String {
+        let payload: serde_json::Value = serde_json::from_slice(bytes).expect("error json");
+        payload["error"]
+            .as_str()
+            .expect("error message")
+            .to_string()
+    }
+
+    #[test]
+    fn error_redaction() {
+        let err = || TCError::bad_request("no such file: /srv/secret/config.json");
+
+        let message = error_message(&encode_error(err()));
+        assert_eq!(message, err().to_string());
+
+        set_error_redaction(true);
+        let message = error_message(&encode_error(err()));
+        set_error_redaction(false);
+
+        assert_eq!(message, err().code().to_string());
+        assert!(!message.contains("secret"));
+    }
+
+    #[test]
+    fn rejects_duplicate_keys_by_default() {
+        let err = Value::decode(br#"{"a": 1, "b": 2, "a": 3}"#).expect_err("duplicate");
+        assert!(err.to_string().contains("duplicate key: a"), "{err}");
+
+        let nested = br#"[{"outer": {"k": 1, "k": 2}}]"#;
+        assert!(Value::decode(nested).is_err());
+        assert!(decode_serde_json::<serde_json::Value>(nested).is_err());
+
+        // keys may repeat across different objects
+        assert!(decode_serde_json::<serde_json::Value>(br#"[{"a": 1}, {"a": 2}]"#).is_ok());
+
+        // an escaped key is the same key, but a string value which looks like one isn't
+        let escaped = br#"{"a": 1, "\u0061": 2}"#;
+        let err = Value::decode(escaped).expect_err("escaped duplicate");
+        assert!(err.to_string().contains("duplicate key: a"), "{err}");
+        assert!(decode_serde_json::<serde_json::Value>(escaped).is_err());
+        assert!(check_duplicate_keys(br#"{"a": "\"a\": 1", "b": ["a", "a"]}"#).is_ok());
+    }
+
+    #[test]
+    fn duplicate_keys_last_wins() {
+        set_duplicate_key_policy(DuplicateKeys::LastWins);
+        let json = decode_serde_json::<serde_json::Value>(br#"{"a": 1, "a": 3}"#);
+        set_duplicate_key_policy(DuplicateKeys::Reject);
+
+        assert_eq!(json.expect("last wins")["a"], 3);
+    }
+
+    #[test]
+    fn problem_json_errors() {
+        set_problem_json(true);
+        let not_found = encode_error(TCError::not_found("/lib/missing"));
+        let bad_request = encode_error(TCError::bad_request("no name"));
+        set_problem_json(false);
+
+        let problem: serde_json::Value = serde_json::from_slice(&not_found).expect("problem");
+        assert_eq!(problem["type"], "about:blank");
+        assert_eq!(problem["title"], "Not Found");
+        assert_eq!(problem["status"], 404);
+        assert!(
+            problem["detail"]
+                .as_str()
+                .expect("detail")
+                .contains("/lib/missing")
+        );
+        assert!(problem.get("error").is_none());
+
+        let problem: serde_json::Value = serde_json::from_slice(&bad_request).expect("problem");
+        assert_eq!(problem["status"], 400);
+        assert_eq!(problem["title"], "Bad Request");
+
+        for (err, status) in [
+            (TCError::unauthorized("expired"), 401),
+            (TCError::method_not_allowed(Method::Put, "/lib"), 405),
+            (TCError::timeout("too slow"), 408),
+            (TCError::bad_gateway("upstream"), 502),
+            (TCError::internal("oops"), 500),
+        ] {
+            set_problem_json(true);
+            let problem = encode_error(err);
+            set_problem_json(false);
+
+            let problem: serde_json::Value = serde_json::from_slice(&problem).expect("problem");
+            assert_eq!(problem["status"], status);
+        }
+
+        let compact = error_message(&encode_error(TCError::bad_request("no name")));
+        assert!(compact.contains("no name"));
+    }
+
+    #[test]
+    fn classifies_retryable_errors() {
+        let timeout = encode_error(TCError::timeout("upstream took too long"));
+        let bad_request = encode_error(TCError::bad_request("no name"));
+
+        let timeout: serde_json::Value = serde_json::from_slice(&timeout).expect("timeout");
+        assert_eq!(timeout["retryable"], true);
+
+        let bad_request: serde_json::Value =
+            serde_json::from_slice(&bad_request).expect("bad request");
+        assert_eq!(bad_request["retryable"], false);
+
+        assert!(!is_retryable(&TCError::not_found("/lib/missing")));
+    }
+
+    #[test]
+    fn dispatch_get_authed_forwards_token() {
+        let mut header: serde_json::Value =
+            serde_json::from_slice(&txn_header_bytes()).expect("header json");
+        header
+            .as_object_mut()
+            .expect("header object")
+            .insert("token".to_string(), "bearer-123".into());
+        let header_bytes = serde_json::to_vec(&header).expect("header json");
+
+        let response_bytes = try_dispatch_get_authed_bytes::<_, FakeTxn, Value, Value>(
+            &AuthedHandler,
+            &header_bytes,
+            &[],
+        )
+        .expect("authed response");
+
+        let response: Value = try_decode_json_slice((), &response_bytes).expect("decode response");
+        assert_eq!(response, Value::String("bearer-123".to_string()));
+
+        let response_bytes = try_dispatch_get_authed_bytes::<_, FakeTxn, Value, Value>(
+            &AuthedHandler,
+            &txn_header_bytes(),
+            &[],
+        )
+        .expect("anonymous response");
+
+        let response: Value = try_decode_json_slice((), &response_bytes).expect("decode response");
+        assert_eq!(response, Value::String("anonymous".to_string()));
+    }
+
+    #[test]
+    fn rejects_overlong_strings() {
+        set_max_string_len(16);
+
+        let short = encode_json_bytes("short".to_string()).expect("json");
+        let long = encode_json_bytes("x".repeat(17)).expect("json");
+        let nested = format!(r#"{{"name": "{}"}}"#, "y".repeat(32)).into_bytes();
+
+        let short_result = Value::decode(&short);
+        let long_result = Value::decode(&long);
+        let raw_result = String::decode("z".repeat(17).as_bytes());
+        let nested_result = decode_serde_json::<serde_json::Value>(&nested);
+
+        set_max_string_len(DEFAULT_MAX_STRING_LEN);
+
+        assert_eq!(short_result.expect("short"), Value::from("short"));
+        assert!(long_result.is_err());
+        assert!(raw_result.is_err());
+        assert!(nested_result.is_err());
+    }
+
+    #[test]
+    fn oversized_response_returns_error_payload() {
+        set_max_response_bytes(32);
+
+        let small = response_bytes(Ok(b"\"ok\"".to_vec()));
+        let large = response_bytes(Ok(vec![b'x'; 33]));
+        let leaked = try_leak_bytes(vec![b'x'; 33]);
+
+        set_max_response_bytes(DEFAULT_MAX_RESPONSE_BYTES);
+
+        assert_eq!(small, b"\"ok\"");
+        assert!(error_message(&large).contains("exceeds the maximum"));
+        assert!(leaked.is_err());
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "leak-tracking"))]
+    fn tracks_outstanding_bytes() {
+        assert_eq!(outstanding_bytes(), 0);
+
+        let first = unpack_ptr_len(leak_bytes(vec![1; 8]));
+        let second = unpack_ptr_len(leak_bytes(vec![2; 4]));
+        assert_eq!(outstanding_bytes(), 12);
+
+        free(first.0, first.1);
+        assert_eq!(outstanding_bytes(), 4);
+
+        free(second.0, second.1);
+        assert_eq!(outstanding_bytes(), 0);
+    }
+
+    #[test]
+    fn unified_dispatch_routes_by_method() {
+        let header_bytes = txn_header_bytes();
+        let body_bytes = encode_json_bytes(Value::from("World")).expect("body json");
+
+        let response_bytes = try_dispatch_bytes::<_, FakeTxn>(
+            Method::try_from(0).expect("method"),
+            &HelloHandler,
+            &header_bytes,
+            &body_bytes,
+        )
+        .expect("get response");
+
+        let response: Value = try_decode_json_slice((), &response_bytes).expect("decode response");
+        assert_eq!(response, Value::String("Hello, World!".to_string()));
+
+        let response_bytes = try_dispatch_bytes::<_, FakeTxn>(
+            Method::try_from(1).expect("method"),
+            &HelloHandler,
+            &header_bytes,
+            &body_bytes,
+        )
+        .expect("put response");
+
+        let response: Value = try_decode_json_slice((), &response_bytes).expect("decode response");
+        let expected = format!("stored {:?}", Value::from("World"));
+        assert_eq!(response, Value::String(expected));
+
+        assert!(
+            try_dispatch_bytes::<_, FakeTxn>(
+                Method::Delete,
+                &HelloHandler,
+                &header_bytes,
+                &body_bytes
+            )
+            .is_err()
+        );
+        assert!(Method::try_from(4).is_err());
+    }
+
+    /// Greets `/greet/<name>` dynamically and rejects any other path.
+    struct GreetFallback;
+
+    impl FallbackHandler for GreetFallback {
+        fn handle(&self, path: &str, header_bytes: &[u8], body_bytes: &[u8]) -> TCResult<Vec<u8>> {
+            match path.strip_prefix("/greet/") {
+                Some(name) => {
+                    let body = encode_json_bytes(Value::from(name))?;
+                    try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(
+                        &HelloHandler,
+                        header_bytes,
+                        &body,
+                    )
+                }
+                None => Err(TCError::not_found(format!("no route for {path}"))),
+            }
+        }
+    }
+
+    #[test]
+    fn fallback_handles_unmatched_paths() {
+        let header_bytes = txn_header_bytes();
+        let fallback =
+            |path: &[u8]| try_dispatch_fallback_bytes(&GreetFallback, path, &header_bytes, &[]);
+
+        let greeting = fallback(b"/greet/Ada").expect("dynamic route");
+        assert_eq!(
+            Value::decode(&greeting).expect("greeting"),
+            Value::from("Hello, Ada!")
+        );
+
+        let err = fallback(b"/unknown").expect_err("unknown path");
+        assert!(err.to_string().contains("no route for /unknown"));
+
+        assert!(fallback(b"relative").is_err());
+        assert!(fallback(&[0xff]).is_err());
+    }
+
+    /// Arbitrary JSON-representable values. A `Value` has no map variant, so maps are
+    /// generated as records (tuples of `(name, value)` pairs, as in [`crate::redact`]), which
+    /// nest like any other value. Excluded by design:
+    /// - non-finite floats, since JSON has no representation for NaN or infinity
+    fn arbitrary_value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::None),
+            any::<u64>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            any::<f64>()
+                .prop_filter("finite", |n| n.is_finite())
+                .prop_map(Value::from),
+            ".*".prop_map(Value::String),
+            prop::collection::vec(any::<u8>(), 0..32).prop_map(|bytes| Value::Bytes(bytes.into())),
+        ];
+
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..8)
+                    .prop_map(|items| Value::Tuple(items.into())),
+                prop::collection::btree_map("[a-z_]{1,8}", inner.clone(), 0..8).prop_map(record),
+                ("[a-z_]{1,8}", inner).prop_map(|(name, value)| {
+                    let inner = record([(name.clone(), value)].into());
+                    record([(name, inner)].into())
+                }),
+            ]
+        })
+    }
+
+    fn record(fields: std::collections::BTreeMap<String, Value>) -> Value {
+        let fields = fields
+            .into_iter()
+            .map(|(name, value)| Value::Tuple(vec![Value::String(name), value].into()))
+            .collect::<Vec<_>>();
+
+        Value::Tuple(fields.into())
+    }
+
+    proptest! {
+        #[test]
+        fn json_round_trip(value in arbitrary_value()) {
+            let bytes = value.clone().encode().expect("value json");
+            let decoded = Value::decode(&bytes).expect("decode value");
+            prop_assert_eq!(decoded, value);
+        }
+    }
+
+    #[test]
+    fn rejects_expired_requests() {
+        let body_bytes = encode_json_bytes(Value::from(1u64)).expect("body json");
+        let fresh = txn_header_bytes_at(host::now());
+        let expired = txn_header_bytes();
+
+        set_max_request_age(Some(Duration::from_secs(60)));
+
+        let fresh_result =
+            try_dispatch_put_bytes::<_, FakeTxn, Value, Value>(&VerbHandler, &fresh, &body_bytes);
+        let expired_result =
+            try_dispatch_put_bytes::<_, FakeTxn, Value, Value>(&VerbHandler, &expired, &body_bytes);
+
+        set_max_request_age(None);
+
+        assert!(fresh_result.is_ok());
+        let err = expired_result.expect_err("expired request");
+        assert!(err.to_string().contains("request expired"));
+    }
+
+    #[test]
+    fn enforces_claim_scope() {
+        let claim_on = |link: &str| {
+            let header =
+                txn_header_bytes_for(crate::claim::full(Link::from_str(link).expect("link")));
+            decode_header_bytes(&header)
+        };
+
+        set_claim_scope(Some(
+            Link::from_str("/lib/example-devco/example").expect("scope"),
+        ));
+
+        let matching = claim_on("/lib/example-devco/example");
+        let child = claim_on("/lib/example-devco/example/0.1.0");
+        let parent = claim_on("/lib");
+        let sibling = claim_on("/lib/example-devco/example-other");
+        let unrelated = claim_on("/lib/other-devco/other");
+
+        set_claim_scope(None);
+
+        assert!(matching.is_ok());
+        assert!(child.is_ok());
+        assert!(parent.is_ok());
+        assert!(sibling.is_err());
+        let err = unrelated.expect_err("unrelated claim");
+        assert!(err.to_string().contains("does not apply"));
+
+        assert!(claim_on("/lib/other-devco/other").is_ok());
+    }
+
+    #[test]
+    fn decodes_borrowed_host_slice() {
+        // the host writes the body into the middle of a buffer it obtained from `alloc`
+        let body = encode_json_bytes(Value::from("borrowed")).expect("body json");
+        let len = body.len() as i32 + 16;
+        let ptr = alloc(len);
+        memory::write(ptr + 8, &body);
+
+        let borrowed = unsafe { borrow_bytes(ptr + 8, body.len() as i32) };
+        let request = Value::decode(borrowed).expect("decode request");
+        assert_eq!(request, Value::from("borrowed"));
+
+        free(ptr, len);
+
+        let header = txn_header_bytes();
+        let ptr = alloc(header.len() as i32);
+        memory::write(ptr, &header);
+
+        let borrowed = unsafe { borrow_bytes(ptr, header.len() as i32) };
+        assert!(decode_header_bytes(borrowed).is_ok());
+        free(ptr, header.len() as i32);
+
+        assert!(unsafe { borrow_bytes(0, 16) }.is_empty());
+        assert!(unsafe { borrow_bytes(8, 0) }.is_empty());
+    }
+
+    #[test]
+    fn packs_ptr_low_len_high() {
+        assert_eq!(pack_ptr_len(0x1000, 0x20), 0x0000_0020_0000_1000);
+        assert_eq!(
+            pack_ptr_len(0x1234_5678, 0x7fff_ffff),
+            0x7fff_ffff_1234_5678
+        );
+
+        // pointers above 2 GiB must not sign-extend into the length
+        assert_eq!(pack_ptr_len(-1, 1), 0x0000_0001_ffff_ffff);
+        assert_eq!(
+            pack_ptr_len(i32::MIN, 0).to_le_bytes(),
+            [0, 0, 0, 0x80, 0, 0, 0, 0]
+        );
+
+        assert_eq!(unpack_ptr_len(0x0000_0020_0000_1000), (0x1000, 0x20));
+        assert_eq!(unpack_ptr_len(pack_ptr_len(-1, 1)), (-1, 1));
+    }
+
+    #[test]
+    fn distinguishes_absent_and_empty_bodies() {
+        let header_bytes = txn_header_bytes();
+        let put = |body: Option<&[u8]>| {
+            let response = try_dispatch_put_opt_bytes::<_, FakeTxn, Option<Value>, Value>(
+                &OptHandler,
+                &header_bytes,
+                body,
+            )
+            .expect("put response");
+
+            Value::decode(&response).expect("decode response")
+        };
+
+        let present = encode_json_bytes(Value::from(7u64)).expect("body json");
+
+        assert_eq!(put(None), Value::from("absent"));
+        assert_eq!(
+            put(Some(&[][..])),
+            Value::String(format!("present:{:?}", Value::None))
+        );
+        assert_eq!(
+            put(Some(present.as_slice())),
+            Value::String(format!("present:{:?}", Value::from(7u64)))
+        );
+    }
+
+    #[test]
+    fn lists_library_routes() {
+        let schema = crate::LibrarySchemaBuilder::new("/lib/example-devco/example/0.1.0", "0.1.0")
+            .expect("schema")
+            .build();
+
+        let library: tc_ir::StaticLibrary<FakeTxn, tc_ir::Dir<()>> =
+            tc_ir::StaticLibrary::new(schema, tc_ir::Dir::new());
+
+        let routes = [
+            RouteExport::new("/hello", "hello").with_method(Method::Get),
+            RouteExport::new("/store", "store"),
+        ];
+
+        assert_eq!(
+            library_routes(&library, &routes),
+            vec![
+                RouteInfo {
+                    path: "/hello".to_string(),
+                    export: "hello".to_string(),
+                    method: Some(Method::Get),
+                    required_mode: Mode::from(0),
+                    depends_on: Vec::new(),
+                    imports: Vec::new(),
+                    example_request: None,
+                    example_response: None,
+                    timeout_ms: None,
+                    version: "0.1.0".to_string(),
+                },
+                RouteInfo {
+                    path: "/store".to_string(),
+                    export: "store".to_string(),
+                    method: None,
+                    required_mode: Mode::from(0),
+                    depends_on: Vec::new(),
+                    imports: Vec::new(),
+                    example_request: None,
+                    example_response: None,
+                    timeout_ms: None,
+                    version: "0.1.0".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn manifest_includes_route_examples() {
+        let schema = crate::LibrarySchemaBuilder::new("/lib/example-devco/example/0.1.0", "0.1.0")
+            .expect("schema")
+            .build();
+
+        let library: tc_ir::StaticLibrary<FakeTxn, tc_ir::Dir<()>> =
+            tc_ir::StaticLibrary::new(schema, tc_ir::Dir::new());
+
+        let hello = RouteExport::new("/hello", "hello")
+            .with_example_request(r#""World""#)
+            .with_example_response(r#""Hello, World!""#);
+
+        let manifest = manifest_bytes(&library, &[hello]).expect("manifest");
+        let manifest: serde_json::Value = serde_json::from_slice(&manifest).expect("json");
+        assert_eq!(manifest["routes"][0]["example_request"], r#""World""#);
+        assert_eq!(
+            manifest["routes"][0]["example_response"],
+            r#""Hello, World!""#
+        );
+
+        let invalid = RouteExport::new("/hello", "hello").with_example_response("{not json");
+        let err = invalid.validate().expect_err("invalid example");
+        assert!(err.to_string().contains("example response"), "{err}");
+        assert!(manifest_bytes(&library, &[invalid]).is_err());
+    }
+
+    #[test]
+    fn oversized_manifest_falls_back_to_stream() {
+        let schema = crate::LibrarySchemaBuilder::new("/lib/example-devco/example/0.1.0", "0.1.0")
+            .expect("schema")
+            .build();
+
+        let library: tc_ir::StaticLibrary<FakeTxn, tc_ir::Dir<()>> =
+            tc_ir::StaticLibrary::new(schema, tc_ir::Dir::new());
+
+        let routes = [RouteExport::new("/hello", "hello")];
+        let manifest = manifest_bytes(&library, &routes).expect("manifest");
+        assert_eq!(
+            library_entry_bytes(&library, &routes, Some(MANIFEST_STREAM_EXPORT)).expect("entry"),
+            manifest
+        );
+
+        set_max_manifest_bytes(manifest.len() - 1);
+        let fallback = library_entry_bytes(&library, &routes, Some(MANIFEST_STREAM_EXPORT));
+        let unstreamed = library_entry_bytes(&library, &routes, None);
+        set_max_manifest_bytes(DEFAULT_MAX_MANIFEST_BYTES);
+
+        let err = unstreamed.expect_err("no stream export");
+        assert!(err.to_string().contains("exceeds"));
+
+        let fallback: serde_json::Value =
+            serde_json::from_slice(&fallback.expect("fallback")).expect("json");
+        assert_eq!(fallback["manifest_bytes"], manifest.len());
+        assert_eq!(fallback["stream_export"], MANIFEST_STREAM_EXPORT);
+        assert!(
+            fallback["error"]
+                .as_str()
+                .expect("error")
+                .contains("exceeds")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_utf8_text() {
+        let mut body = vec![b'a'; SIMD_UTF8_THRESHOLD * 2];
+        body[SIMD_UTF8_THRESHOLD + 1] = 0xFF;
+
+        let err = String::decode(&body).expect_err("invalid utf-8");
+        assert!(err.to_string().contains("invalid utf-8"));
+
+        let err = String::decode(b"ab\xC3").expect_err("truncated utf-8");
+        assert!(err.to_string().contains("invalid utf-8"));
+
+        let text = "é".repeat(SIMD_UTF8_THRESHOLD);
+        assert_eq!(String::decode(text.as_bytes()).expect("valid utf-8"), text);
+    }
+
+    #[test]
+    fn rejects_invalid_routes() {
+        let schema = crate::LibrarySchemaBuilder::new("/lib/example-devco/example/0.1.0", "0.1.0")
+            .expect("schema")
+            .build();
+
+        let library: tc_ir::StaticLibrary<FakeTxn, tc_ir::Dir<()>> =
+            tc_ir::StaticLibrary::new(schema, tc_ir::Dir::new());
+
+        let manifest =
+            manifest_bytes(&library, &[RouteExport::new("/hello", "hello_v2")]).expect("manifest");
+        assert!(!manifest.is_empty());
+
+        for route in [
+            RouteExport::new("hello", "hello"),
+            RouteExport::new("", "hello"),
+            RouteExport::new("/hello world", "hello"),
+            RouteExport::new("/hello", ""),
+            RouteExport::new("/hello", "2hello"),
+            RouteExport::new("/hello", "hello-world"),
+        ] {
+            assert!(
+                route.validate().is_err(),
+                "{} -> {}",
+                route.path,
+                route.export
+            );
+            assert!(manifest_bytes(&library, &[route]).is_err());
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip_as_base64() {
+        let bytes = "héllo, wörld ✓"
+            .as_bytes()
+            .iter()
+            .copied()
+            .chain([0, 0xff, 0xfe]);
+        let bytes: Vec<u8> = bytes.collect();
+        let nested = Value::Tuple(vec![Value::Bytes(vec![0xff].into())].into());
+        let value =
+            Value::Tuple(vec![Value::Bytes(bytes.clone().into()), Value::from("x"), nested].into());
+
+        let json = value.clone().encode().expect("value json");
+        let expected = format!(r#"{{"$bytes":"{}"}}"#, BASE64.encode(&bytes));
+        assert!(String::from_utf8_lossy(&json).contains(&expected));
+
+        assert_eq!(Value::decode(&json).expect("decode value"), value);
+        assert!(Value::decode(br#"{"$bytes": "not base64!"}"#).is_err());
+    }
+
+    #[test]
+    fn passes_route_path_to_shared_handler() {
+        struct MountHandler;
+
+        impl tc_ir::HandleGet<FakeTxn> for MountHandler {
+            type Request = Value;
+            type RequestContext = ();
+            type Response = Value;
+            type Error = TCError;
+            type Fut<'a> =
+                Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+            fn get<'a>(&'a self, _txn: &'a FakeTxn, _request: Value) -> TCResult<Self::Fut<'a>> {
+                let path = route_path().expect("route path");
+                Ok(Box::pin(async move { Ok(Value::from(path)) }))
+            }
+        }
+
+        let get = |route: &RouteExport| {
+            let response = try_dispatch_get_scoped_bytes::<_, FakeTxn, Value, Value>(
+                route,
+                &MountHandler,
+                &txn_header_bytes(),
+                &[],
+            )
+            .expect("response");
+
+            Value::decode(&response).expect("path")
+        };
+
+        assert_eq!(
+            get(&RouteExport::new("/users", "users")),
+            Value::from("/users")
+        );
+        assert_eq!(
+            get(&RouteExport::new("/admins", "admins")),
+            Value::from("/admins")
+        );
+        assert_eq!(route_path(), None);
+    }
+
+    #[test]
+    fn enforces_per_route_timeouts() {
+        /// Takes as many (simulated) milliseconds as the request says.
+        struct SlowHandler;
+
+        impl tc_ir::HandleGet<FakeTxn> for SlowHandler {
+            type Request = u64;
+            type RequestContext = ();
+            type Response = Value;
+            type Error = TCError;
+            type Fut<'a> =
+                Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+            fn get<'a>(&'a self, _txn: &'a FakeTxn, millis: u64) -> TCResult<Self::Fut<'a>> {
+                crate::testing::with_mock(|host| host.advance_time(Duration::from_millis(millis)));
+                Ok(Box::pin(async move { Ok(Value::from(millis)) }))
+            }
+        }
+
+        let mock = crate::testing::MockHost::new();
+        let _guard = mock.install();
+
+        let lookup = RouteExport::new("/lookup", "lookup");
+        let analytics = RouteExport::new("/analytics", "analytics").with_timeout_ms(500);
+
+        let get = |route: &RouteExport, millis: u64| {
+            try_dispatch_get_scoped_bytes::<_, FakeTxn, u64, Value>(
+                route,
+                &SlowHandler,
+                &txn_header_bytes(),
+                millis.to_string().as_bytes(),
+            )
+        };
+
+        // without a default, only a route which declares a budget has one
+        assert!(get(&lookup, 200).is_ok());
+        assert!(get(&analytics, 600).is_err());
+
+        set_default_timeout(Some(Duration::from_millis(100)));
+        let slow_lookup = get(&lookup, 200);
+        let slow_analytics = get(&analytics, 200);
+        set_default_timeout(None);
+
+        let err = slow_lookup.expect_err("lookup overran the default");
+        assert!(
+            err.to_string()
+                .contains("/lookup exceeded its timeout of 100 ms"),
+            "{err}"
+        );
+        assert!(is_retryable(&err));
+        assert!(slow_analytics.is_ok());
+
+        let route: serde_json::Value =
+            serde_json::from_slice(&encode_json_bytes(analytics).expect("route")).expect("json");
+        assert_eq!(route["timeout_ms"], 500);
+    }
+
+    #[test]
+    fn enforces_route_mode() {
+        let link = Link::from_str("/lib").expect("claim link");
+        let read_only = txn_header_bytes_for(crate::claim::read_only(link.clone()));
+        let read_write = txn_header_bytes_for(crate::claim::read_write(link));
+        let body = encode_json_bytes(Value::from("World")).expect("body json");
+
+        let hello =
+            RouteExport::new("/hello", "hello").with_required_mode(Mode::from(crate::claim::READ));
+        let store =
+            RouteExport::new("/store", "store").with_required_mode(Mode::from(crate::claim::WRITE));
+
+        for header in [&read_only, &read_write] {
+            assert!(
+                try_dispatch_get_scoped_bytes::<_, FakeTxn, Value, Value>(
+                    &hello,
+                    &HelloHandler,
+                    header,
+                    &body,
+                )
+                .is_ok()
+            );
+        }
+
+        let denied = try_dispatch_put_scoped_bytes::<_, FakeTxn, Value, Value>(
+            &store,
+            &HelloHandler,
+            &read_only,
+            &body,
+        );
+        assert!(
+            denied
+                .expect_err("read-only claim")
+                .to_string()
+                .contains("/store")
+        );
+
+        assert!(
+            try_dispatch_put_scoped_bytes::<_, FakeTxn, Value, Value>(
+                &store,
+                &HelloHandler,
+                &read_write,
+                &body,
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn async_dispatch_matches_blocking() {
+        let header_bytes = txn_header_bytes();
+        let body_bytes = encode_json_bytes(Value::from("World")).expect("body json");
+
+        let blocking = try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(
+            &HelloHandler,
+            &header_bytes,
+            &body_bytes,
+        )
+        .expect("blocking response");
+
+        let polled = block_on(async {
+            dispatch_get_async::<_, FakeTxn, Value, Value>(
+                &HelloHandler,
+                &header_bytes,
+                &body_bytes,
+            )
+            .await
+        });
+
+        assert_eq!(polled, blocking);
+
+        let error = block_on(dispatch_get_async::<_, FakeTxn, Value, Value>(
+            &HelloHandler,
+            &[],
+            &body_bytes,
+        ));
+        assert!(String::from_utf8_lossy(&error).contains("missing transaction header"));
+    }
+
+    #[test]
+    fn streams_large_manifest() {
+        let schema = crate::LibrarySchemaBuilder::new("/lib/example-devco/example/0.1.0", "0.1.0")
+            .expect("schema")
+            .build();
+
+        let library: tc_ir::StaticLibrary<FakeTxn, tc_ir::Dir<()>> =
+            tc_ir::StaticLibrary::new(schema, tc_ir::Dir::new());
+
+        let routes: Vec<RouteExport> = (0..2_000)
+            .map(|i| {
+                let path = String::leak(format!("/route/{i}"));
+                let export = String::leak(format!("route_{i}"));
+                RouteExport::new(path, export)
+            })
+            .collect();
+
+        let routes: &'static [RouteExport] = Vec::leak(routes);
+
+        let handle = manifest_stream(&library, routes).expect("manifest stream");
+
+        let mut chunks = Vec::new();
+        while let Some(chunk) = crate::stream::try_stream_next(handle) {
+            chunks.push(chunk);
+        }
+
+        assert_eq!(chunks.len(), routes.len() + 2);
+
+        let streamed: serde_json::Value =
+            serde_json::from_slice(&chunks.concat()).expect("streamed manifest");
+
+        let buffered = manifest_bytes(&library, routes).expect("manifest");
+        let buffered: serde_json::Value = serde_json::from_slice(&buffered).expect("manifest");
+
+        assert_eq!(streamed, buffered);
+        assert_eq!(
+            streamed["routes"].as_array().map(Vec::len),
+            Some(routes.len())
+        );
+    }
+
+    #[test]
+    fn read_only_library_rejects_mutations() {
+        set_read_only(true);
+
+        let put = try_dispatch_put_bytes::<_, FakeTxn, Value, Value>(
+            &HelloHandler,
+            &txn_header_bytes(),
+            br#""draft""#,
+        );
+
+        let get = try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(
+            &HelloHandler,
+            &txn_header_bytes(),
+            br#""Ada""#,
+        );
+
+        let schema = crate::LibrarySchemaBuilder::new("/lib/example-devco/example/0.1.0", "0.1.0")
+            .expect("schema")
+            .build();
+
+        let library: tc_ir::StaticLibrary<FakeTxn, tc_ir::Dir<()>> =
+            tc_ir::StaticLibrary::new(schema, tc_ir::Dir::new());
+
+        let manifest = manifest_bytes(&library, &[RouteExport::new("/hello", "hello")]);
+
+        set_read_only(false);
+
+        let err = put.expect_err("read-only put");
+        assert!(err.to_string().contains("read-only library"), "{err}");
+
+        let get = get.expect("read-only get");
+        assert_eq!(
+            Value::decode(&get).expect("greeting"),
+            Value::from("Hello, Ada!")
+        );
+
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&manifest.expect("manifest")).expect("manifest json");
+        assert_eq!(manifest["read_only"], true);
+    }
+
+    #[test]
+    fn rejects_duplicate_routes() {
+        let err = validate_routes(&[
+            RouteExport::new("/hello", "hello"),
+            RouteExport::new("/greet", "hello"),
+        ])
+        .expect_err("duplicate export");
+        assert!(err.to_string().contains("duplicate export"));
+
+        let err = validate_routes(&[
+            RouteExport::new("/hello", "hello_get").with_method(Method::Get),
+            RouteExport::new("/hello", "hello"),
+        ])
+        .expect_err("duplicate path");
+        assert!(err.to_string().contains("duplicate route path"));
+
+        validate_routes(&[
+            RouteExport::new("/hello", "hello_get").with_method(Method::Get),
+            RouteExport::new("/hello", "hello_put").with_method(Method::Put),
+        ])
+        .expect("one export per method");
+    }
+
+    #[test]
+    fn orders_route_dependencies() {
+        let routes = [
+            RouteExport::new("/report", "report").with_dependencies(&["/users", "/orders"]),
+            RouteExport::new("/orders", "orders").with_dependencies(&["/users"]),
+            RouteExport::new("/users", "users"),
+        ];
+
+        assert_eq!(
+            route_init_order(&routes).expect("init order"),
+            vec!["/users", "/orders", "/report"]
+        );
+
+        let json = encode_json_bytes(routes[0]).expect("route json");
+        let json: serde_json::Value = serde_json::from_slice(&json).expect("route json");
+        assert_eq!(json["depends_on"], serde_json::json!(["/users", "/orders"]));
+
+        let unknown = [RouteExport::new("/a", "a").with_dependencies(&["/missing"])];
+        assert!(route_init_order(&unknown).is_err());
+    }
+
+    #[test]
+    fn detects_route_dependency_cycle() {
+        let routes = [
+            RouteExport::new("/a", "a").with_dependencies(&["/b"]),
+            RouteExport::new("/b", "b").with_dependencies(&["/c"]),
+            RouteExport::new("/c", "c").with_dependencies(&["/a"]),
+        ];
+
+        let err = route_init_order(&routes).expect_err("cycle");
+        assert!(err.to_string().contains("/a -> /b -> /c -> /a"));
+    }
+
+    #[test]
+    fn scalar_fast_path_matches_decoder() {
+        let inputs: &[&[u8]] = &[
+            b"",
+            b" ",
+            b"0",
+            b"-0",
+            b"7",
+            b" 42\n",
+            b"-42",
+            b"007",
+            b"-007",
+            b"+1",
+            b"1e3",
+            b"1.5",
+            b"18446744073709551615",
+            b"18446744073709551616",
+            b"-9223372036854775808",
+            b"-9223372036854775809",
+            b"true",
+            b" false ",
+            b"\"7\"",
+        ];
+
+        for input in inputs {
+            let fast = Number::decode(input).map(|n| format!("{n:?}"));
+            let slow = decode_number(input).map(|n| format!("{n:?}"));
+            assert_eq!(
+                fast.ok(),
+                slow.ok(),
+                "Number {:?}",
+                String::from_utf8_lossy(input)
+            );
+
+            let fast = u64::decode(input).ok();
+            let slow = decode_serde_json::<u64>(input).ok();
+            assert_eq!(fast, slow, "u64 {:?}", String::from_utf8_lossy(input));
+
+            let fast = i64::decode(input).ok();
+            let slow = decode_serde_json::<i64>(input).ok();
+            assert_eq!(fast, slow, "i64 {:?}", String::from_utf8_lossy(input));
+
+            let fast = bool::decode(input).ok();
+            let slow = decode_serde_json::<bool>(input).ok();
+            assert_eq!(fast, slow, "bool {:?}", String::from_utf8_lossy(input));
+        }
+
+        assert!(fast_int(b"-0").is_none());
+        assert!(fast_int(b"007").is_none());
+        assert!(matches!(fast_int(b" 42 "), Some(FastInt::UInt(42))));
+    }
 }
@@ -0,0 +1,196 @@
+//! Per-call allocation accounting, so a runaway handler fails its call instead of exhausting
+//! the instance's memory.
+//!
+//! With the `alloc-limit` feature on `wasm32`, this crate installs a [`LimitedAllocator`]
+//! over the system allocator as the global allocator. It counts the bytes each call holds
+//! (net of frees) from the moment its header is decoded. Once they pass the limit set by
+//! [`set_allocation_limit`] the call is marked as over its limit: allocations are still
+//! served (so the handler can unwind normally) but the dispatch returns
+//! `TCError::internal("allocation limit exceeded")` instead of the handler's response.
+//!
+//! Tradeoffs:
+//! - a single allocation larger than the memory the instance can grow to still traps
+//! - long-running handlers should poll [`allocation_limit_exceeded`] to stop early
+//! - every allocation pays for a few thread-local counter updates
+//! - it can't be combined with the `bump-alloc` feature, which installs its own allocator
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use tc_error::{TCError, TCResult};
+
+#[cfg(all(
+    feature = "alloc-limit",
+    feature = "bump-alloc",
+    target_arch = "wasm32"
+))]
+compile_error!("the alloc-limit and bump-alloc features each install a global allocator");
+
+#[cfg(any(all(feature = "alloc-limit", target_arch = "wasm32"), test))]
+#[global_allocator]
+static ALLOCATOR: LimitedAllocator<System> = LimitedAllocator::new(System);
+
+thread_local! {
+    static ALLOCATION_LIMIT: Cell<Option<usize>> = const { Cell::new(None) };
+    static CALL_LIVE_BYTES: Cell<usize> = const { Cell::new(0) };
+    static CALL_PEAK_BYTES: Cell<usize> = const { Cell::new(0) };
+    static LIMIT_EXCEEDED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Set the maximum number of bytes a single call may hold at once, or `None` (the default)
+/// for no limit.
+pub fn set_allocation_limit(limit: Option<usize>) {
+    ALLOCATION_LIMIT.with(|current| current.set(limit));
+}
+
+/// Whether the current call has passed its allocation limit.
+pub fn allocation_limit_exceeded() -> bool {
+    LIMIT_EXCEEDED.with(Cell::get)
+}
+
+/// The most bytes the current (or most recent) call has held at once.
+pub fn call_peak_allocation() -> usize {
+    CALL_PEAK_BYTES.with(Cell::get)
+}
+
+/// Start accounting for a new call, as each dispatch does on decoding its header.
+pub(crate) fn begin_call_allocations() {
+    CALL_LIVE_BYTES.with(|live| live.set(0));
+    CALL_PEAK_BYTES.with(|peak| peak.set(0));
+    LIMIT_EXCEEDED.with(|exceeded| exceeded.set(false));
+}
+
+/// Fail if the current call has passed its allocation limit.
+pub(crate) fn check_call_allocations() -> TCResult<()> {
+    if allocation_limit_exceeded() {
+        Err(TCError::internal("allocation limit exceeded"))
+    } else {
+        Ok(())
+    }
+}
+
+// these use `try_with` since the allocator may be called while thread locals are torn down
+fn record_alloc(size: usize) {
+    let _ = CALL_LIVE_BYTES.try_with(|live| {
+        let bytes = live.get().saturating_add(size);
+        live.set(bytes);
+
+        let _ = CALL_PEAK_BYTES.try_with(|peak| peak.set(peak.get().max(bytes)));
+
+        let limit = ALLOCATION_LIMIT.try_with(Cell::get).ok().flatten();
+        if limit.is_some_and(|limit| bytes > limit) {
+            let _ = LIMIT_EXCEEDED.try_with(|exceeded| exceeded.set(true));
+        }
+    });
+}
+
+fn record_dealloc(size: usize) {
+    let _ = CALL_LIVE_BYTES.try_with(|live| live.set(live.get().saturating_sub(size)));
+}
+
+/// Wraps another allocator to count the bytes allocated during each call.
+pub struct LimitedAllocator<A> {
+    inner: A,
+}
+
+impl<A> LimitedAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for LimitedAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+        record_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            record_dealloc(layout.size());
+            record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+    use tc_value::Value;
+
+    use crate::abi::{WasmRequest, response_bytes, try_dispatch_get_bytes};
+    use crate::convert::FromValue;
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+
+    /// Allocates a buffer of the requested size.
+    struct AllocHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for AllocHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let len = u64::from_value(request)? as usize;
+            let buffer = std::hint::black_box(vec![1u8; len]);
+            let len = buffer.len() as u64;
+            Ok(Box::pin(async move { Ok(Value::from(len)) }))
+        }
+    }
+
+    fn alloc(len: usize) -> Vec<u8> {
+        let body = len.to_string();
+        response_bytes(try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(
+            &AllocHandler,
+            &txn_header_bytes(),
+            body.as_bytes(),
+        ))
+    }
+
+    #[test]
+    fn fails_calls_past_the_allocation_limit() {
+        set_allocation_limit(Some(1 << 20));
+
+        let response = alloc(1 << 10);
+        assert_eq!(
+            Value::decode(&response).expect("small allocation"),
+            Value::from(1024u64)
+        );
+        assert!(call_peak_allocation() >= 1 << 10);
+
+        let response = alloc(2 << 20);
+        let error = String::from_utf8(response).expect("error json");
+        assert!(error.contains("allocation limit exceeded"), "{error}");
+        assert!(allocation_limit_exceeded());
+
+        // the next call starts from a clean slate
+        let response = alloc(1 << 10);
+        assert!(Value::decode(&response).is_ok());
+        assert!(!allocation_limit_exceeded());
+
+        set_allocation_limit(None);
+    }
+}
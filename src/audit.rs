@@ -0,0 +1,152 @@
+//! Opt-in audit records, for deployments which need a trail of what each call did.
+
+use destream::en::{self, EncodeMap};
+use tc_error::TCResult;
+use tc_ir::Claim;
+
+use crate::abi::{WasmResponse, encode_json_bytes};
+use crate::host::{self, LogLevel};
+
+/// The prefix of every audit record written to the host log.
+pub const AUDIT_LOG_PREFIX: &str = "audit ";
+
+/// What a call did: the `actor` (the caller's claim) performed `action` on `resource`.
+///
+/// Encoded as `{"actor": {"link": "...", "mode": <bits>}, "action": "...", "resource": "..."}`.
+#[derive(Clone)]
+pub struct AuditRecord {
+    pub actor: Claim,
+    pub action: String,
+    pub resource: String,
+}
+
+impl AuditRecord {
+    pub fn new(actor: Claim, action: impl Into<String>, resource: impl Into<String>) -> Self {
+        Self {
+            actor,
+            action: action.into(),
+            resource: resource.into(),
+        }
+    }
+}
+
+impl<'en> en::IntoStream<'en> for AuditRecord {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(3))?;
+        map.encode_entry("actor", Actor(self.actor))?;
+        map.encode_entry("action", self.action)?;
+        map.encode_entry("resource", self.resource)?;
+        map.end()
+    }
+}
+
+struct Actor(Claim);
+
+impl<'en> en::IntoStream<'en> for Actor {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(2))?;
+        map.encode_entry("link", self.0.link().to_string())?;
+        map.encode_entry("mode", u32::from(self.0.mode()))?;
+        map.end()
+    }
+}
+
+/// A response with [`AuditRecord`]s attached. Encoding it writes each record to the host log
+/// (at [`LogLevel::Info`], as [`AUDIT_LOG_PREFIX`] followed by the record's JSON) and then
+/// encodes the body unchanged, so clients see the same response as without auditing.
+pub struct Audited<T> {
+    body: T,
+    records: Vec<AuditRecord>,
+}
+
+impl<T> Audited<T> {
+    pub fn new(body: T) -> Self {
+        Self {
+            body,
+            records: Vec::new(),
+        }
+    }
+
+    pub fn record(mut self, record: AuditRecord) -> Self {
+        self.records.push(record);
+        self
+    }
+}
+
+impl<T: WasmResponse> WasmResponse for Audited<T> {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        for record in self.records {
+            let record = encode_json_bytes(record)?;
+            let record = String::from_utf8_lossy(&record);
+            host::log(LogLevel::Info, &format!("{AUDIT_LOG_PREFIX}{record}"));
+        }
+
+        self.body.encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+    use tc_error::TCError;
+    use tc_ir::Transaction;
+    use tc_value::Value;
+
+    use crate::abi::{WasmRequest, try_dispatch_put_bytes};
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+    use crate::testing::MockHost;
+
+    struct StoreHandler;
+
+    impl tc_ir::HandlePut<FakeTxn> for StoreHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Audited<Value>;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn put<'a>(&'a self, txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let record = AuditRecord::new(txn.claim().clone(), "store", "/documents/1");
+            Ok(Box::pin(
+                async move { Ok(Audited::new(request).record(record)) },
+            ))
+        }
+    }
+
+    #[test]
+    fn emits_audit_record_through_host_log() {
+        let mock = MockHost::new();
+        let _guard = mock.install();
+
+        let response = try_dispatch_put_bytes::<_, FakeTxn, Value, Audited<Value>>(
+            &StoreHandler,
+            &txn_header_bytes(),
+            br#""contents""#,
+        )
+        .expect("store");
+
+        assert_eq!(
+            Value::decode(&response).expect("response"),
+            Value::from("contents")
+        );
+
+        let logs = mock.logs();
+        assert_eq!(logs.len(), 1);
+
+        let (level, message) = &logs[0];
+        assert_eq!(*level, LogLevel::Info);
+
+        let record = message
+            .strip_prefix(AUDIT_LOG_PREFIX)
+            .expect("audit prefix");
+        let record: serde_json::Value = serde_json::from_str(record).expect("audit json");
+        assert_eq!(record["action"], "store");
+        assert_eq!(record["resource"], "/documents/1");
+        assert_eq!(record["actor"]["link"], "/lib");
+        assert_eq!(record["actor"]["mode"], 0o777);
+    }
+}
@@ -0,0 +1,253 @@
+//! Several route calls in one host invocation, sharing a single transaction header.
+//!
+//! A library exports `tc_batch(header_ptr, header_len, body_ptr, body_len) -> i64`, whose body
+//! is a JSON array of `{"route": "/path", "method": "GET", "body": <json>}` entries (`method`
+//! and `body` are optional) and whose response is a JSON array of the same length, holding
+//! each entry's response or error payload in order.
+
+use serde::Deserialize;
+use tc_error::{TCError, TCResult};
+use tc_ir::Transaction;
+
+use crate::abi::{
+    Method, RouteExport, WasmTransaction, borrow_bytes, decode_header_bytes, decode_serde_json,
+    encode_error, respond,
+};
+
+/// Serves the entries of a batch. Each entry is routed to the [`RouteExport`] with its path
+/// (and method, if the path has more than one route) and called with the batch's shared
+/// transaction, e.g. by forwarding to the matching `dispatch_*_decoded` function:
+///
+/// ```ignore
+/// impl BatchHandler<ExampleTxn> for Library {
+///     fn call(&self, route: &RouteExport, _method: Method, txn: &ExampleTxn, body: &[u8])
+///         -> TCResult<Vec<u8>>
+///     {
+///         match route.export {
+///             "hello" => dispatch_get_decoded(&self.hello, txn, Value::decode(body)?)?.encode(),
+///             other => Err(TCError::not_found(other)),
+///         }
+///     }
+/// }
+/// ```
+pub trait BatchHandler<Txn: WasmTransaction> {
+    fn call(
+        &self,
+        route: &RouteExport,
+        method: Method,
+        txn: &Txn,
+        body: &[u8],
+    ) -> TCResult<Vec<u8>>;
+}
+
+#[derive(Deserialize)]
+struct BatchEntry {
+    route: String,
+    #[serde(default)]
+    method: Option<String>,
+    #[serde(default)]
+    body: Option<serde_json::Value>,
+}
+
+/// Implements the `tc_batch` export, routing each entry through `routes`.
+pub fn dispatch_batch<H, Txn>(
+    routes: &[RouteExport],
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: BatchHandler<Txn>,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_batch_bytes(
+        routes,
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and batch body. The header is decoded once for the whole
+/// batch; an entry which fails (e.g. names an unknown route) doesn't stop the ones after it.
+pub fn try_dispatch_batch_bytes<H, Txn>(
+    routes: &[RouteExport],
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: BatchHandler<Txn>,
+{
+    let header = decode_header_bytes(header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+    let entries: Vec<BatchEntry> = decode_serde_json(body_bytes)?;
+
+    let mut response = Vec::from(*b"[");
+
+    for (i, entry) in entries.into_iter().enumerate() {
+        if i > 0 {
+            response.push(b',');
+        }
+
+        match call_entry(routes, handler, &txn, entry) {
+            Ok(bytes) => response.extend(bytes),
+            Err(err) => response.extend(encode_error(err)),
+        }
+    }
+
+    response.push(b']');
+    Ok(response)
+}
+
+fn call_entry<H, Txn>(
+    routes: &[RouteExport],
+    handler: &H,
+    txn: &Txn,
+    entry: BatchEntry,
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: BatchHandler<Txn>,
+{
+    let method = entry.method.as_deref().map(str::parse).transpose()?;
+    let route = find_route(routes, &entry.route, method)?;
+    route.authorize(txn.claim())?;
+
+    let body = match entry.body {
+        Some(body) => serde_json::to_vec(&body)
+            .map_err(|err| TCError::bad_request(format!("invalid batch body: {err}")))?,
+        None => Vec::new(),
+    };
+
+    let method = method.or(route.method()).unwrap_or(Method::Get);
+    let response = handler.call(route, method, txn, &body)?;
+
+    // the responses are spliced into a JSON array, so each one must itself be JSON
+    serde_json::from_slice::<serde::de::IgnoredAny>(&response)
+        .map_err(|_| TCError::internal(format!("the response from {} is not JSON", route.path)))?;
+
+    Ok(response)
+}
+
+fn find_route<'a>(
+    routes: &'a [RouteExport],
+    path: &str,
+    method: Option<Method>,
+) -> TCResult<&'a RouteExport> {
+    let mut matches = routes.iter().filter(|route| {
+        route.path == path
+            && match (route.method(), method) {
+                (Some(served), Some(method)) => served == method,
+                _ => true,
+            }
+    });
+
+    match (matches.next(), matches.next()) {
+        (Some(route), None) => Ok(route),
+        (Some(_), Some(_)) => Err(TCError::bad_request(format!(
+            "batch entry for {path} must name a method"
+        ))),
+        (None, _) => Err(TCError::not_found(path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+    use tc_value::Value;
+
+    use crate::abi::{WasmRequest, WasmResponse, dispatch_get_decoded, dispatch_put_decoded};
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+
+    /// Reads and writes a single stored value.
+    struct Store {
+        value: Mutex<Value>,
+    }
+
+    impl tc_ir::HandleGet<FakeTxn> for Store {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(
+            &'a self,
+            _txn: &'a FakeTxn,
+            _request: Self::Request,
+        ) -> TCResult<Self::Fut<'a>> {
+            let value = self.value.lock().expect("store").clone();
+            Ok(Box::pin(async move { Ok(value) }))
+        }
+    }
+
+    impl tc_ir::HandlePut<FakeTxn> for Store {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn put<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let previous = std::mem::replace(&mut *self.value.lock().expect("store"), request);
+            Ok(Box::pin(async move { Ok(previous) }))
+        }
+    }
+
+    impl BatchHandler<FakeTxn> for Store {
+        fn call(
+            &self,
+            route: &RouteExport,
+            method: Method,
+            txn: &FakeTxn,
+            body: &[u8],
+        ) -> TCResult<Vec<u8>> {
+            match (route.export, method) {
+                ("store", Method::Get) => {
+                    dispatch_get_decoded(self, txn, Value::decode(body)?)?.encode()
+                }
+                ("store", Method::Put) => {
+                    dispatch_put_decoded(self, txn, Value::decode(body)?)?.encode()
+                }
+                _ => Err(TCError::method_not_allowed(method, route.path)),
+            }
+        }
+    }
+
+    const ROUTES: &[RouteExport] = &[RouteExport::new("/store", "store")];
+
+    #[test]
+    fn dispatches_batch_entries_in_order() {
+        let store = Store {
+            value: Mutex::new(Value::from("initial")),
+        };
+        let batch = br#"[
+            {"route": "/store", "method": "PUT", "body": "first"},
+            {"route": "/store"},
+            {"route": "/missing"}
+        ]"#;
+
+        let response =
+            try_dispatch_batch_bytes(ROUTES, &store, &txn_header_bytes(), batch).expect("batch");
+
+        let responses: Vec<serde_json::Value> =
+            serde_json::from_slice(&response).expect("batch response");
+
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0], "initial");
+        assert_eq!(responses[1], "first");
+        assert!(responses[2]["error"].is_string());
+    }
+}
@@ -0,0 +1,152 @@
+//! A bump allocator for short-lived request/response buffers.
+//!
+//! With the `bump-alloc` feature on `wasm32`, this crate installs a [`BumpAllocator`] over
+//! a static arena of [`HEAP_SIZE`] bytes as the global allocator. Allocation is a pointer
+//! increment and `dealloc` is a no-op, which keeps code size small and avoids
+//! fragmentation from request/response churn.
+//!
+//! Tradeoffs:
+//! - memory is only reclaimed in bulk by [`reset_heap`], which the library's `tc_reset`
+//!   export must call once the host has consumed (and freed) the previous response
+//! - anything allocated after the last [`mark_heap`] is discarded on reset, so call
+//!   `mark_heap` once lazily-initialized statics (e.g. the library itself) are built
+//! - the arena has a fixed size; an allocation which doesn't fit fails (and aborts)
+//!   instead of growing linear memory
+//! - the allocator is not thread-safe, which is fine for single-threaded WASM only
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::{Cell, UnsafeCell};
+use std::ptr;
+
+/// The size of the global arena installed by the `bump-alloc` feature (32 MiB).
+pub const HEAP_SIZE: usize = 32 << 20;
+
+#[cfg(all(feature = "bump-alloc", target_arch = "wasm32"))]
+#[global_allocator]
+static ALLOCATOR: BumpAllocator<HEAP_SIZE> = BumpAllocator::new();
+
+/// Record the current heap usage as the point [`reset_heap`] returns to.
+#[cfg(all(feature = "bump-alloc", target_arch = "wasm32"))]
+pub fn mark_heap() {
+    ALLOCATOR.mark();
+}
+
+/// Release every allocation made since the last [`mark_heap`]. The response cache is
+/// cleared first, since its entries live on the heap.
+///
+/// # Safety
+/// No value allocated since the last mark may be used after this call.
+#[cfg(all(feature = "bump-alloc", target_arch = "wasm32"))]
+pub unsafe fn reset_heap() {
+    crate::cache::reset_response_cache();
+    unsafe { ALLOCATOR.reset() }
+}
+
+/// A bump allocator over a fixed arena of `N` bytes.
+pub struct BumpAllocator<const N: usize> {
+    arena: UnsafeCell<[u8; N]>,
+    offset: Cell<usize>,
+    watermark: Cell<usize>,
+}
+
+// SAFETY: WASM libraries are single-threaded, so the allocator is never shared across threads
+unsafe impl<const N: usize> Sync for BumpAllocator<N> {}
+
+impl<const N: usize> BumpAllocator<N> {
+    pub const fn new() -> Self {
+        Self {
+            arena: UnsafeCell::new([0; N]),
+            offset: Cell::new(0),
+            watermark: Cell::new(0),
+        }
+    }
+
+    /// The number of bytes currently allocated (including alignment padding).
+    pub fn used(&self) -> usize {
+        self.offset.get()
+    }
+
+    /// Record the current offset as the point [`Self::reset`] returns to.
+    pub fn mark(&self) {
+        self.watermark.set(self.offset.get());
+    }
+
+    /// Release every allocation made since the last [`Self::mark`].
+    ///
+    /// # Safety
+    /// No value allocated since the last mark may be used after this call.
+    pub unsafe fn reset(&self) {
+        self.offset.set(self.watermark.get());
+    }
+}
+
+impl<const N: usize> Default for BumpAllocator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for BumpAllocator<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let base = self.arena.get() as *mut u8;
+        let start = (base as usize + self.offset.get()).next_multiple_of(layout.align());
+        let offset = start - base as usize;
+
+        match offset.checked_add(layout.size()) {
+            Some(end) if end <= N => {
+                self.offset.set(end);
+                unsafe { base.add(offset) }
+            }
+            _ => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        // memory is reclaimed in bulk by `reset`
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_aligned_allocations() {
+        let allocator = BumpAllocator::<256>::new();
+
+        let byte = unsafe { allocator.alloc(Layout::new::<u8>()) };
+        let word = unsafe { allocator.alloc(Layout::new::<u64>()) };
+
+        assert!(!byte.is_null());
+        assert!(!word.is_null());
+        assert_eq!(word as usize % std::mem::align_of::<u64>(), 0);
+        assert!(allocator.used() >= 9);
+    }
+
+    #[test]
+    fn fails_when_exhausted() {
+        let allocator = BumpAllocator::<64>::new();
+
+        let layout = Layout::from_size_align(48, 1).expect("layout");
+        assert!(!unsafe { allocator.alloc(layout) }.is_null());
+        assert!(unsafe { allocator.alloc(layout) }.is_null());
+    }
+
+    #[test]
+    fn resets_to_watermark() {
+        let allocator = BumpAllocator::<128>::new();
+        let layout = Layout::from_size_align(32, 8).expect("layout");
+
+        let retained = unsafe { allocator.alloc(layout) };
+        allocator.mark();
+        let used = allocator.used();
+
+        let first = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.reset() };
+        assert_eq!(allocator.used(), used);
+
+        let second = unsafe { allocator.alloc(layout) };
+        assert_eq!(first, second);
+        assert_ne!(retained, second);
+    }
+}
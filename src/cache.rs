@@ -0,0 +1,270 @@
+//! An opt-in response cache for pure GET handlers.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, decode_header_bytes,
+    dispatch_get_decoded, respond,
+};
+use crate::codec::request_body;
+
+/// The default number of responses retained by the cache.
+pub const DEFAULT_RESPONSE_CACHE_CAPACITY: usize = 64;
+
+thread_local! {
+    static RESPONSE_CACHE: RefCell<ResponseCache> = RefCell::new(ResponseCache::default());
+    static RESPONSE_CACHE_CAPACITY: Cell<usize> = const {
+        Cell::new(DEFAULT_RESPONSE_CACHE_CAPACITY)
+    };
+}
+
+/// Set the maximum number of cached responses. Least-recently-used entries are evicted
+/// once the cache is full.
+pub fn set_response_cache_capacity(capacity: usize) {
+    RESPONSE_CACHE_CAPACITY.with(|max| max.set(capacity));
+    RESPONSE_CACHE.with(|cache| cache.borrow_mut().evict_to(capacity));
+}
+
+/// Drop every cached response.
+pub fn reset_response_cache() {
+    RESPONSE_CACHE.with(|cache| *cache.borrow_mut() = ResponseCache::default());
+}
+
+/// Like [`crate::dispatch_get`], but serves repeated requests for `route` from the response
+/// cache. Only use this for handlers whose response depends on nothing but the request
+/// body and the caller's claim; errors are never cached.
+///
+/// The header is decoded and checked (including request expiry and claim scope) before the
+/// cache is consulted, and responses are cached per claim, so a caller is never served a
+/// response computed for a different claim.
+pub fn dispatch_get_cached<H, Txn, Req, Res>(
+    route: &'static str,
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Res, Error = TCError>,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_cached_bytes(
+        route,
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and body through the response cache.
+pub fn try_dispatch_get_cached_bytes<H, Txn, Req, Res>(
+    route: &'static str,
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Res, Error = TCError>,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let header = decode_header_bytes(header_bytes)?;
+    let claim = (
+        header.claim().link().to_string(),
+        u32::from(header.claim().mode()),
+    );
+    let txn = Txn::from_wasm_header(header)?;
+
+    // key by the body as JSON, so the same request in another codec shares its entry
+    let body = request_body(body_bytes)?;
+    let key = (route, claim, body.to_vec());
+    if let Some(response) = RESPONSE_CACHE.with(|cache| cache.borrow_mut().get(&key)) {
+        return Ok(response);
+    }
+
+    let request = Req::decode(&body)?;
+    let response = dispatch_get_decoded(handler, &txn, request)?.encode()?;
+
+    let capacity = RESPONSE_CACHE_CAPACITY.with(Cell::get);
+    RESPONSE_CACHE.with(|cache| cache.borrow_mut().insert(key, response.clone(), capacity));
+
+    Ok(response)
+}
+
+/// The route, the link and mode of the caller's claim, and the request body.
+type CacheKey = (&'static str, (String, u32), Vec<u8>);
+
+#[derive(Default)]
+struct ResponseCache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    clock: u64,
+}
+
+struct CacheEntry {
+    response: Vec<u8>,
+    last_used: u64,
+}
+
+impl ResponseCache {
+    fn get(&mut self, key: &CacheKey) -> Option<Vec<u8>> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.clock;
+        Some(entry.response.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, response: Vec<u8>, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+
+        self.evict_to(capacity - 1);
+        self.clock += 1;
+
+        let entry = CacheEntry {
+            response,
+            last_used: self.clock,
+        };
+
+        self.entries.insert(key, entry);
+    }
+
+    fn evict_to(&mut self, capacity: usize) {
+        while self.entries.len() > capacity {
+            let lru = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+                .expect("cache entry");
+
+            self.entries.remove(&lru);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tc_value::Value;
+
+    use pathlink::Link;
+    use std::str::FromStr;
+
+    use crate::claim;
+    use crate::test_support::{FakeTxn, txn_header_bytes, txn_header_bytes_for};
+
+    /// Echoes the request along with how many times it has been called.
+    #[derive(Default)]
+    struct CountingHandler {
+        calls: AtomicUsize,
+    }
+
+    impl CountingHandler {
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    impl tc_ir::HandleGet<FakeTxn> for CountingHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let calls = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(Box::pin(async move {
+                Ok(Value::String(format!("{request:?}#{calls}")))
+            }))
+        }
+    }
+
+    fn get(handler: &CountingHandler, route: &'static str, body: &str) -> Value {
+        get_as(handler, &txn_header_bytes(), route, body).expect("cached response")
+    }
+
+    fn get_as(
+        handler: &CountingHandler,
+        header: &[u8],
+        route: &'static str,
+        body: &str,
+    ) -> TCResult<Value> {
+        let response = try_dispatch_get_cached_bytes::<_, FakeTxn, Value, Value>(
+            route,
+            handler,
+            header,
+            body.as_bytes(),
+        )?;
+
+        Value::decode(&response)
+    }
+
+    #[test]
+    fn caches_by_route_and_request() {
+        let handler = CountingHandler::default();
+
+        let first = get(&handler, "/a", r#""x""#);
+        assert_eq!(get(&handler, "/a", r#""x""#), first);
+        assert_eq!(handler.calls(), 1);
+
+        assert_ne!(get(&handler, "/a", r#""y""#), first);
+        assert_ne!(get(&handler, "/b", r#""x""#), first);
+        assert_eq!(handler.calls(), 3);
+
+        reset_response_cache();
+        assert_ne!(get(&handler, "/a", r#""x""#), first);
+        assert_eq!(handler.calls(), 4);
+    }
+
+    #[test]
+    fn checks_header_and_claim_before_serving_from_cache() {
+        let handler = CountingHandler::default();
+        let first = get(&handler, "/claims", r#""x""#);
+
+        let reader = claim::read_only(Link::from_str("/lib").expect("link"));
+        let other = get_as(&handler, &txn_header_bytes_for(reader), "/claims", r#""x""#)
+            .expect("other claim");
+
+        assert_ne!(other, first);
+        assert_eq!(handler.calls(), 2);
+
+        assert!(get_as(&handler, &[], "/claims", r#""x""#).is_err());
+        assert_eq!(handler.calls(), 2);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let handler = CountingHandler::default();
+
+        set_response_cache_capacity(2);
+
+        get(&handler, "/a", "1");
+        get(&handler, "/a", "2");
+        get(&handler, "/a", "1");
+        get(&handler, "/a", "3");
+        assert_eq!(handler.calls(), 3);
+
+        get(&handler, "/a", "1");
+        assert_eq!(handler.calls(), 3);
+
+        get(&handler, "/a", "2");
+        assert_eq!(handler.calls(), 4);
+
+        set_response_cache_capacity(DEFAULT_RESPONSE_CACHE_CAPACITY);
+    }
+}
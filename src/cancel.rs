@@ -0,0 +1,165 @@
+//! Cooperative cancellation of in-flight handlers.
+//!
+//! WASM can't be preempted, so when a client disconnects the host raises a flag which
+//! long-running handlers poll (via [`CancellationToken::check`]) between units of work.
+
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, check_deadline, decode_header_bytes,
+    dispatch_get_decoded, respond,
+};
+use crate::codec::request_body;
+use crate::host;
+
+/// A handle on the host's cancellation flag for the current call.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CancellationToken;
+
+impl CancellationToken {
+    /// Whether the host has asked for the current call to be cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        host::is_cancelled()
+    }
+
+    /// Return a cancellation error if the host has asked for the current call to be cancelled,
+    /// or a timeout error if the route has used up its time budget (see [`check_deadline`]).
+    pub fn check(&self) -> TCResult<()> {
+        if self.is_cancelled() {
+            Err(TCError::cancelled("request cancelled by the host"))
+        } else {
+            check_deadline()
+        }
+    }
+}
+
+/// A request paired with the [`CancellationToken`] for its call.
+pub struct Cancellable<Req> {
+    pub token: CancellationToken,
+    pub request: Req,
+}
+
+/// Like [`crate::dispatch_get`], but also passes a [`CancellationToken`] to the handler.
+pub fn dispatch_get_cancellable<H, Txn, Req, Res>(
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<
+            Txn,
+            Request = Cancellable<Req>,
+            RequestContext = (),
+            Response = Res,
+            Error = TCError,
+        >,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_cancellable_bytes(
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and body, passing a [`CancellationToken`] to the handler.
+pub fn try_dispatch_get_cancellable_bytes<H, Txn, Req, Res>(
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<
+            Txn,
+            Request = Cancellable<Req>,
+            RequestContext = (),
+            Response = Res,
+            Error = TCError,
+        >,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let header = decode_header_bytes(header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+    let token = CancellationToken;
+    token.check()?;
+
+    let request = Req::decode(&request_body(body_bytes)?)?;
+    dispatch_get_decoded(handler, &txn, Cancellable { token, request })?.encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tc_value::Value;
+
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+    use crate::testing::MockHost;
+
+    /// Counts to the requested number, checking for cancellation before each step.
+    #[derive(Default)]
+    struct CountHandler {
+        counted: AtomicU64,
+    }
+
+    impl tc_ir::HandleGet<FakeTxn> for CountHandler {
+        type Request = Cancellable<u64>;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            Ok(Box::pin(async move {
+                for _ in 0..request.request {
+                    request.token.check()?;
+                    self.counted.fetch_add(1, Ordering::SeqCst);
+                }
+
+                Ok(Value::from(self.counted.load(Ordering::SeqCst)))
+            }))
+        }
+    }
+
+    fn count(handler: &CountHandler, to: u64) -> TCResult<Vec<u8>> {
+        try_dispatch_get_cancellable_bytes::<_, FakeTxn, u64, Value>(
+            handler,
+            &txn_header_bytes(),
+            to.to_string().as_bytes(),
+        )
+    }
+
+    #[test]
+    fn completes_when_not_cancelled() {
+        let handler = CountHandler::default();
+        let response = count(&handler, 10).expect("count");
+        assert_eq!(
+            Value::decode(&response).expect("decode"),
+            Value::from(10u64)
+        );
+        assert!(!host::is_cancelled());
+    }
+
+    #[test]
+    fn handler_honors_cancellation() {
+        // the dispatch checks once, then the handler checks before each step
+        let mock = MockHost::new().with_cancellation_after(4);
+        let _guard = mock.install();
+
+        let handler = CountHandler::default();
+        assert!(count(&handler, 10).is_err());
+        assert_eq!(handler.counted.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,163 @@
+//! Chains of dependent `OpRef`s for the host to execute in order, so a handler can hand off
+//! e.g. "look up the user, then call another library with the result" in one response.
+//!
+//! Each step is named, and its result is bound to that name for the rest of the chain: a
+//! later step refers to it with the string `"$<name>"` (see [`OpChain::result_of`]) anywhere
+//! in its arguments, which the host replaces with the result before executing the step. The
+//! result of the chain is the result of its last step.
+
+use destream::en::{self, EncodeMap};
+use std::collections::BTreeSet;
+use tc_error::{TCError, TCResult};
+use tc_ir::OpRef;
+use tc_value::Value;
+
+use crate::abi::{WasmResponse, encode_json_bytes};
+
+/// A response which asks the host to execute a sequence of `OpRef`s, encoded as the frame
+/// `{"$frame": "op_chain", "steps": [{"name": "<name>", "op": <OpRef>}, ...]}`.
+#[derive(Clone, Default)]
+pub struct OpChain {
+    steps: Vec<(String, OpRef)>,
+}
+
+impl OpChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a step which executes `op` and binds its result to `name`.
+    pub fn then(mut self, name: impl Into<String>, op: OpRef) -> Self {
+        self.steps.push((name.into(), op));
+        self
+    }
+
+    /// The placeholder for the result of the earlier step `name`, to use as (or within) the
+    /// arguments of a later step.
+    pub fn result_of(name: &str) -> Value {
+        Value::String(format!("${name}"))
+    }
+
+    /// The names of this chain's steps, in execution order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.steps.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Check that the chain has at least one step and that its step names are unique,
+    /// non-empty identifiers.
+    pub fn validate(&self) -> TCResult<()> {
+        if self.steps.is_empty() {
+            return Err(TCError::internal("an op chain must have at least one step"));
+        }
+
+        let mut names = BTreeSet::new();
+        for name in self.names() {
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+            if !valid {
+                return Err(TCError::internal(format!(
+                    "invalid op chain step name {name:?}"
+                )));
+            }
+
+            if !names.insert(name) {
+                return Err(TCError::internal(format!(
+                    "duplicate op chain step name {name}"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct ChainStep {
+    name: String,
+    op: OpRef,
+}
+
+impl<'en> en::IntoStream<'en> for ChainStep {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(2))?;
+        map.encode_entry("name", self.name)?;
+        map.encode_entry("op", self.op)?;
+        map.end()
+    }
+}
+
+impl<'en> en::IntoStream<'en> for OpChain {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let steps = self
+            .steps
+            .into_iter()
+            .map(|(name, op)| ChainStep { name, op })
+            .collect::<Vec<_>>();
+
+        let mut map = encoder.encode_map(Some(2))?;
+        map.encode_entry("$frame", "op_chain")?;
+        map.encode_entry("steps", steps)?;
+        map.end()
+    }
+}
+
+impl WasmResponse for OpChain {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        self.validate()?;
+        encode_json_bytes(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pathlink::Link;
+    use std::str::FromStr;
+    use tc_ir::{Scalar, Subject};
+
+    fn get(path: &str, key: Value) -> OpRef {
+        let link = Link::from_str(path).expect("link");
+        OpRef::Get((Subject::Link(link), Scalar::Value(key)))
+    }
+
+    #[test]
+    fn encodes_two_step_chain() {
+        let chain = OpChain::new()
+            .then(
+                "user",
+                get("/lib/example-devco/users/0.1.0/user", Value::from("ada")),
+            )
+            .then(
+                "greeting",
+                get(
+                    "/lib/example-devco/example/0.1.0/hello",
+                    OpChain::result_of("user"),
+                ),
+            );
+
+        assert_eq!(chain.names().collect::<Vec<_>>(), ["user", "greeting"]);
+
+        let frame = chain.encode().expect("chain");
+        let frame: serde_json::Value = serde_json::from_slice(&frame).expect("frame");
+        assert_eq!(frame["$frame"], "op_chain");
+
+        let steps = frame["steps"].as_array().expect("steps");
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0]["name"], "user");
+        assert_eq!(steps[1]["name"], "greeting");
+        assert!(steps[1]["op"].to_string().contains("$user"), "{}", steps[1]);
+    }
+
+    #[test]
+    fn rejects_invalid_chains() {
+        assert!(OpChain::new().encode().is_err());
+
+        let op = get("/lib/example-devco/users/0.1.0/user", Value::from("ada"));
+        let duplicate = OpChain::new().then("user", op.clone()).then("user", op);
+        let err = duplicate.encode().expect_err("duplicate step");
+        assert!(err.to_string().contains("duplicate"), "{err}");
+    }
+}
@@ -0,0 +1,53 @@
+//! Constructors for a [`Claim`] with a meaningful [`Mode`], so handlers and tests state
+//! their intent instead of passing raw mode bits.
+
+use pathlink::Link;
+use tc_ir::Claim;
+use umask::Mode;
+
+/// Read permission for every class.
+pub const READ: u32 = 0o444;
+
+/// Write permission for every class.
+pub const WRITE: u32 = 0o222;
+
+/// Read and write permission for every class.
+pub const READ_WRITE: u32 = READ | WRITE;
+
+/// A claim which may only read `link`.
+pub fn read_only(link: Link) -> Claim {
+    Claim::new(link, Mode::from(READ))
+}
+
+/// A claim which may read and write `link`.
+pub fn read_write(link: Link) -> Claim {
+    Claim::new(link, Mode::from(READ_WRITE))
+}
+
+/// A claim with every permission on `link`.
+pub fn full(link: Link) -> Claim {
+    Claim::new(link, Mode::all())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    fn link() -> Link {
+        Link::from_str("/lib/example-devco/example/0.1.0").expect("link")
+    }
+
+    #[test]
+    fn constructors_set_mode() {
+        assert_eq!(read_only(link()).mode(), Mode::from(0o444));
+        assert_eq!(read_write(link()).mode(), Mode::from(0o666));
+        assert_eq!(full(link()).mode(), Mode::all());
+    }
+
+    #[test]
+    fn constructors_keep_link() {
+        assert_eq!(read_only(link()).link(), &link());
+    }
+}
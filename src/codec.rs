@@ -0,0 +1,210 @@
+//! The body encodings a host may select per call, via the transaction header's optional
+//! `"codec"` field, e.g. `{"codec": {"request": "text", "response": "json"}}`.
+//!
+//! The codecs are parsed with the header and apply until the call's response is sent: the
+//! dispatch functions read the body via [`request_body`], which re-encodes a `text` body as
+//! a JSON string, and the response is encoded in the response codec before it's returned.
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::fmt;
+use std::str::FromStr;
+
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{contains, decode_serde_json, encode_json_bytes};
+
+thread_local! {
+    static CALL_CODECS: Cell<(Codec, Codec)> = const { Cell::new((Codec::Json, Codec::Json)) };
+}
+
+/// A body encoding.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Codec {
+    /// JSON, the default in both directions.
+    Json,
+    /// A raw UTF-8 string, decoded by the `String` request fallback.
+    Text,
+}
+
+impl FromStr for Codec {
+    type Err = TCError;
+
+    fn from_str(name: &str) -> TCResult<Self> {
+        match name {
+            "json" => Ok(Self::Json),
+            "text" => Ok(Self::Text),
+            other => Err(TCError::bad_request(format!("unknown codec: {other}"))),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Json => "json",
+            Self::Text => "text",
+        })
+    }
+}
+
+impl Codec {
+    /// The MIME type of a body in this encoding.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Text => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+/// Every supported `(request, response)` codec combination.
+pub const SUPPORTED_CODECS: &[(Codec, Codec)] =
+    &[(Codec::Json, Codec::Json), (Codec::Text, Codec::Json)];
+
+const CODEC_KEY: &[u8] = b"\"codec\"";
+
+/// The `(request, response)` codecs of a header without a `codec` field.
+const DEFAULT_CODECS: (Codec, Codec) = (Codec::Json, Codec::Json);
+
+/// Validate and remove the `codec` field (if any) so the rest of the header decodes as a
+/// `TxnHeader`, and return the requested `(request, response)` codecs. Runs before the body
+/// is read, so an unsupported pair fails cleanly.
+pub(crate) fn strip_codecs(header_bytes: &[u8]) -> TCResult<(Cow<'_, [u8]>, (Codec, Codec))> {
+    if !contains(header_bytes, CODEC_KEY) {
+        return Ok((Cow::Borrowed(header_bytes), DEFAULT_CODECS));
+    }
+
+    let mut header: serde_json::Value = decode_serde_json(header_bytes)?;
+    let Some(codecs) = header
+        .as_object_mut()
+        .and_then(|header| header.remove("codec"))
+    else {
+        return Ok((Cow::Borrowed(header_bytes), DEFAULT_CODECS));
+    };
+
+    let codec = |direction: &str| match codecs.get(direction) {
+        None | Some(serde_json::Value::Null) => Some(Codec::Json),
+        Some(serde_json::Value::String(name)) => name.parse().ok(),
+        Some(_) => None,
+    };
+
+    let codecs = match (codec("request"), codec("response")) {
+        (Some(request), Some(response)) if SUPPORTED_CODECS.contains(&(request, response)) => {
+            (request, response)
+        }
+        _ => return Err(TCError::bad_request("unsupported codec combination")),
+    };
+
+    let header = serde_json::to_vec(&header).map_err(TCError::bad_request)?;
+    Ok((Cow::Owned(header), codecs))
+}
+
+/// Use `codecs` for the body and response of the call being dispatched.
+pub(crate) fn begin_call_codecs(codecs: (Codec, Codec)) {
+    CALL_CODECS.with(|current| current.set(codecs));
+}
+
+/// The codec the response of the call being dispatched will be encoded in.
+pub(crate) fn response_codec() -> Codec {
+    CALL_CODECS.with(Cell::get).1
+}
+
+/// The body of the call being dispatched, as JSON: a `text` body becomes a JSON string.
+pub(crate) fn request_body(body: &[u8]) -> TCResult<Cow<'_, [u8]>> {
+    match CALL_CODECS.with(Cell::get).0 {
+        Codec::Json => Ok(Cow::Borrowed(body)),
+        Codec::Text => {
+            let text = std::str::from_utf8(body)
+                .map_err(|err| TCError::bad_request(format!("invalid utf-8 text body: {err}")))?;
+
+            encode_json_bytes(text).map(Cow::Owned)
+        }
+    }
+}
+
+/// Encode the JSON `response` of the call being dispatched in its response codec, and reset
+/// the codecs for the next call. A `text` response must be a JSON string.
+pub(crate) fn encode_response(response: Vec<u8>) -> TCResult<Vec<u8>> {
+    match CALL_CODECS
+        .with(|current| current.replace(DEFAULT_CODECS))
+        .1
+    {
+        Codec::Json => Ok(response),
+        Codec::Text => serde_json::from_slice::<String>(&response)
+            .map(String::into_bytes)
+            .map_err(|_| TCError::internal("a text response must be a string")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+
+    use crate::abi::{WasmRequest, decode_header_bytes, try_dispatch_get_bytes};
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+
+    struct EchoHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for EchoHandler {
+        type Request = String;
+        type RequestContext = ();
+        type Response = String;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            Ok(Box::pin(async move { Ok(request) }))
+        }
+    }
+
+    fn header_with_codecs(request: &str, response: &str) -> Vec<u8> {
+        let mut header: serde_json::Value =
+            serde_json::from_slice(&txn_header_bytes()).expect("header json");
+
+        header.as_object_mut().expect("header map").insert(
+            "codec".to_string(),
+            serde_json::json!({ "request": request, "response": response }),
+        );
+
+        serde_json::to_vec(&header).expect("header json")
+    }
+
+    fn get(header: &[u8], body: &[u8]) -> TCResult<Vec<u8>> {
+        try_dispatch_get_bytes::<_, FakeTxn, String, String>(&EchoHandler, header, body)
+    }
+
+    #[test]
+    fn accepts_supported_codecs() {
+        for (request, response) in SUPPORTED_CODECS {
+            let header = header_with_codecs(&request.to_string(), &response.to_string());
+            assert!(decode_header_bytes(&header).is_ok());
+        }
+
+        let header = header_with_codecs("text", "json");
+        let response = get(&header, b"plain text").expect("text request");
+        assert_eq!(String::decode(&response).expect("decode"), "plain text");
+
+        // a text body is never parsed as JSON, even when it would be valid JSON
+        let response = get(&header, br#""quoted""#).expect("text request");
+        assert_eq!(String::decode(&response).expect("decode"), r#""quoted""#);
+
+        let response = get(&txn_header_bytes(), br#""quoted""#).expect("json request");
+        assert_eq!(String::decode(&response).expect("decode"), "quoted");
+    }
+
+    #[test]
+    fn rejects_unsupported_codecs() {
+        for (request, response) in [("binary", "json"), ("json", "text"), ("json", "cbor")] {
+            let header = header_with_codecs(request, response);
+
+            // the body is never decoded, so even an invalid one yields the codec error
+            let err = get(&header, b"{not json").expect_err("unsupported codecs");
+            assert!(err.to_string().contains("unsupported codec combination"));
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Conditional GET: a client which already holds a response sends its hash, and the handler
+//! answers "not modified" instead of re-sending an unchanged body.
+
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, decode_header_bytes,
+    dispatch_get_decoded, respond, take_header_field,
+};
+use crate::codec::request_body;
+
+/// A request paired with the `if_none_match` hash (if any) from its header, typically the
+/// [`crate::content_hash`] of a response the client received earlier.
+pub struct ConditionalRequest<Req> {
+    pub if_none_match: Option<String>,
+    pub request: Req,
+}
+
+impl<Req> ConditionalRequest<Req> {
+    /// Whether the client already holds the response with the given `hash`.
+    pub fn is_match(&self, hash: &str) -> bool {
+        self.if_none_match.as_deref() == Some(hash)
+    }
+}
+
+/// The response to a [`ConditionalRequest`]: either a body, encoded as-is, or the frame
+/// `{"$frame": "not_modified"}` telling the host the client's copy is still current.
+pub enum Conditional<T> {
+    Modified(T),
+    NotModified,
+}
+
+impl<T: WasmResponse> WasmResponse for Conditional<T> {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        match self {
+            Self::Modified(body) => body.encode(),
+            Self::NotModified => Ok(br#"{"$frame":"not_modified"}"#.to_vec()),
+        }
+    }
+}
+
+/// Like [`crate::dispatch_get`], but passes the header's `if_none_match` hash to the handler.
+pub fn dispatch_get_conditional<H, Txn, Req, Res>(
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<
+            Txn,
+            Request = ConditionalRequest<Req>,
+            RequestContext = (),
+            Response = Conditional<Res>,
+            Error = TCError,
+        >,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_conditional_bytes(
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and body, passing the header's `if_none_match` hash to
+/// the handler.
+pub fn try_dispatch_get_conditional_bytes<H, Txn, Req, Res>(
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<
+            Txn,
+            Request = ConditionalRequest<Req>,
+            RequestContext = (),
+            Response = Conditional<Res>,
+            Error = TCError,
+        >,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let (if_none_match, header_bytes) = take_header_field(header_bytes, "if_none_match")?;
+    let header = decode_header_bytes(&header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+    let request = Req::decode(&request_body(body_bytes)?)?;
+
+    let request = ConditionalRequest {
+        if_none_match,
+        request,
+    };
+
+    dispatch_get_decoded(handler, &txn, request)?.encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+    use tc_value::Value;
+
+    use crate::abi::canonical_json_bytes;
+    use crate::response::content_hash;
+    use crate::test_support::{FakeTxn, txn_header_bytes, txn_header_bytes_with_field};
+
+    const DOCUMENT: &str = "revision 2";
+
+    fn document_hash() -> String {
+        content_hash(&canonical_json_bytes(DOCUMENT.to_string()).expect("canonical json"))
+    }
+
+    struct DocumentHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for DocumentHandler {
+        type Request = ConditionalRequest<Value>;
+        type RequestContext = ();
+        type Response = Conditional<String>;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let response = if request.is_match(&document_hash()) {
+                Conditional::NotModified
+            } else {
+                Conditional::Modified(DOCUMENT.to_string())
+            };
+
+            Ok(Box::pin(async move { Ok(response) }))
+        }
+    }
+
+    fn get(if_none_match: &str) -> serde_json::Value {
+        let header = txn_header_bytes_with_field("if_none_match", if_none_match);
+
+        let response = try_dispatch_get_conditional_bytes::<_, FakeTxn, Value, String>(
+            &DocumentHandler,
+            &header,
+            &[],
+        )
+        .expect("response");
+
+        serde_json::from_slice(&response).expect("response json")
+    }
+
+    #[test]
+    fn answers_not_modified_for_matching_hash() {
+        assert_eq!(
+            get(&document_hash()),
+            serde_json::json!({"$frame": "not_modified"})
+        );
+
+        assert_eq!(get("stale"), serde_json::json!(DOCUMENT));
+
+        let response = try_dispatch_get_conditional_bytes::<_, FakeTxn, Value, String>(
+            &DocumentHandler,
+            &txn_header_bytes(),
+            &[],
+        )
+        .expect("unconditional response");
+
+        assert_eq!(String::decode(&response).expect("body"), DOCUMENT);
+    }
+}
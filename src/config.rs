@@ -0,0 +1,124 @@
+//! Per-library configuration supplied by the host at mount time.
+//!
+//! By convention a library exports `init(config_ptr, config_len) -> i64`, which the host calls
+//! once with a JSON config before any dispatch:
+//!
+//! ```ignore
+//! static CONFIG: LibraryConfig<MyConfig> = LibraryConfig::new();
+//!
+//! #[unsafe(no_mangle)]
+//! pub extern "C" fn init(config_ptr: i32, config_len: i32) -> i64 {
+//!     CONFIG.init(config_ptr, config_len)
+//! }
+//! ```
+
+use serde::de::DeserializeOwned;
+use std::sync::OnceLock;
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{borrow_bytes, decode_serde_json, respond};
+
+/// A library's configuration, checked by [`Self::validate`] once it's decoded.
+pub trait InitConfig: DeserializeOwned {
+    fn validate(&self) -> TCResult<()> {
+        Ok(())
+    }
+}
+
+/// Decode and validate a JSON config. An empty config is decoded as `{}`.
+pub fn decode_init_config<T: InitConfig>(bytes: &[u8]) -> TCResult<T> {
+    let bytes = if bytes.is_empty() { &b"{}"[..] } else { bytes };
+    let config: T = decode_serde_json(bytes)?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// Holds a library's [`InitConfig`] in a `static`, for handlers to read via [`Self::get`].
+pub struct LibraryConfig<T> {
+    config: OnceLock<T>,
+}
+
+impl<T> LibraryConfig<T> {
+    pub const fn new() -> Self {
+        Self {
+            config: OnceLock::new(),
+        }
+    }
+
+    /// The config, or an internal error if the host hasn't called `init`.
+    pub fn get(&self) -> TCResult<&T> {
+        self.config
+            .get()
+            .ok_or_else(|| TCError::internal("library config is not initialized"))
+    }
+}
+
+impl<T: InitConfig> LibraryConfig<T> {
+    /// Implements the `init` export: returns `0` on success, or an error payload (as from a
+    /// dispatch) for the host to surface if the config is malformed or invalid.
+    pub fn init(&self, config_ptr: i32, config_len: i32) -> i64 {
+        let config_bytes = unsafe { borrow_bytes(config_ptr, config_len) };
+        respond(self.try_init(config_bytes).map(|()| Vec::new()))
+    }
+
+    /// Decode, validate, and store an already-read config. Fails if already initialized.
+    pub fn try_init(&self, config_bytes: &[u8]) -> TCResult<()> {
+        let config = decode_init_config(config_bytes)?;
+
+        self.config
+            .set(config)
+            .map_err(|_| TCError::bad_request("library config is already initialized"))
+    }
+}
+
+impl<T> Default for LibraryConfig<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Config {
+        endpoint: String,
+        #[serde(default)]
+        beta: bool,
+    }
+
+    impl InitConfig for Config {
+        fn validate(&self) -> TCResult<()> {
+            if self.endpoint.starts_with('/') {
+                Ok(())
+            } else {
+                Err(TCError::bad_request("endpoint must be an absolute path"))
+            }
+        }
+    }
+
+    static CONFIG: LibraryConfig<Config> = LibraryConfig::new();
+
+    #[test]
+    fn initializes_and_reads_config() {
+        assert!(CONFIG.get().is_err());
+
+        assert!(CONFIG.try_init(b"{").is_err());
+        assert!(CONFIG.try_init(b"{}").is_err());
+        assert!(CONFIG.try_init(br#"{"endpoint": "lib/other"}"#).is_err());
+
+        CONFIG
+            .try_init(br#"{"endpoint": "/lib/other", "beta": true}"#)
+            .expect("init");
+
+        let config = CONFIG.get().expect("config");
+        assert_eq!(config.endpoint, "/lib/other");
+        assert!(config.beta);
+
+        assert!(CONFIG.try_init(br#"{"endpoint": "/lib/again"}"#).is_err());
+        assert_eq!(CONFIG.get().expect("config").endpoint, "/lib/other");
+    }
+}
@@ -0,0 +1,191 @@
+//! Conversions from a request [`Value`] to common Rust types, with descriptive errors.
+
+use tc_error::{TCError, TCResult};
+use tc_value::{Float, Int, Number, UInt, Value};
+
+/// A type which can be extracted from a [`Value`], failing with a bad request error which
+/// names the expected type and the value actually found.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> TCResult<Self>;
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> TCResult<Self> {
+        Ok(value)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> TCResult<Self> {
+        match value {
+            Value::String(string) => Ok(string.to_string()),
+            other => Err(mismatch("a string", &other)),
+        }
+    }
+}
+
+impl FromValue for u64 {
+    fn from_value(value: Value) -> TCResult<Self> {
+        integer(&value)
+            .and_then(|n| u64::try_from(n).ok())
+            .ok_or_else(|| mismatch("an unsigned integer", &value))
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: Value) -> TCResult<Self> {
+        integer(&value)
+            .and_then(|n| i64::try_from(n).ok())
+            .ok_or_else(|| mismatch("a 64-bit integer", &value))
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> TCResult<Self> {
+        match &value {
+            Value::Number(Number::Float(Float::F32(n))) => Ok(*n as f64),
+            Value::Number(Number::Float(Float::F64(n))) => Ok(*n),
+            _ => integer(&value)
+                .map(|n| n as f64)
+                .ok_or_else(|| mismatch("a number", &value)),
+        }
+    }
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(value: Value) -> TCResult<Self> {
+        match value {
+            Value::Bytes(bytes) => Ok(bytes.to_vec()),
+            other => Err(mismatch("bytes", &other)),
+        }
+    }
+}
+
+/// `Value::None` converts to `None`; anything else must convert to `T`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> TCResult<Self> {
+        match value {
+            Value::None => Ok(None),
+            other => T::from_value(other).map(Some),
+        }
+    }
+}
+
+/// A tuple converts element-wise; the error names the index of the first mismatch.
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> TCResult<Self> {
+        let Value::Tuple(items) = value else {
+            return Err(mismatch("a tuple", &value));
+        };
+
+        items
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| {
+                T::from_value(item).map_err(|err| TCError::bad_request(format!("[{i}]: {err}")))
+            })
+            .collect()
+    }
+}
+
+/// Shorthand for [`FromValue`] conversions, e.g. `request.try_into_string()?`.
+pub trait ValueExt: Sized {
+    fn try_into_type<T: FromValue>(self) -> TCResult<T>;
+
+    fn try_into_string(self) -> TCResult<String> {
+        self.try_into_type()
+    }
+
+    fn try_into_u64(self) -> TCResult<u64> {
+        self.try_into_type()
+    }
+
+    fn try_into_i64(self) -> TCResult<i64> {
+        self.try_into_type()
+    }
+
+    fn try_into_f64(self) -> TCResult<f64> {
+        self.try_into_type()
+    }
+
+    fn try_into_bytes(self) -> TCResult<Vec<u8>> {
+        self.try_into_type()
+    }
+}
+
+impl ValueExt for Value {
+    fn try_into_type<T: FromValue>(self) -> TCResult<T> {
+        T::from_value(self)
+    }
+}
+
+fn integer(value: &Value) -> Option<i128> {
+    match value {
+        Value::Number(Number::UInt(n)) => match n {
+            UInt::U8(n) => Some(*n as i128),
+            UInt::U16(n) => Some(*n as i128),
+            UInt::U32(n) => Some(*n as i128),
+            UInt::U64(n) => Some(*n as i128),
+        },
+        Value::Number(Number::Int(n)) => match n {
+            Int::I8(n) => Some(*n as i128),
+            Int::I16(n) => Some(*n as i128),
+            Int::I32(n) => Some(*n as i128),
+            Int::I64(n) => Some(*n as i128),
+        },
+        _ => None,
+    }
+}
+
+fn mismatch(expected: &str, found: &Value) -> TCError {
+    TCError::bad_request(format!("expected {expected} but found {found:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_matching_values() {
+        assert_eq!(Value::from("Ada").try_into_string().expect("string"), "Ada");
+        assert_eq!(Value::from(42u64).try_into_u64().expect("u64"), 42);
+        assert_eq!(Value::from(-42i64).try_into_i64().expect("i64"), -42);
+        assert_eq!(Value::from(7u64).try_into_i64().expect("i64"), 7);
+        assert_eq!(Value::from(1.5f64).try_into_f64().expect("f64"), 1.5);
+        assert_eq!(Value::from(2u64).try_into_f64().expect("f64"), 2.);
+
+        let bytes = Value::Bytes(vec![1, 2, 3].into());
+        assert_eq!(bytes.try_into_bytes().expect("bytes"), vec![1, 2, 3]);
+
+        let tuple = Value::Tuple(vec![Value::from(1u64), Value::from(2u64)].into());
+        assert_eq!(tuple.try_into_type::<Vec<u64>>().expect("list"), vec![1, 2]);
+
+        assert_eq!(
+            Value::None.try_into_type::<Option<String>>().expect("none"),
+            None
+        );
+    }
+
+    #[test]
+    fn reports_type_mismatches() {
+        let err = Value::from(42u64)
+            .try_into_string()
+            .expect_err("not a string");
+        assert!(err.to_string().contains("expected a string but found"));
+
+        let err = Value::from(-1i64).try_into_u64().expect_err("negative");
+        assert!(err.to_string().contains("expected an unsigned integer"));
+
+        let err = Value::from(1.5f64)
+            .try_into_i64()
+            .expect_err("not an integer");
+        assert!(err.to_string().contains("expected a 64-bit integer"));
+
+        assert!(Value::from("1").try_into_f64().is_err());
+        assert!(Value::None.try_into_bytes().is_err());
+
+        let tuple = Value::Tuple(vec![Value::from(1u64), Value::from("two")].into());
+        let err = tuple.try_into_type::<Vec<u64>>().expect_err("mixed tuple");
+        assert!(err.to_string().contains("[1]: "));
+    }
+}
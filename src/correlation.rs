@@ -0,0 +1,126 @@
+//! Opt-in correlation of responses with their transactions, so a client pipelining requests
+//! (or a log of responses) can match each response to the request which produced it.
+//!
+//! Once enabled via [`set_txn_id_envelope`], every export which decodes a transaction header
+//! wraps its response in the frame `{"$frame": "correlated", "txn_id": <TxnId>, "body": ...}`,
+//! where `txn_id` is the id from the request's (already decoded) header, encoded as in the
+//! header. Other frames are wrapped too, so the host unwraps this one first. Errors are sent
+//! as-is.
+
+use std::cell::{Cell, RefCell};
+use tc_error::TCResult;
+use tc_ir::TxnHeader;
+
+use crate::abi::encode_json_bytes;
+
+thread_local! {
+    static TXN_ID_ENVELOPE: Cell<bool> = const { Cell::new(false) };
+    static CALL_TXN_ID: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+/// Tag each response with its transaction id in a `correlated` frame. Disabled by default,
+/// to keep payloads minimal for clients which don't pipeline requests.
+pub fn set_txn_id_envelope(enabled: bool) {
+    TXN_ID_ENVELOPE.with(|current| current.set(enabled));
+}
+
+/// Remember the encoded id of the transaction `header` describes, if responses are to be
+/// tagged, so the response of the call being dispatched can be.
+pub(crate) fn begin_call_txn_id(header: &TxnHeader) -> TCResult<()> {
+    let txn_id = if TXN_ID_ENVELOPE.with(Cell::get) {
+        Some(encode_json_bytes(header.id())?)
+    } else {
+        None
+    };
+
+    CALL_TXN_ID.with(|current| current.replace(txn_id));
+    Ok(())
+}
+
+/// Take the encoded id the response of the call being dispatched is to be tagged with.
+pub(crate) fn take_call_txn_id() -> Option<Vec<u8>> {
+    CALL_TXN_ID.with(RefCell::take)
+}
+
+/// Wrap an encoded JSON response in a `correlated` frame, if it was tagged.
+pub(crate) fn with_txn_id(txn_id: Option<Vec<u8>>, body: Vec<u8>) -> Vec<u8> {
+    let Some(txn_id) = txn_id else {
+        return body;
+    };
+
+    let prefix = br#"{"$frame":"correlated","txn_id":"#;
+
+    let mut framed = Vec::with_capacity(prefix.len() + txn_id.len() + body.len() + 9);
+    framed.extend_from_slice(prefix);
+    framed.extend_from_slice(&txn_id);
+    framed.extend_from_slice(br#","body":"#);
+    framed.extend_from_slice(&body);
+    framed.push(b'}');
+    framed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tc_value::Value;
+
+    use crate::abi::{WasmRequest, decode_header_bytes, response_bytes, try_dispatch_get_bytes};
+    use crate::cache::try_dispatch_get_cached_bytes;
+    use crate::test_support::{FakeTxn, GreetHandler, txn_header_bytes};
+
+    fn greet() -> Vec<u8> {
+        response_bytes(try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(
+            &GreetHandler,
+            &txn_header_bytes(),
+            &[],
+        ))
+    }
+
+    #[test]
+    fn tags_response_with_txn_id() {
+        set_txn_id_envelope(true);
+        let response = greet();
+        set_txn_id_envelope(false);
+
+        let header = decode_header_bytes(&txn_header_bytes()).expect("header");
+        let txn_id = encode_json_bytes(header.id()).expect("txn id");
+        let txn_id: serde_json::Value = serde_json::from_slice(&txn_id).expect("txn id json");
+
+        let frame: serde_json::Value = serde_json::from_slice(&response).expect("frame");
+        assert_eq!(frame["$frame"], "correlated");
+        assert_eq!(frame["txn_id"], txn_id);
+        assert_eq!(frame["body"], "hello");
+
+        let response = greet();
+        assert_eq!(
+            Value::decode(&response).expect("plain"),
+            Value::from("hello")
+        );
+    }
+
+    #[test]
+    fn tags_responses_of_other_dispatchers() {
+        set_txn_id_envelope(true);
+        let cached = response_bytes(try_dispatch_get_cached_bytes::<_, FakeTxn, Value, Value>(
+            "/greet",
+            &GreetHandler,
+            &txn_header_bytes(),
+            &[],
+        ));
+        let failed = response_bytes(try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(
+            &GreetHandler,
+            &[],
+            &[],
+        ));
+        set_txn_id_envelope(false);
+
+        let frame: serde_json::Value = serde_json::from_slice(&cached).expect("frame");
+        assert_eq!(frame["$frame"], "correlated");
+        assert_eq!(frame["body"], "hello");
+
+        // errors are sent as-is
+        let error: serde_json::Value = serde_json::from_slice(&failed).expect("error");
+        assert!(error.get("$frame").is_none(), "{error}");
+    }
+}
@@ -0,0 +1,204 @@
+//! Build and configuration info for debugging a deployed library.
+//!
+//! By convention a library exports `tc_diagnostics() -> i64`, which takes no header and may
+//! be called at any time:
+//!
+//! ```ignore
+//! #[unsafe(no_mangle)]
+//! pub extern "C" fn tc_diagnostics() -> i64 {
+//!     tc_wasm::diagnostics()
+//! }
+//! ```
+//!
+//! A library which declares the host imports its routes call (see
+//! [`RouteExport::with_imports`]) calls [`diagnostics_for`] with its routes instead, so that
+//! the host can check it provides each of them before instantiating the library.
+
+use destream::en::{self, EncodeMap};
+use std::collections::BTreeMap;
+use tc_error::TCResult;
+
+use crate::abi::{ABI_VERSION, RouteExport, encode_json_bytes, respond, uses_host_clock};
+use crate::codec::{Codec, SUPPORTED_CODECS};
+use crate::host::HostImport;
+
+/// The version of this crate the library was built with.
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The names of the crate features enabled in this build.
+pub fn enabled_features() -> Vec<&'static str> {
+    [
+        ("alloc-limit", cfg!(feature = "alloc-limit")),
+        ("bump-alloc", cfg!(feature = "bump-alloc")),
+        ("debug-print", cfg!(feature = "debug-print")),
+        ("json5", cfg!(feature = "json5")),
+        ("leak-tracking", cfg!(feature = "leak-tracking")),
+        ("log-redacted-errors", cfg!(feature = "log-redacted-errors")),
+        ("minimal-executor", cfg!(feature = "minimal-executor")),
+        ("progress", cfg!(feature = "progress")),
+        ("simd", cfg!(feature = "simd")),
+        ("testing", cfg!(feature = "testing")),
+        ("tracing", cfg!(feature = "tracing")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect()
+}
+
+/// The host imports every library built with this configuration needs, whatever its routes
+/// declare: `tc_now` once a setting which reads the host's clock is enabled (server timing, a
+/// maximum request age or a default timeout), and `tc_log` with the `log-redacted-errors`
+/// feature.
+pub fn required_imports() -> Vec<HostImport> {
+    let mut imports = Vec::new();
+
+    if uses_host_clock() {
+        imports.push(HostImport::Now);
+    }
+
+    if cfg!(feature = "log-redacted-errors") {
+        imports.push(HostImport::Log);
+    }
+
+    imports
+}
+
+/// The names of the host imports `route` needs: those it declares, `tc_now` if it has a time
+/// budget, plus [`required_imports`].
+fn route_import_names(route: &RouteExport) -> Vec<&'static str> {
+    let mut names = route.import_names();
+    if route.timeout().is_some() {
+        names.push(HostImport::Now.name());
+    }
+    names.extend(required_imports().into_iter().map(|import| import.name()));
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+struct Diagnostics<'a> {
+    routes: &'a [RouteExport],
+}
+
+impl<'en> en::IntoStream<'en> for Diagnostics<'_> {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let codecs = SUPPORTED_CODECS
+            .iter()
+            .map(|(request, response)| CodecPair(*request, *response))
+            .collect::<Vec<_>>();
+
+        let imports = self
+            .routes
+            .iter()
+            .map(|route| (route.export, route_import_names(route)))
+            .collect::<BTreeMap<_, _>>();
+
+        let required = required_imports()
+            .into_iter()
+            .map(|import| import.name())
+            .collect::<Vec<_>>();
+
+        let mut map = encoder.encode_map(Some(6))?;
+        map.encode_entry("abi_version", ABI_VERSION)?;
+        map.encode_entry("crate_version", CRATE_VERSION)?;
+        map.encode_entry("features", enabled_features())?;
+        map.encode_entry("codecs", codecs)?;
+        map.encode_entry("required_imports", required)?;
+        map.encode_entry("imports", imports)?;
+        map.end()
+    }
+}
+
+struct CodecPair(Codec, Codec);
+
+impl<'en> en::IntoStream<'en> for CodecPair {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(2))?;
+        map.encode_entry("request", self.0.to_string())?;
+        map.encode_entry("response", self.1.to_string())?;
+        map.end()
+    }
+}
+
+/// Encode `{"abi_version", "crate_version", "features", "codecs", "required_imports",
+/// "imports"}`, where `codecs` lists each supported `{"request", "response"}` codec pair and
+/// `required_imports` the names of [`required_imports`]. `imports` is empty since no routes
+/// are given; see [`diagnostics_for_bytes`].
+pub fn diagnostics_bytes() -> TCResult<Vec<u8>> {
+    diagnostics_for_bytes(&[])
+}
+
+/// Like [`diagnostics_bytes`], but `imports` maps the export name of each of `routes` to the
+/// sorted names of the host imports it declares plus the required imports, e.g.
+/// `{"hello": ["tc_log", "tc_now"]}`.
+pub fn diagnostics_for_bytes(routes: &[RouteExport]) -> TCResult<Vec<u8>> {
+    encode_json_bytes(Diagnostics { routes })
+}
+
+/// Implements the `tc_diagnostics` export.
+pub fn diagnostics() -> i64 {
+    respond(diagnostics_bytes())
+}
+
+/// Implements the `tc_diagnostics` export for a library with the given `routes`.
+pub fn diagnostics_for(routes: &[RouteExport]) -> i64 {
+    respond(diagnostics_for_bytes(routes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_abi_version_and_codecs() {
+        let diagnostics: serde_json::Value =
+            serde_json::from_slice(&diagnostics_bytes().expect("diagnostics")).expect("json");
+
+        assert_eq!(diagnostics["abi_version"], ABI_VERSION);
+        assert_eq!(diagnostics["crate_version"], CRATE_VERSION);
+        assert!(diagnostics["features"].is_array());
+
+        let codecs = diagnostics["codecs"].as_array().expect("codecs");
+        assert!(codecs.contains(&serde_json::json!({"request": "json", "response": "json"})));
+        assert_eq!(diagnostics["imports"], serde_json::json!({}));
+
+        let required = |diagnostics: &serde_json::Value| {
+            diagnostics["required_imports"]
+                .as_array()
+                .expect("required")
+                .contains(&serde_json::json!("tc_now"))
+        };
+        assert!(!required(&diagnostics));
+
+        crate::set_server_timing(true);
+        let timed: serde_json::Value =
+            serde_json::from_slice(&diagnostics_bytes().expect("diagnostics")).expect("json");
+        crate::set_server_timing(false);
+
+        assert!(required(&timed));
+    }
+
+    #[test]
+    fn lists_declared_host_imports() {
+        let routes = [
+            RouteExport::new("/hello", "hello").with_imports(&[HostImport::Log, HostImport::Now]),
+            RouteExport::new("/proxy", "proxy").with_imports(&[HostImport::Resolve]),
+            RouteExport::new("/static", "static_page"),
+            RouteExport::new("/report", "report").with_timeout_ms(500),
+        ];
+
+        let diagnostics: serde_json::Value =
+            serde_json::from_slice(&diagnostics_for_bytes(&routes).expect("diagnostics"))
+                .expect("json");
+
+        assert_eq!(
+            diagnostics["imports"],
+            serde_json::json!({
+                "hello": ["tc_log", "tc_now"],
+                "proxy": ["tc_resolve"],
+                "static_page": [],
+                "report": ["tc_now"],
+            })
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! The executor which drives handler futures to completion within a (blocking) export call.
+//!
+//! By default this is `futures::executor::block_on`. With the `minimal-executor` feature, a
+//! busy-polling loop ([`poll_to_completion`]) is used instead, so nothing calls the `futures`
+//! executor (or its thread-parking machinery) and the linker can drop it from
+//! code-size-sensitive builds. WASM is single-threaded, so a handler's future can only make
+//! progress by being polled anyway.
+
+use futures::{Future, Stream, StreamExt};
+use std::task::{Context, Poll, Waker};
+
+/// Run `future` to completion on the executor selected at build time.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    #[cfg(feature = "minimal-executor")]
+    return poll_to_completion(future);
+
+    #[cfg(not(feature = "minimal-executor"))]
+    futures::executor::block_on(future)
+}
+
+/// Iterate over the items of `stream`, blocking on each with [`block_on`].
+pub(crate) fn block_on_stream<S: Stream + Unpin>(mut stream: S) -> impl Iterator<Item = S::Item> {
+    std::iter::from_fn(move || block_on(stream.next()))
+}
+
+/// Poll `future` in a loop until it's ready, with a waker which does nothing.
+pub fn poll_to_completion<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+
+        std::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::pin::Pin;
+    use tc_error::{TCError, TCResult};
+    use tc_value::Value;
+
+    use crate::abi::{WasmRequest, dispatch_get_async, encode_json_bytes};
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+
+    /// Answers only after it's been polled once without making progress.
+    struct YieldingHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for YieldingHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Value) -> TCResult<Self::Fut<'a>> {
+            let mut yielded = false;
+            let ready = std::future::poll_fn(move |cx| {
+                if yielded {
+                    Poll::Ready(())
+                } else {
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            });
+
+            Ok(Box::pin(async move {
+                ready.await;
+                Ok(request)
+            }))
+        }
+    }
+
+    #[test]
+    fn minimal_poller_drives_dispatch() {
+        let body = encode_json_bytes(Value::from("echo")).expect("body");
+
+        let response = poll_to_completion(dispatch_get_async::<_, FakeTxn, Value, Value>(
+            &YieldingHandler,
+            &txn_header_bytes(),
+            &body,
+        ));
+
+        assert_eq!(
+            Value::decode(&response).expect("response"),
+            Value::from("echo")
+        );
+
+        let blocked = block_on(dispatch_get_async::<_, FakeTxn, Value, Value>(
+            &YieldingHandler,
+            &txn_header_bytes(),
+            &body,
+        ));
+
+        assert_eq!(blocked, response);
+    }
+
+    /// Run with `cargo test --features minimal-executor`, so the exports' own executor is
+    /// the poller.
+    #[cfg(feature = "minimal-executor")]
+    #[test]
+    fn minimal_executor_drives_exports() {
+        use crate::abi::try_dispatch_get_bytes;
+
+        let body = encode_json_bytes(Value::from("echo")).expect("body");
+
+        let response = try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(
+            &YieldingHandler,
+            &txn_header_bytes(),
+            &body,
+        )
+        .expect("response");
+
+        assert_eq!(
+            Value::decode(&response).expect("response"),
+            Value::from("echo")
+        );
+    }
+}
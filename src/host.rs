@@ -0,0 +1,357 @@
+//! Functions imported from the TinyChain host.
+//!
+//! On `wasm32` these call into the `tc_host` import module. Off-wasm (e.g. under
+//! `cargo test`) they fall back to simple in-process stubs, or to the installed
+//! [`crate::testing::MockHost`] when the `testing` feature is enabled.
+
+#[cfg(any(feature = "tracing", feature = "testing", test))]
+use std::cell::RefCell;
+use std::fmt;
+use tc_error::TCResult;
+use tc_ir::{NetworkTime, OpRef};
+use tc_value::Value;
+
+use crate::abi::{WasmRequest, encode_json_bytes};
+use crate::state::StateId;
+
+#[cfg(any(feature = "tracing", feature = "testing", test))]
+thread_local! {
+    static CALL_TRACE: RefCell<Vec<ResolvedOp>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(i32)]
+pub enum LogLevel {
+    Debug = 0,
+    Info = 1,
+    Warn = 2,
+    Error = 3,
+}
+
+/// A function imported from the `tc_host` module. A route declares the imports it calls via
+/// [`crate::RouteExport::with_imports`], so that the host can check it provides them before
+/// instantiating the library rather than failing to link.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum HostImport {
+    Log,
+    Now,
+    Progress,
+    Random,
+    Cancelled,
+    Resolve,
+    Store,
+    Load,
+}
+
+impl HostImport {
+    /// The name of this function in the `tc_host` import module.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Log => "tc_log",
+            Self::Now => "tc_now",
+            Self::Progress => "tc_progress",
+            Self::Random => "tc_random",
+            Self::Cancelled => "tc_cancelled",
+            Self::Resolve => "tc_resolve",
+            Self::Store => "tc_store",
+            Self::Load => "tc_load",
+        }
+    }
+}
+
+impl fmt::Display for HostImport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Write a message to the host log.
+pub fn log(level: LogLevel, message: &str) {
+    imp::log(level, message)
+}
+
+/// Report how far a long-running operation has got, as a `fraction` between 0 and 1, so the
+/// host can relay it to the caller. Unlike [`log`], progress is meant for the client.
+///
+/// On `wasm32` this calls the `tc_progress` import only with the `progress` feature enabled;
+/// without it, progress is dropped and the library doesn't import `tc_progress` at all.
+pub fn progress(fraction: f32, message: &str) {
+    let fraction = if fraction.is_nan() {
+        0.
+    } else {
+        fraction.clamp(0., 1.)
+    };
+
+    imp::progress(fraction, message)
+}
+
+/// Fill `buf` with random bytes from the host's entropy source, e.g. to generate a nonce or
+/// an ID. Handlers must not assume the bytes are deterministic (for example across a replay
+/// of the same transaction).
+pub fn random_bytes(buf: &mut [u8]) {
+    if !buf.is_empty() {
+        imp::random_bytes(buf)
+    }
+}
+
+/// Whether the host has asked for the current call to be cancelled (e.g. because the client
+/// disconnected). Prefer [`crate::CancellationToken`] in handlers.
+pub fn is_cancelled() -> bool {
+    imp::is_cancelled()
+}
+
+/// The host's current time.
+pub fn now() -> NetworkTime {
+    NetworkTime::from_nanos(imp::now())
+}
+
+/// Ask the host to resolve `op` (e.g. a call to another library) and return the result.
+pub fn resolve(op: OpRef) -> TCResult<Value> {
+    let request = encode_json_bytes(op)?;
+    let response = imp::resolve(&request);
+
+    #[cfg(any(feature = "tracing", feature = "testing", test))]
+    record_resolved(&request, &response);
+
+    Value::decode(&response?)
+}
+
+/// Ask the host to persist `value` on the library's behalf, returning the id it assigned.
+/// Return a [`crate::StateRef`] to hand the id to the client.
+pub fn store(value: Value) -> TCResult<StateId> {
+    let value = encode_json_bytes(value)?;
+    imp::store(&value).map(StateId::from)
+}
+
+/// Load a value previously persisted via [`store`].
+pub fn load(id: &StateId) -> TCResult<Value> {
+    Value::decode(&imp::load(id.as_str())?)
+}
+
+/// Load the JSON encoding of the value the host holds under `id`, for a caller to decode.
+pub(crate) fn load_json(id: &str) -> TCResult<Vec<u8>> {
+    imp::load(id)
+}
+
+/// One host round-trip made via [`resolve`], as recorded in the [`last_call_trace`].
+#[cfg(any(feature = "tracing", feature = "testing", test))]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ResolvedOp {
+    /// The JSON encoding of the resolved `OpRef`.
+    pub op: String,
+    /// The size of the encoded result, or `None` if the host returned an error.
+    pub response_len: Option<usize>,
+}
+
+/// Every `OpRef` resolved during the most recent (or current) call, in order. Requires the
+/// `tracing` or `testing` feature.
+#[cfg(any(feature = "tracing", feature = "testing", test))]
+pub fn last_call_trace() -> Vec<ResolvedOp> {
+    CALL_TRACE.with(|trace| trace.borrow().clone())
+}
+
+/// Start a new [`last_call_trace`], as each dispatch does on decoding its header.
+#[cfg(any(feature = "tracing", feature = "testing", test))]
+pub(crate) fn begin_call_trace() {
+    CALL_TRACE.with(|trace| trace.borrow_mut().clear())
+}
+
+#[cfg(any(feature = "tracing", feature = "testing", test))]
+fn record_resolved(request: &[u8], response: &TCResult<Vec<u8>>) {
+    let resolved = ResolvedOp {
+        op: String::from_utf8_lossy(request).into_owned(),
+        response_len: response.as_ref().ok().map(Vec::len),
+    };
+
+    CALL_TRACE.with(|trace| trace.borrow_mut().push(resolved))
+}
+
+#[cfg(target_arch = "wasm32")]
+mod imp {
+    use tc_error::{TCError, TCResult};
+
+    use super::LogLevel;
+    use crate::abi::{decode_serde_json, track_freed, unpack_ptr_len};
+
+    #[link(wasm_import_module = "tc_host")]
+    unsafe extern "C" {
+        fn tc_log(level: i32, ptr: i32, len: i32);
+        fn tc_now() -> i64;
+        #[cfg(feature = "progress")]
+        fn tc_progress(fraction: f32, ptr: i32, len: i32);
+        fn tc_random(ptr: i32, len: i32);
+        fn tc_cancelled() -> i32;
+        fn tc_resolve(ptr: i32, len: i32) -> i64;
+        fn tc_store(ptr: i32, len: i32) -> i64;
+        fn tc_load(ptr: i32, len: i32) -> i64;
+    }
+
+    pub fn log(level: LogLevel, message: &str) {
+        unsafe { tc_log(level as i32, message.as_ptr() as i32, message.len() as i32) }
+    }
+
+    pub fn progress(fraction: f32, message: &str) {
+        #[cfg(feature = "progress")]
+        unsafe {
+            tc_progress(fraction, message.as_ptr() as i32, message.len() as i32)
+        }
+
+        #[cfg(not(feature = "progress"))]
+        let _ = (fraction, message);
+    }
+
+    pub fn now() -> u64 {
+        unsafe { tc_now() as u64 }
+    }
+
+    pub fn random_bytes(buf: &mut [u8]) {
+        unsafe { tc_random(buf.as_mut_ptr() as i32, buf.len() as i32) }
+    }
+
+    pub fn is_cancelled() -> bool {
+        unsafe { tc_cancelled() != 0 }
+    }
+
+    pub fn resolve(request: &[u8]) -> TCResult<Vec<u8>> {
+        let packed = unsafe { tc_resolve(request.as_ptr() as i32, request.len() as i32) };
+        let value = take_result(packed)?;
+        serde_json::to_vec(&value).map_err(TCError::internal)
+    }
+
+    pub fn store(value: &[u8]) -> TCResult<String> {
+        let packed = unsafe { tc_store(value.as_ptr() as i32, value.len() as i32) };
+        match take_result(packed)? {
+            serde_json::Value::String(id) => Ok(id),
+            other => Err(TCError::bad_gateway(format!("invalid state id: {other}"))),
+        }
+    }
+
+    pub fn load(id: &str) -> TCResult<Vec<u8>> {
+        let packed = unsafe { tc_load(id.as_ptr() as i32, id.len() as i32) };
+        let value = take_result(packed)?;
+        serde_json::to_vec(&value).map_err(TCError::internal)
+    }
+
+    /// Decode a `{"ok": <value>}` or `{"error": "<message>"}` frame written by the host.
+    fn take_result(packed: i64) -> TCResult<serde_json::Value> {
+        let frame = take_host_buffer(packed);
+
+        let mut frame: serde_json::Map<String, serde_json::Value> = decode_serde_json(&frame)?;
+        if let Some(error) = frame.remove("error") {
+            return Err(TCError::bad_gateway(error));
+        }
+
+        Ok(frame.remove("ok").unwrap_or_default())
+    }
+
+    /// Take ownership of a buffer the host wrote into memory obtained from our `alloc` export.
+    fn take_host_buffer(packed: i64) -> Vec<u8> {
+        let (ptr, len) = unpack_ptr_len(packed);
+        if ptr == 0 || len <= 0 {
+            return Vec::new();
+        }
+
+        track_freed(len as usize);
+        unsafe { crate::memory::take(ptr, len as usize) }.into_vec()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod imp {
+    use tc_error::{TCError, TCResult};
+
+    use super::LogLevel;
+
+    pub fn log(level: LogLevel, message: &str) {
+        #[cfg(any(feature = "testing", test))]
+        if crate::testing::with_mock(|host| host.record_log(level, message)).is_some() {
+            return;
+        }
+
+        #[cfg(feature = "debug-print")]
+        eprintln!("[{level:?}] {message}");
+
+        let _ = (level, message);
+    }
+
+    pub fn progress(fraction: f32, message: &str) {
+        #[cfg(any(feature = "testing", test))]
+        crate::testing::with_mock(|host| host.record_progress(fraction, message));
+
+        let _ = (fraction, message);
+    }
+
+    pub fn now() -> u64 {
+        #[cfg(any(feature = "testing", test))]
+        if let Some(now) = crate::testing::with_mock(|host| host.now_nanos()) {
+            return now;
+        }
+
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("system time")
+            .as_nanos() as u64
+    }
+
+    pub fn random_bytes(buf: &mut [u8]) {
+        getrandom::getrandom(buf).expect("system entropy source")
+    }
+
+    pub fn is_cancelled() -> bool {
+        #[cfg(any(feature = "testing", test))]
+        if let Some(cancelled) = crate::testing::with_mock(|host| host.poll_cancelled()) {
+            return cancelled;
+        }
+
+        false
+    }
+
+    pub fn resolve(request: &[u8]) -> TCResult<Vec<u8>> {
+        #[cfg(any(feature = "testing", test))]
+        if let Some(response) = crate::testing::with_mock(|host| host.answer(request)) {
+            return response;
+        }
+
+        let _ = request;
+        Err(TCError::bad_gateway("no TinyChain host to resolve against"))
+    }
+
+    pub fn store(value: &[u8]) -> TCResult<String> {
+        #[cfg(any(feature = "testing", test))]
+        if let Some(id) = crate::testing::with_mock(|host| host.store_state(value)) {
+            return Ok(id);
+        }
+
+        let _ = value;
+        Err(TCError::bad_gateway("no TinyChain host to store state in"))
+    }
+
+    pub fn load(id: &str) -> TCResult<Vec<u8>> {
+        #[cfg(any(feature = "testing", test))]
+        if let Some(value) = crate::testing::with_mock(|host| host.load_state(id)) {
+            return value;
+        }
+
+        Err(TCError::bad_gateway(format!(
+            "no TinyChain host to load state {id} from"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_random_buffer() {
+        let mut first = [0u8; 64];
+        let mut second = [0u8; 64];
+        random_bytes(&mut first);
+        random_bytes(&mut second);
+
+        assert_ne!(first, [0u8; 64]);
+        assert_ne!(first, second);
+
+        random_bytes(&mut []);
+    }
+}
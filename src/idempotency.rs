@@ -0,0 +1,439 @@
+//! Opt-in deduplication of PUT and POST requests by idempotency key, so a client can safely
+//! retry a write.
+//!
+//! A key is scoped to the route and the caller's claim, so one caller can never be replayed
+//! another's response, and it's bound to the body it was first sent with: reusing a key with a
+//! different body is a bad request rather than a silent replay of the original response.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, decode_header_bytes,
+    dispatch_post_decoded, dispatch_put_decoded, respond, take_header_field,
+};
+use crate::codec::request_body;
+use crate::host;
+use crate::response::content_hash;
+
+/// The default number of responses retained per thread for replay.
+pub const DEFAULT_IDEMPOTENCY_CACHE_CAPACITY: usize = 256;
+
+/// The default time for which a key's response is replayed.
+pub const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+thread_local! {
+    static IDEMPOTENCY_CACHE: RefCell<ReplayCache> = RefCell::new(ReplayCache::default());
+    static IDEMPOTENCY_CACHE_CAPACITY: Cell<usize> = const {
+        Cell::new(DEFAULT_IDEMPOTENCY_CACHE_CAPACITY)
+    };
+    static IDEMPOTENCY_WINDOW: Cell<Duration> = const { Cell::new(DEFAULT_IDEMPOTENCY_WINDOW) };
+    static ROUTE_IDEMPOTENCY_WINDOWS: RefCell<HashMap<&'static str, Duration>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Set the maximum number of retained responses. Once full, the oldest is evicted.
+pub fn set_idempotency_cache_capacity(capacity: usize) {
+    IDEMPOTENCY_CACHE_CAPACITY.with(|max| max.set(capacity));
+}
+
+/// Set how long (by the host's clock) a key's response is replayed; older entries expire.
+pub fn set_idempotency_window(window: Duration) {
+    IDEMPOTENCY_WINDOW.with(|current| current.set(window));
+}
+
+/// Override the replay window for `route` (e.g. a short one for a high-volume route), or
+/// restore the default set via [`set_idempotency_window`] with `None`.
+pub fn set_route_idempotency_window(route: &'static str, window: Option<Duration>) {
+    ROUTE_IDEMPOTENCY_WINDOWS.with(|windows| {
+        let mut windows = windows.borrow_mut();
+        match window {
+            Some(window) => windows.insert(route, window),
+            None => windows.remove(route),
+        };
+    })
+}
+
+/// The replay window of `route`: its own, if set, or else the default.
+pub fn idempotency_window(route: &str) -> Duration {
+    ROUTE_IDEMPOTENCY_WINDOWS
+        .with(|windows| windows.borrow().get(route).copied())
+        .unwrap_or_else(|| IDEMPOTENCY_WINDOW.with(Cell::get))
+}
+
+/// Drop every retained response.
+pub fn reset_idempotency_cache() {
+    IDEMPOTENCY_CACHE.with(|cache| *cache.borrow_mut() = ReplayCache::default());
+}
+
+/// A request paired with the `idempotency_key` (if any) from its header.
+pub struct Idempotent<Req> {
+    pub key: Option<String>,
+    pub request: Req,
+}
+
+/// The route, the link and mode of the caller's claim, and the idempotency key.
+type CacheKey = (&'static str, (String, u32), String);
+
+struct Replay {
+    response: Vec<u8>,
+    body_hash: String,
+    stored_at: u64,
+    seq: u64,
+}
+
+/// Retained responses, with the order they were stored in so that the oldest can be found
+/// without scanning every entry.
+#[derive(Default)]
+struct ReplayCache {
+    entries: HashMap<CacheKey, Replay>,
+    order: BTreeMap<u64, CacheKey>,
+    next_seq: u64,
+}
+
+impl ReplayCache {
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(replay) = self.entries.remove(key) {
+            self.order.remove(&replay.seq);
+        }
+    }
+
+    fn pop_oldest(&mut self) {
+        if let Some((_, key)) = self.order.pop_first() {
+            self.entries.remove(&key);
+        }
+    }
+
+    fn oldest_is_expired(&self, now: u64) -> bool {
+        self.order
+            .first_key_value()
+            .and_then(|(_, key)| Some((key, self.entries.get(key)?)))
+            .is_some_and(|(key, replay)| is_expired(key, replay, now))
+    }
+}
+
+macro_rules! define_idempotent_dispatch {
+    (
+        $dispatch_fn:ident,
+        $try_dispatch_bytes_fn:ident,
+        $dispatch_decoded_fn:ident,
+        $handler_trait:ident,
+        $doc_base:literal,
+    ) => {
+        #[doc = concat!("Like [`crate::", $doc_base, "`], but replays the response to a repeated")]
+        /// `idempotency_key` on `route` within its [`idempotency_window`] instead of calling
+        /// the handler again. Requests without a key are always dispatched.
+        pub fn $dispatch_fn<H, Txn, Req, Res>(
+            route: &'static str,
+            handler: &H,
+            header_ptr: i32,
+            header_len: i32,
+            body_ptr: i32,
+            body_len: i32,
+        ) -> i64
+        where
+            Txn: WasmTransaction,
+            H: tc_ir::$handler_trait<
+                    Txn,
+                    Request = Idempotent<Req>,
+                    RequestContext = (),
+                    Response = Res,
+                    Error = TCError,
+                >,
+            Req: WasmRequest,
+            Res: WasmResponse,
+        {
+            let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+            let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+            respond($try_dispatch_bytes_fn(
+                route,
+                handler,
+                header_bytes,
+                body_bytes,
+            ))
+        }
+
+        /// Dispatch an already-read header and body, deduplicating by idempotency key.
+        pub fn $try_dispatch_bytes_fn<H, Txn, Req, Res>(
+            route: &'static str,
+            handler: &H,
+            header_bytes: &[u8],
+            body_bytes: &[u8],
+        ) -> TCResult<Vec<u8>>
+        where
+            Txn: WasmTransaction,
+            H: tc_ir::$handler_trait<
+                    Txn,
+                    Request = Idempotent<Req>,
+                    RequestContext = (),
+                    Response = Res,
+                    Error = TCError,
+                >,
+            Req: WasmRequest,
+            Res: WasmResponse,
+        {
+            let (key, header_bytes) = take_header_field(header_bytes, "idempotency_key")?;
+            let header = decode_header_bytes(&header_bytes)?;
+            let claim = (
+                header.claim().link().to_string(),
+                u32::from(header.claim().mode()),
+            );
+            let txn = Txn::from_wasm_header(header)?;
+
+            let cache_key = key.clone().map(|key| (route, claim, key));
+            let body_hash = content_hash(body_bytes);
+            let replayed = cache_key.as_ref().map(|key| replay(key, &body_hash));
+            if let Some(response) = replayed.transpose()?.flatten() {
+                return Ok(response);
+            }
+
+            let request = Idempotent {
+                key,
+                request: Req::decode(&request_body(body_bytes)?)?,
+            };
+
+            let response = $dispatch_decoded_fn(handler, &txn, request)?.encode()?;
+
+            if let Some(cache_key) = cache_key {
+                retain(cache_key, body_hash, response.clone());
+            }
+
+            Ok(response)
+        }
+    };
+}
+
+define_idempotent_dispatch!(
+    dispatch_put_idempotent,
+    try_dispatch_put_idempotent_bytes,
+    dispatch_put_decoded,
+    HandlePut,
+    "dispatch_put",
+);
+
+define_idempotent_dispatch!(
+    dispatch_post_idempotent,
+    try_dispatch_post_idempotent_bytes,
+    dispatch_post_decoded,
+    HandlePost,
+    "dispatch_post",
+);
+
+/// The response retained for `key`, if it hasn't expired. Fails if `key` was first used with
+/// a body other than the one hashed as `body_hash`.
+fn replay(key: &CacheKey, body_hash: &str) -> TCResult<Option<Vec<u8>>> {
+    let now = host::now().as_nanos();
+
+    IDEMPOTENCY_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let Some(replay) = cache.entries.get(key) else {
+            return Ok(None);
+        };
+
+        if is_expired(key, replay, now) {
+            cache.remove(key);
+            Ok(None)
+        } else if replay.body_hash != body_hash {
+            Err(TCError::bad_request(format!(
+                "idempotency key {} was already used with a different request body",
+                key.2
+            )))
+        } else {
+            Ok(Some(replay.response.clone()))
+        }
+    })
+}
+
+fn retain(key: CacheKey, body_hash: String, response: Vec<u8>) {
+    let capacity = IDEMPOTENCY_CACHE_CAPACITY.with(Cell::get);
+    if capacity == 0 {
+        return;
+    }
+
+    let now = host::now().as_nanos();
+
+    IDEMPOTENCY_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        cache.remove(&key);
+
+        while cache.oldest_is_expired(now) {
+            cache.pop_oldest();
+        }
+
+        while cache.entries.len() >= capacity {
+            cache.pop_oldest();
+        }
+
+        let seq = cache.next_seq;
+        cache.next_seq += 1;
+        cache.order.insert(seq, key.clone());
+
+        let replay = Replay {
+            response,
+            body_hash,
+            stored_at: now,
+            seq,
+        };
+
+        cache.entries.insert(key, replay);
+    })
+}
+
+fn is_expired((route, _, _): &CacheKey, replay: &Replay, now: u64) -> bool {
+    Duration::from_nanos(now.saturating_sub(replay.stored_at)) > idempotency_window(route)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tc_ir::NetworkTime;
+    use tc_value::Value;
+
+    use pathlink::Link;
+    use std::str::FromStr;
+
+    use crate::claim;
+    use crate::test_support::{
+        FakeTxn, txn_header_bytes, txn_header_bytes_for, txn_header_bytes_with_field,
+    };
+    use crate::testing::MockHost;
+
+    /// Issues a new order number for each call.
+    #[derive(Default)]
+    struct OrderHandler {
+        issued: AtomicU64,
+    }
+
+    impl tc_ir::HandlePost<FakeTxn> for OrderHandler {
+        type Request = Idempotent<Value>;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn post<'a>(
+            &'a self,
+            _txn: &'a FakeTxn,
+            _request: Self::Request,
+        ) -> TCResult<Self::Fut<'a>> {
+            let order = self.issued.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(Box::pin(async move { Ok(Value::from(order)) }))
+        }
+    }
+
+    fn order(route: &'static str, handler: &OrderHandler, header: &[u8]) -> Value {
+        order_with(route, handler, header, b"").expect("order")
+    }
+
+    fn order_with(
+        route: &'static str,
+        handler: &OrderHandler,
+        header: &[u8],
+        body: &[u8],
+    ) -> TCResult<Value> {
+        let response = try_dispatch_post_idempotent_bytes::<_, FakeTxn, Value, Value>(
+            route, handler, header, body,
+        )?;
+
+        Value::decode(&response)
+    }
+
+    #[test]
+    fn replays_response_for_repeated_key() {
+        let handler = OrderHandler::default();
+        let first = txn_header_bytes_with_field("idempotency_key", "k-1");
+        let second = txn_header_bytes_with_field("idempotency_key", "k-2");
+
+        assert_eq!(order("/orders", &handler, &first), Value::from(1u64));
+        assert_eq!(order("/orders", &handler, &first), Value::from(1u64));
+        assert_eq!(order("/orders", &handler, &second), Value::from(2u64));
+        assert_eq!(
+            order("/orders", &handler, &txn_header_bytes()),
+            Value::from(3u64)
+        );
+        assert_eq!(
+            order("/orders", &handler, &txn_header_bytes()),
+            Value::from(4u64)
+        );
+        assert_eq!(order("/refunds", &handler, &first), Value::from(5u64));
+    }
+
+    #[test]
+    fn scopes_keys_to_claim_and_body() {
+        let handler = OrderHandler::default();
+        let header = txn_header_bytes_with_field("idempotency_key", "k-1");
+
+        assert_eq!(
+            order_with("/orders", &handler, &header, br#""a""#).expect("first"),
+            Value::from(1u64)
+        );
+
+        let err = order_with("/orders", &handler, &header, br#""b""#).expect_err("reused key");
+        assert!(err.to_string().contains("different request body"), "{err}");
+
+        let reader = claim::read_only(Link::from_str("/lib").expect("link"));
+        let mut other: serde_json::Value =
+            serde_json::from_slice(&txn_header_bytes_for(reader)).expect("header json");
+        other["idempotency_key"] = "k-1".into();
+        let other = serde_json::to_vec(&other).expect("header json");
+
+        assert_eq!(
+            order_with("/orders", &handler, &other, br#""a""#).expect("other caller"),
+            Value::from(2u64)
+        );
+    }
+
+    #[test]
+    fn expires_replays_after_window() {
+        set_idempotency_window(Duration::from_secs(60));
+
+        let handler = OrderHandler::default();
+        let header = txn_header_bytes_with_field("idempotency_key", "k-1");
+
+        {
+            let _guard = MockHost::new().install();
+            assert_eq!(order("/expiring", &handler, &header), Value::from(1u64));
+        }
+
+        {
+            let later = NetworkTime::from_nanos(Duration::from_secs(61).as_nanos() as u64);
+            let _guard = MockHost::new().with_time(later).install();
+            assert_eq!(order("/expiring", &handler, &header), Value::from(2u64));
+        }
+
+        set_idempotency_window(DEFAULT_IDEMPOTENCY_WINDOW);
+    }
+
+    #[test]
+    fn applies_per_route_window() {
+        set_route_idempotency_window("/quotes", Some(Duration::from_secs(5)));
+        assert_eq!(idempotency_window("/quotes"), Duration::from_secs(5));
+        assert_eq!(idempotency_window("/orders"), DEFAULT_IDEMPOTENCY_WINDOW);
+
+        let handler = OrderHandler::default();
+        let header = txn_header_bytes_with_field("idempotency_key", "k-1");
+
+        let host = MockHost::new();
+        let _guard = host.install();
+
+        assert_eq!(order("/quotes", &handler, &header), Value::from(1u64));
+        assert_eq!(order("/orders", &handler, &header), Value::from(2u64));
+
+        host.advance_time(Duration::from_secs(3));
+        assert_eq!(order("/quotes", &handler, &header), Value::from(1u64));
+
+        host.advance_time(Duration::from_secs(3));
+        assert_eq!(order("/quotes", &handler, &header), Value::from(3u64));
+        assert_eq!(order("/orders", &handler, &header), Value::from(2u64));
+
+        set_route_idempotency_window("/quotes", None);
+        assert_eq!(idempotency_window("/quotes"), DEFAULT_IDEMPOTENCY_WINDOW);
+    }
+}
@@ -0,0 +1,116 @@
+//! Optional integrity framing of responses, so the host can detect a response corrupted on
+//! its way out of the instance.
+//!
+//! Once enabled via [`set_integrity_hash`], every response from a `dispatch_*` function
+//! (including error payloads) is sent as
+//!
+//! ```text
+//! [payload length: u32, little-endian][payload][SHA-256 of payload: 32 bytes]
+//! ```
+//!
+//! where the hash is TinyChain's content hash (see [`crate::content_hash`]) in raw rather
+//! than hex form. [`verify_integrity_frame`] is the reference decoder.
+
+use sha2::{Digest, Sha256};
+use std::cell::Cell;
+use tc_error::{TCError, TCResult};
+
+use crate::request::{FRAME_PREFIX_LEN, frame_body};
+
+/// The size of the hash which trails an integrity frame.
+pub const INTEGRITY_HASH_LEN: usize = 32;
+
+thread_local! {
+    static INTEGRITY_HASH: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Append an integrity hash to every response. Disabled by default.
+pub fn set_integrity_hash(enabled: bool) {
+    INTEGRITY_HASH.with(|current| current.set(enabled));
+}
+
+/// Frame `payload` with its length and trailing hash.
+pub fn integrity_frame(payload: &[u8]) -> TCResult<Vec<u8>> {
+    let mut frame = frame_body(payload)?;
+    frame.reserve_exact(INTEGRITY_HASH_LEN);
+    frame.extend_from_slice(&Sha256::digest(payload));
+    Ok(frame)
+}
+
+/// Return the payload of an integrity frame, failing if the frame is truncated or the
+/// payload doesn't match its hash.
+pub fn verify_integrity_frame(frame: &[u8]) -> TCResult<&[u8]> {
+    let corrupt = |reason: &str| TCError::internal(format!("corrupt response: {reason}"));
+
+    if frame.len() < FRAME_PREFIX_LEN + INTEGRITY_HASH_LEN {
+        return Err(corrupt("frame is too short"));
+    }
+
+    let (prefix, rest) = frame.split_at(FRAME_PREFIX_LEN);
+    let len = u32::from_le_bytes(prefix.try_into().expect("length prefix")) as usize;
+    if rest.len() != len + INTEGRITY_HASH_LEN {
+        return Err(corrupt("length mismatch"));
+    }
+
+    let (payload, hash) = rest.split_at(len);
+    if Sha256::digest(payload).as_slice() == hash {
+        Ok(payload)
+    } else {
+        Err(corrupt("hash mismatch"))
+    }
+}
+
+/// Frame an encoded response if integrity hashing is enabled.
+pub(crate) fn with_integrity_hash(payload: Vec<u8>) -> TCResult<Vec<u8>> {
+    if INTEGRITY_HASH.with(Cell::get) {
+        integrity_frame(&payload)
+    } else {
+        Ok(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tc_value::Value;
+
+    use crate::abi::{WasmRequest, response_bytes, try_dispatch_get_bytes};
+    use crate::test_support::{FakeTxn, GreetHandler, txn_header_bytes};
+
+    #[test]
+    fn appends_verifiable_hash() {
+        set_integrity_hash(true);
+        let frame = response_bytes(try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(
+            &GreetHandler,
+            &txn_header_bytes(),
+            &[],
+        ));
+        let error_frame = response_bytes(try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(
+            &GreetHandler,
+            &[],
+            &[],
+        ));
+        set_integrity_hash(false);
+
+        assert!(verify_integrity_frame(&error_frame).is_ok());
+
+        let payload = verify_integrity_frame(&frame).expect("intact frame");
+        assert_eq!(
+            Value::decode(payload).expect("payload"),
+            Value::from("hello")
+        );
+        assert_eq!(
+            &frame[frame.len() - INTEGRITY_HASH_LEN..],
+            Sha256::digest(payload).as_slice()
+        );
+
+        let mut corrupted = frame.clone();
+        corrupted[FRAME_PREFIX_LEN] ^= 0xff;
+        let err = verify_integrity_frame(&corrupted).expect_err("corrupted payload");
+        assert!(err.to_string().contains("hash mismatch"), "{err}");
+
+        let truncated = &frame[..frame.len() - 1];
+        assert!(verify_integrity_frame(truncated).is_err());
+    }
+}
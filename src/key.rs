@@ -0,0 +1,94 @@
+//! Composite keys, for routes keyed by more than one value (e.g. `["users", 42]`).
+//!
+//! A [`CompositeKey`] decodes a JSON array into a tuple of typed components (of two to six
+//! components), or into a `Vec` of any length. Each component is decoded by its own
+//! [`WasmRequest`] impl, so an invalid key fails with an error naming the component.
+
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{WasmRequest, decode_serde_json};
+
+/// A request which is a JSON array of key components, e.g. `CompositeKey<(String, u64)>`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CompositeKey<T>(pub T);
+
+/// Split a composite key into the JSON encoding of each component, checking its length if
+/// the key has a fixed number of components.
+fn key_components(bytes: &[u8], len: Option<usize>) -> TCResult<Vec<Vec<u8>>> {
+    let components: Vec<serde_json::Value> = decode_serde_json(bytes).map_err(|err| {
+        TCError::bad_request(format!("a composite key must be a JSON array: {err}"))
+    })?;
+
+    match len {
+        Some(len) if components.len() != len => Err(TCError::bad_request(format!(
+            "expected a key of {len} components but found {}",
+            components.len()
+        ))),
+        _ => components
+            .iter()
+            .map(|component| serde_json::to_vec(component).map_err(TCError::internal))
+            .collect(),
+    }
+}
+
+fn decode_component<T: WasmRequest>(index: usize, component: &[u8]) -> TCResult<T> {
+    T::decode(component)
+        .map_err(|err| TCError::bad_request(format!("invalid key component {index}: {err}")))
+}
+
+impl<T: WasmRequest> WasmRequest for CompositeKey<Vec<T>> {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        key_components(bytes, None)?
+            .iter()
+            .enumerate()
+            .map(|(index, component)| decode_component(index, component))
+            .collect::<TCResult<Vec<T>>>()
+            .map(Self)
+    }
+}
+
+macro_rules! composite_key {
+    ($len:literal; $($index:tt: $component:ident),+) => {
+        impl<$($component: WasmRequest),+> WasmRequest for CompositeKey<($($component,)+)> {
+            fn decode(bytes: &[u8]) -> TCResult<Self> {
+                let components = key_components(bytes, Some($len))?;
+                Ok(Self(($(decode_component::<$component>($index, &components[$index])?,)+)))
+            }
+        }
+    };
+}
+
+composite_key!(2; 0: A, 1: B);
+composite_key!(3; 0: A, 1: B, 2: C);
+composite_key!(4; 0: A, 1: B, 2: C, 3: D);
+composite_key!(5; 0: A, 1: B, 2: C, 3: D, 4: E);
+composite_key!(6; 0: A, 1: B, 2: C, 3: D, 4: E, 5: F);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_typed_components() {
+        let CompositeKey((table, id)) =
+            CompositeKey::<(String, u64)>::decode(br#"["users", 42]"#).expect("key");
+
+        assert_eq!(table, "users");
+        assert_eq!(id, 42);
+
+        let CompositeKey(path) = CompositeKey::<Vec<u64>>::decode(b"[1, 2, 3]").expect("path");
+        assert_eq!(path, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn names_the_invalid_component() {
+        let err =
+            CompositeKey::<(String, u64)>::decode(br#"["users", "42"]"#).expect_err("wrong type");
+        assert!(err.to_string().contains("invalid key component 1"), "{err}");
+
+        let err = CompositeKey::<(String, u64)>::decode(br#"["users"]"#).expect_err("too short");
+        assert!(err.to_string().contains("2 components"), "{err}");
+
+        assert!(CompositeKey::<Vec<u64>>::decode(br#""users""#).is_err());
+    }
+}
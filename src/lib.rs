@@ -1,2 +1,81 @@
 pub mod abi;
+#[cfg(any(feature = "alloc-limit", test))]
+pub mod alloc_limit;
+pub mod audit;
+pub mod batch;
+#[cfg(any(feature = "bump-alloc", test))]
+pub mod bump;
+pub mod cache;
+pub mod cancel;
+pub mod chain;
+pub mod claim;
+pub mod codec;
+pub mod conditional;
+pub mod config;
+pub mod convert;
+pub mod correlation;
+pub mod diagnostics;
+pub mod executor;
+pub mod host;
+pub mod idempotency;
+pub mod integrity;
+pub mod key;
+pub mod live;
+pub mod memo;
+mod memory;
+pub mod options;
+pub mod patch;
+pub mod print;
+pub mod redact;
+pub mod represent;
+pub mod request;
+pub mod response;
+pub mod schema;
+pub mod sink;
+pub mod state;
+pub mod stream;
+pub mod subscribe;
+#[cfg(any(feature = "testing", test))]
+pub mod testing;
+pub mod text;
+pub mod timing;
+pub mod transform;
+pub mod validate;
+pub mod version;
+
+#[cfg(test)]
+mod test_support;
+
 pub use abi::*;
+pub use audit::*;
+pub use batch::*;
+pub use cache::*;
+pub use cancel::*;
+pub use chain::*;
+pub use codec::*;
+pub use conditional::*;
+pub use config::*;
+pub use convert::*;
+pub use correlation::*;
+pub use diagnostics::*;
+pub use idempotency::*;
+pub use integrity::*;
+pub use key::*;
+pub use live::*;
+pub use memo::*;
+pub use options::*;
+pub use patch::*;
+pub use redact::*;
+pub use represent::*;
+pub use request::*;
+pub use response::*;
+pub use schema::*;
+pub use sink::*;
+pub use state::*;
+pub use stream::*;
+pub use subscribe::*;
+pub use text::*;
+pub use timing::*;
+pub use transform::*;
+pub use validate::*;
+pub use version::*;
@@ -0,0 +1,346 @@
+//! Incremental updates to a live object: a client polling a changing object sends the hash of
+//! the version it holds, and the handler answers with a JSON Patch (RFC 6902) from that
+//! version to the current one instead of re-sending the whole object.
+//!
+//! The library is responsible for keeping the versions a client may hold (e.g. the last few
+//! revisions, keyed by their [`crate::content_hash`]). When it no longer has the client's
+//! version, it answers with the full object.
+
+use destream::en;
+use serde::Serialize;
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, canonical_json_bytes,
+    decode_header_bytes, dispatch_get_decoded, respond, take_header_field,
+};
+use crate::codec::request_body;
+use crate::response::content_hash;
+
+/// A request paired with the `base_hash` (if any) from its header: the [`content_hash`] of the
+/// canonical JSON of the version of the object the client already holds.
+pub struct LiveRequest<Req> {
+    pub base_hash: Option<String>,
+    pub request: Req,
+}
+
+/// One JSON Patch operation, whose `path` is a JSON Pointer (RFC 6901).
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add {
+        path: String,
+        value: serde_json::Value,
+    },
+    Remove {
+        path: String,
+    },
+    Replace {
+        path: String,
+        value: serde_json::Value,
+    },
+}
+
+/// The response to a [`LiveRequest`], encoded as the frame
+/// `{"$frame": "json_patch", "base": "<hash>", "hash": "<hash>", "ops": [...]}`, where `ops`
+/// transforms the canonical JSON of the version hashed as `base` into that of the current
+/// version, hashed as `hash` (for the client to send as its next `base_hash`).
+///
+/// With no base (e.g. the client's version is unknown) the full object is sent without a
+/// frame.
+pub struct JsonPatch<T> {
+    base: Option<T>,
+    value: T,
+}
+
+impl<T> JsonPatch<T> {
+    pub fn new(base: Option<T>, value: T) -> Self {
+        Self { base, value }
+    }
+}
+
+impl<T> WasmResponse for JsonPatch<T>
+where
+    T: for<'en> en::IntoStream<'en>,
+{
+    fn encode(self) -> TCResult<Vec<u8>> {
+        let value = canonical_json_bytes(self.value)?;
+
+        let Some(base) = self.base else {
+            return Ok(value);
+        };
+
+        let base = canonical_json_bytes(base)?;
+        let ops = diff_json(&parse_json(&base)?, &parse_json(&value)?);
+
+        let frame = serde_json::json!({
+            "$frame": "json_patch",
+            "base": content_hash(&base),
+            "hash": content_hash(&value),
+            "ops": ops,
+        });
+
+        serde_json::to_vec(&frame).map_err(TCError::internal)
+    }
+}
+
+fn parse_json(canonical: &[u8]) -> TCResult<serde_json::Value> {
+    serde_json::from_slice(canonical).map_err(TCError::internal)
+}
+
+/// Compute the JSON Patch operations which transform `base` into `target`.
+///
+/// Maps are diffed key by key. Arrays are diffed index by index, then extended with `add`
+/// operations or truncated with `remove` operations (from the end, so each index is still
+/// valid when it's applied). Any other change replaces the value.
+pub fn diff_json(base: &serde_json::Value, target: &serde_json::Value) -> Vec<PatchOperation> {
+    let mut ops = Vec::new();
+    diff_at(&mut String::new(), base, target, &mut ops);
+    ops
+}
+
+fn diff_at(
+    path: &mut String,
+    base: &serde_json::Value,
+    target: &serde_json::Value,
+    ops: &mut Vec<PatchOperation>,
+) {
+    use serde_json::Value as Json;
+
+    match (base, target) {
+        _ if base == target => {}
+        (Json::Object(base), Json::Object(target)) => {
+            for (key, value) in base {
+                let len = push_token(path, key);
+                match target.get(key) {
+                    Some(target) => diff_at(path, value, target, ops),
+                    None => ops.push(PatchOperation::Remove { path: path.clone() }),
+                }
+                path.truncate(len);
+            }
+
+            for (key, value) in target {
+                if !base.contains_key(key) {
+                    let len = push_token(path, key);
+                    ops.push(PatchOperation::Add {
+                        path: path.clone(),
+                        value: value.clone(),
+                    });
+                    path.truncate(len);
+                }
+            }
+        }
+        (Json::Array(base), Json::Array(target)) => {
+            for (index, (base, target)) in base.iter().zip(target).enumerate() {
+                let len = push_token(path, &index.to_string());
+                diff_at(path, base, target, ops);
+                path.truncate(len);
+            }
+
+            for (index, value) in target.iter().enumerate().skip(base.len()) {
+                let len = push_token(path, &index.to_string());
+                ops.push(PatchOperation::Add {
+                    path: path.clone(),
+                    value: value.clone(),
+                });
+                path.truncate(len);
+            }
+
+            for index in (target.len()..base.len()).rev() {
+                let len = push_token(path, &index.to_string());
+                ops.push(PatchOperation::Remove { path: path.clone() });
+                path.truncate(len);
+            }
+        }
+        _ => ops.push(PatchOperation::Replace {
+            path: path.clone(),
+            value: target.clone(),
+        }),
+    }
+}
+
+/// Append `/token` to `path`, escaped per RFC 6901, returning the length to truncate back to.
+fn push_token(path: &mut String, token: &str) -> usize {
+    let len = path.len();
+    path.push('/');
+    path.push_str(&token.replace('~', "~0").replace('/', "~1"));
+    len
+}
+
+/// Like [`crate::dispatch_get`], but passes the header's `base_hash` to the handler.
+pub fn dispatch_get_live<H, Txn, Req, Res>(
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<
+            Txn,
+            Request = LiveRequest<Req>,
+            RequestContext = (),
+            Response = JsonPatch<Res>,
+            Error = TCError,
+        >,
+    Req: WasmRequest,
+    Res: for<'en> en::IntoStream<'en>,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_live_bytes(
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and body, passing the header's `base_hash` to the
+/// handler.
+pub fn try_dispatch_get_live_bytes<H, Txn, Req, Res>(
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<
+            Txn,
+            Request = LiveRequest<Req>,
+            RequestContext = (),
+            Response = JsonPatch<Res>,
+            Error = TCError,
+        >,
+    Req: WasmRequest,
+    Res: for<'en> en::IntoStream<'en>,
+{
+    let (base_hash, header_bytes) = take_header_field(header_bytes, "base_hash")?;
+    let header = decode_header_bytes(&header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+    let request = Req::decode(&request_body(body_bytes)?)?;
+
+    let request = LiveRequest { base_hash, request };
+    dispatch_get_decoded(handler, &txn, request)?.encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::collections::BTreeMap;
+    use std::pin::Pin;
+    use tc_value::Value;
+
+    use crate::test_support::{FakeTxn, txn_header_bytes, txn_header_bytes_with_field};
+
+    fn version(revision: u64) -> BTreeMap<String, Value> {
+        let fields = match revision {
+            1 => [
+                ("status", Value::from("starting")),
+                ("uptime", Value::from(0u64)),
+                ("warning", Value::from("disk almost full")),
+            ],
+            _ => [
+                ("errors", Value::from(0u64)),
+                ("status", Value::from("running")),
+                ("uptime", Value::from(60u64)),
+            ],
+        };
+
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    }
+
+    fn hash(revision: u64) -> String {
+        content_hash(&canonical_json_bytes(version(revision)).expect("canonical json"))
+    }
+
+    /// Serves revision 2, as a patch from revision 1 if that's what the client holds.
+    struct DashboardHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for DashboardHandler {
+        type Request = LiveRequest<Value>;
+        type RequestContext = ();
+        type Response = JsonPatch<BTreeMap<String, Value>>;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let base = (request.base_hash == Some(hash(1))).then(|| version(1));
+            Ok(Box::pin(
+                async move { Ok(JsonPatch::new(base, version(2))) },
+            ))
+        }
+    }
+
+    fn get(header: &[u8]) -> serde_json::Value {
+        let response =
+            try_dispatch_get_live_bytes::<_, FakeTxn, Value, _>(&DashboardHandler, header, &[])
+                .expect("response");
+
+        serde_json::from_slice(&response).expect("response json")
+    }
+
+    #[test]
+    fn patches_from_the_clients_version() {
+        let frame = get(&txn_header_bytes_with_field("base_hash", &hash(1)));
+
+        assert_eq!(frame["$frame"], "json_patch");
+        assert_eq!(frame["base"], hash(1));
+        assert_eq!(frame["hash"], hash(2));
+        assert_eq!(
+            frame["ops"],
+            serde_json::json!([
+                {"op": "replace", "path": "/status", "value": "running"},
+                {"op": "replace", "path": "/uptime", "value": 60},
+                {"op": "remove", "path": "/warning"},
+                {"op": "add", "path": "/errors", "value": 0},
+            ])
+        );
+
+        let full = canonical_json_bytes(version(2)).expect("canonical json");
+        let full: serde_json::Value = serde_json::from_slice(&full).expect("json");
+        assert_eq!(get(&txn_header_bytes()), full);
+        assert_eq!(
+            get(&txn_header_bytes_with_field("base_hash", "unknown")),
+            full
+        );
+    }
+
+    #[test]
+    fn diffs_arrays_and_escapes_pointers() {
+        let base = serde_json::json!({"a/b": [1, 2, 3], "m~n": {"x": true}});
+        let target = serde_json::json!({"a/b": [1, 5], "m~n": {"x": true, "y": null}});
+
+        assert_eq!(
+            diff_json(&base, &target),
+            vec![
+                PatchOperation::Replace {
+                    path: "/a~1b/1".to_string(),
+                    value: serde_json::json!(5),
+                },
+                PatchOperation::Remove {
+                    path: "/a~1b/2".to_string(),
+                },
+                PatchOperation::Add {
+                    path: "/m~0n/y".to_string(),
+                    value: serde_json::Value::Null,
+                },
+            ]
+        );
+
+        assert!(diff_json(&base, &base).is_empty());
+        assert_eq!(
+            diff_json(&base, &serde_json::json!(7)),
+            vec![PatchOperation::Replace {
+                path: String::new(),
+                value: serde_json::json!(7),
+            }]
+        );
+    }
+}
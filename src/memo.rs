@@ -0,0 +1,204 @@
+//! A bounded cache for expensive, deterministic computations which handlers would otherwise
+//! repeat on every call (e.g. deriving a schema). Unlike the response cache, it works on any
+//! value inside a handler rather than on whole responses.
+
+use std::any::{Any, TypeId};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// The default number of results retained by [`memoize`].
+pub const DEFAULT_MEMO_CAPACITY: usize = 256;
+
+thread_local! {
+    static MEMO: RefCell<Memo> = RefCell::new(Memo::default());
+    static MEMO_CAPACITY: Cell<usize> = const { Cell::new(DEFAULT_MEMO_CAPACITY) };
+}
+
+/// Set the maximum number of memoized results. Least-recently-used results are evicted once
+/// the cache is full.
+pub fn set_memo_capacity(capacity: usize) {
+    MEMO_CAPACITY.with(|max| max.set(capacity));
+    MEMO.with(|memo| memo.borrow_mut().evict_to(capacity));
+}
+
+/// Drop every memoized result.
+pub fn reset_memo() {
+    MEMO.with(|memo| *memo.borrow_mut() = Memo::default());
+}
+
+/// Return the result of `compute` for `key`, only calling it if no result for an equal key
+/// (of the same key and result types) is cached.
+///
+/// Results are looked up by a hash of `key` and then compared with it, so `compute` must
+/// depend on nothing but `key`, and a key used for two different computations should include
+/// something to tell them apart, e.g. `("schema", version)`. Keys whose hashes collide share
+/// one slot, so a collision costs a recomputation but never returns another key's result.
+pub fn memoize<K, V, F>(key: K, compute: F) -> V
+where
+    K: Hash + Eq + 'static,
+    V: Clone + 'static,
+    F: FnOnce() -> V,
+{
+    let slot = memo_key::<K, V>(&key);
+
+    let cached = MEMO.with(|memo| {
+        let mut memo = memo.borrow_mut();
+        let (cached_key, value) = memo.get(&slot)?.downcast_ref::<(K, V)>()?;
+        (*cached_key == key).then(|| value.clone())
+    });
+
+    if let Some(value) = cached {
+        return value;
+    }
+
+    // don't hold the borrow while computing, in case `compute` memoizes something itself
+    let value = compute();
+
+    let capacity = MEMO_CAPACITY.with(Cell::get);
+    MEMO.with(|memo| {
+        let result = Box::new((key, value.clone()));
+        memo.borrow_mut().insert(slot, result, capacity)
+    });
+
+    value
+}
+
+type MemoKey = (TypeId, TypeId, u64);
+
+fn memo_key<K: Hash + 'static, V: 'static>(key: &K) -> MemoKey {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (TypeId::of::<K>(), TypeId::of::<V>(), hasher.finish())
+}
+
+#[derive(Default)]
+struct Memo {
+    entries: HashMap<MemoKey, MemoEntry>,
+    clock: u64,
+}
+
+struct MemoEntry {
+    value: Box<dyn Any>,
+    last_used: u64,
+}
+
+impl Memo {
+    fn get(&mut self, key: &MemoKey) -> Option<&dyn Any> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.clock;
+        Some(entry.value.as_ref())
+    }
+
+    fn insert(&mut self, key: MemoKey, value: Box<dyn Any>, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+
+        self.evict_to(capacity - 1);
+        self.clock += 1;
+
+        let entry = MemoEntry {
+            value,
+            last_used: self.clock,
+        };
+
+        self.entries.insert(key, entry);
+    }
+
+    fn evict_to(&mut self, capacity: usize) {
+        while self.entries.len() > capacity {
+            let lru = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key)
+                .expect("memo entry");
+
+            self.entries.remove(&lru);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(calls: &Cell<usize>, n: u64) -> u64 {
+        memoize(("square", n), || {
+            calls.set(calls.get() + 1);
+            n * n
+        })
+    }
+
+    #[test]
+    fn computes_each_key_once() {
+        let calls = Cell::new(0);
+
+        assert_eq!(square(&calls, 3), 9);
+        assert_eq!(square(&calls, 3), 9);
+        assert_eq!(calls.get(), 1);
+
+        assert_eq!(square(&calls, 4), 16);
+        assert_eq!(calls.get(), 2);
+
+        // the same key with a different result type is a different computation
+        let name = memoize(("square", 3u64), || "nine".to_string());
+        assert_eq!(name, "nine");
+    }
+
+    /// A key whose hash is the same for every value.
+    #[derive(PartialEq, Eq)]
+    struct Colliding(u64);
+
+    impl Hash for Colliding {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            0u64.hash(state)
+        }
+    }
+
+    #[test]
+    fn compares_keys_whose_hashes_collide() {
+        let calls = Cell::new(0);
+        let double = |n: u64| {
+            memoize(Colliding(n), || {
+                calls.set(calls.get() + 1);
+                n * 2
+            })
+        };
+
+        assert_eq!(double(1), 2);
+        assert_eq!(double(2), 4);
+        assert_eq!(double(1), 2);
+        assert_eq!(calls.get(), 3);
+
+        assert_eq!(double(1), 2);
+        assert_eq!(calls.get(), 3);
+
+        reset_memo();
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let calls = Cell::new(0);
+
+        set_memo_capacity(2);
+
+        square(&calls, 1);
+        square(&calls, 2);
+        square(&calls, 1);
+        square(&calls, 3);
+        assert_eq!(calls.get(), 3);
+
+        square(&calls, 1);
+        assert_eq!(calls.get(), 3);
+
+        square(&calls, 2);
+        assert_eq!(calls.get(), 4);
+
+        set_memo_capacity(DEFAULT_MEMO_CAPACITY);
+        reset_memo();
+    }
+}
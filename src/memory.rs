@@ -0,0 +1,132 @@
+//! Buffers shared with the host, addressed by the 32-bit pointers of the export ABI.
+//!
+//! On `wasm32` a pointer is simply the buffer's address in linear memory. Off-wasm (e.g.
+//! under `cargo test`) addresses don't fit in 32 bits, so each buffer handed to the "host" is
+//! registered under a 32-bit stand-in address instead. Either way [`crate::alloc`],
+//! [`crate::free`], [`crate::leak_bytes`] and [`crate::abi::borrow_bytes`] take the same
+//! path, so in-process tests exercise the real release path.
+
+#[cfg(target_arch = "wasm32")]
+mod imp {
+    use std::slice;
+
+    pub fn into_ptr(buffer: Box<[u8]>) -> i32 {
+        Box::into_raw(buffer) as *mut u8 as i32
+    }
+
+    pub unsafe fn take(ptr: i32, len: usize) -> Box<[u8]> {
+        unsafe { Box::from_raw(slice::from_raw_parts_mut(ptr as *mut u8, len)) }
+    }
+
+    pub unsafe fn borrow<'a>(ptr: i32, len: usize) -> &'a [u8] {
+        unsafe { slice::from_raw_parts(ptr as *const u8, len) }
+    }
+
+    #[cfg(any(feature = "testing", test))]
+    pub unsafe fn borrow_mut<'a>(ptr: i32, len: usize) -> &'a mut [u8] {
+        unsafe { slice::from_raw_parts_mut(ptr as *mut u8, len) }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod imp {
+    use std::cell::{Cell, RefCell};
+    use std::collections::BTreeMap;
+    use std::slice;
+
+    /// Stand-in addresses start above zero, which is the null pointer of the ABI.
+    const FIRST_PTR: i32 = 8;
+
+    thread_local! {
+        static BUFFERS: RefCell<BTreeMap<i32, Box<[u8]>>> = const { RefCell::new(BTreeMap::new()) };
+        static NEXT_PTR: Cell<i32> = const { Cell::new(FIRST_PTR) };
+    }
+
+    pub fn into_ptr(buffer: Box<[u8]>) -> i32 {
+        // leave a gap after each buffer, so no pointer into one is a pointer into the next
+        let ptr = NEXT_PTR.with(|next| {
+            let ptr = next.get();
+            let end = i32::try_from(buffer.len() + 8).expect("buffer size");
+            next.set(
+                ptr.checked_add(end)
+                    .expect("stand-in address space exhausted"),
+            );
+            ptr
+        });
+
+        BUFFERS.with(|buffers| buffers.borrow_mut().insert(ptr, buffer));
+        ptr
+    }
+
+    pub unsafe fn take(ptr: i32, len: usize) -> Box<[u8]> {
+        let buffer = BUFFERS.with(|buffers| buffers.borrow_mut().remove(&ptr));
+        let buffer = buffer.unwrap_or_else(|| panic!("no buffer at {ptr:#x}"));
+        assert_eq!(
+            buffer.len(),
+            len,
+            "freed {len} bytes of a {} byte buffer",
+            buffer.len()
+        );
+        buffer
+    }
+
+    pub unsafe fn borrow<'a>(ptr: i32, len: usize) -> &'a [u8] {
+        let data = locate(ptr, len);
+
+        // the buffer's contents are heap-allocated, so they stay put until it's taken
+        unsafe { slice::from_raw_parts(data, len) }
+    }
+
+    #[cfg(any(feature = "testing", test))]
+    pub unsafe fn borrow_mut<'a>(ptr: i32, len: usize) -> &'a mut [u8] {
+        let data = locate(ptr, len);
+        unsafe { slice::from_raw_parts_mut(data, len) }
+    }
+
+    /// The address of `ptr` within the registered buffer which holds `len` bytes from it.
+    fn locate(ptr: i32, len: usize) -> *mut u8 {
+        BUFFERS.with(|buffers| {
+            let mut buffers = buffers.borrow_mut();
+            let (start, buffer) = buffers
+                .range_mut(..=ptr)
+                .next_back()
+                .unwrap_or_else(|| panic!("no buffer at {ptr:#x}"));
+
+            let offset = (ptr - start) as usize;
+            assert!(
+                offset + len <= buffer.len(),
+                "{len} bytes at {ptr:#x} overrun a {} byte buffer",
+                buffer.len()
+            );
+
+            buffer[offset..].as_mut_ptr()
+        })
+    }
+}
+
+/// Hand `buffer` to the host, returning its address.
+pub(crate) fn into_ptr(buffer: Box<[u8]>) -> i32 {
+    imp::into_ptr(buffer)
+}
+
+/// Reclaim the buffer of `len` bytes at `ptr` which was handed to the host via [`into_ptr`].
+///
+/// # Safety
+/// The buffer must not be reclaimed twice, nor borrowed after it's reclaimed.
+pub(crate) unsafe fn take(ptr: i32, len: usize) -> Box<[u8]> {
+    unsafe { imp::take(ptr, len) }
+}
+
+/// Borrow `len` bytes at `ptr` without copying them.
+///
+/// # Safety
+/// The bytes must stay alive and unmodified for as long as the slice is used.
+pub(crate) unsafe fn borrow<'a>(ptr: i32, len: usize) -> &'a [u8] {
+    unsafe { imp::borrow(ptr, len) }
+}
+
+/// Write `bytes` at `ptr`, as the host does to a buffer it obtained from [`crate::alloc`].
+#[cfg(any(feature = "testing", test))]
+pub(crate) fn write(ptr: i32, bytes: &[u8]) {
+    unsafe { imp::borrow_mut(ptr, bytes.len()) }.copy_from_slice(bytes);
+}
@@ -0,0 +1,166 @@
+//! Per-route capability queries, so a client (or a proxy answering a browser's `OPTIONS`
+//! request) can discover what a route supports without invoking it.
+//!
+//! By convention a library exports `tc_route_options(path_ptr, path_len) -> i64`, which takes
+//! the UTF-8 path of a route and no header:
+//!
+//! ```ignore
+//! #[unsafe(no_mangle)]
+//! pub extern "C" fn tc_route_options(path_ptr: i32, path_len: i32) -> i64 {
+//!     tc_wasm::route_options(ROUTES, path_ptr, path_len)
+//! }
+//! ```
+
+use destream::en::{self, EncodeMap};
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{
+    Method, RouteExport, borrow_bytes, decode_utf8, encode_json_bytes, is_read_only, respond,
+};
+use crate::codec::SUPPORTED_CODECS;
+
+const METHODS: [Method; 4] = [Method::Get, Method::Put, Method::Post, Method::Delete];
+
+struct RouteOptions<'a> {
+    path: &'a str,
+    methods: Vec<(Method, u32)>,
+}
+
+impl<'en> en::IntoStream<'en> for RouteOptions<'en> {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let methods = self
+            .methods
+            .into_iter()
+            .map(|(method, required_mode)| MethodOptions {
+                method,
+                required_mode,
+            })
+            .collect::<Vec<_>>();
+
+        let content_types = SUPPORTED_CODECS
+            .iter()
+            .map(|(request, response)| ContentTypes {
+                request: request.content_type(),
+                response: response.content_type(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut map = encoder.encode_map(Some(3))?;
+        map.encode_entry("path", self.path)?;
+        map.encode_entry("methods", methods)?;
+        map.encode_entry("content_types", content_types)?;
+        map.end()
+    }
+}
+
+struct MethodOptions {
+    method: Method,
+    required_mode: u32,
+}
+
+impl<'en> en::IntoStream<'en> for MethodOptions {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(2))?;
+        map.encode_entry("method", self.method.to_string())?;
+        map.encode_entry("required_mode", self.required_mode)?;
+        map.end()
+    }
+}
+
+struct ContentTypes {
+    request: &'static str,
+    response: &'static str,
+}
+
+impl<'en> en::IntoStream<'en> for ContentTypes {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(2))?;
+        map.encode_entry("request", self.request)?;
+        map.encode_entry("response", self.response)?;
+        map.end()
+    }
+}
+
+/// Encode `{"path", "methods", "content_types"}` for the route at `path`, where `methods`
+/// lists each `{"method", "required_mode"}` the route serves (every method, for a route
+/// which doesn't declare one, and only GET for a read-only library) and `content_types`
+/// each supported `{"request", "response"}` MIME type pair.
+pub fn route_options_bytes(routes: &[RouteExport], path: &str) -> TCResult<Vec<u8>> {
+    let mut methods = Vec::new();
+    for route in routes.iter().filter(|route| route.path == path) {
+        let served = match route.method() {
+            Some(method) => vec![method],
+            None => METHODS.to_vec(),
+        };
+
+        for method in served {
+            if method == Method::Get || !is_read_only() {
+                methods.push((method, u32::from(route.required_mode())));
+            }
+        }
+    }
+
+    if methods.is_empty() {
+        return Err(TCError::not_found(path));
+    }
+
+    methods.sort_by_key(|(method, _)| *method as i32);
+    encode_json_bytes(RouteOptions { path, methods })
+}
+
+/// Implements the `tc_route_options` export.
+pub fn route_options(routes: &[RouteExport], path_ptr: i32, path_len: i32) -> i64 {
+    let path_bytes = unsafe { borrow_bytes(path_ptr, path_len) };
+    respond(decode_utf8(path_bytes).and_then(|path| route_options_bytes(routes, path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use umask::Mode;
+
+    use crate::claim;
+
+    fn routes() -> Vec<RouteExport> {
+        vec![
+            RouteExport::new("/hello", "hello").with_method(Method::Get),
+            RouteExport::new("/hello", "set_greeting")
+                .with_method(Method::Put)
+                .with_required_mode(Mode::from(claim::WRITE)),
+            RouteExport::new("/echo", "echo"),
+        ]
+    }
+
+    fn options(path: &str) -> TCResult<serde_json::Value> {
+        let options = route_options_bytes(&routes(), path)?;
+        Ok(serde_json::from_slice(&options).expect("options json"))
+    }
+
+    #[test]
+    fn describes_known_route() {
+        let hello = options("/hello").expect("options");
+        assert_eq!(hello["path"], "/hello");
+        assert_eq!(
+            hello["methods"],
+            serde_json::json!([
+                {"method": "GET", "required_mode": 0},
+                {"method": "PUT", "required_mode": claim::WRITE},
+            ])
+        );
+
+        let content_types = hello["content_types"].as_array().expect("content types");
+        assert!(content_types.contains(&serde_json::json!({
+            "request": "application/json",
+            "response": "application/json",
+        })));
+
+        let echo = options("/echo").expect("echo options");
+        assert_eq!(echo["methods"].as_array().map(Vec::len), Some(4));
+    }
+
+    #[test]
+    fn rejects_unknown_route() {
+        assert!(options("/missing").is_err());
+    }
+}
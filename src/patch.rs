@@ -0,0 +1,263 @@
+//! Binary diffs of canonical responses, for libraries serving versioned documents.
+//!
+//! A diff is a sequence of instructions which rebuild the target from the base:
+//!
+//! - `0x00 <offset> <len>` copies `len` bytes of the base starting at `offset`
+//! - `0x01 <len> <bytes>` inserts the next `len` bytes of the diff
+//!
+//! where `offset` and `len` are unsigned LEB128 varints.
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use destream::en;
+use std::collections::HashMap;
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{WasmResponse, canonical_json_bytes};
+use crate::response::content_hash;
+
+const COPY: u8 = 0x00;
+const INSERT: u8 = 0x01;
+
+/// The size of the base blocks indexed when searching for matches.
+const BLOCK_LEN: usize = 16;
+
+/// A response encoded as a diff against a base the host has cached, as the frame
+/// `{"$frame": "patch", "base": "<hash>", "diff": "<base64>"}`, where `base` is the
+/// [`content_hash`] of the base's canonical JSON and `diff` rebuilds this response's
+/// canonical JSON from it (see [`apply_diff`]).
+///
+/// With no base, or when the diff wouldn't be smaller, it's encoded as the full response.
+pub struct Patch<T> {
+    base: Option<T>,
+    value: T,
+}
+
+impl<T> Patch<T> {
+    pub fn new(base: Option<T>, value: T) -> Self {
+        Self { base, value }
+    }
+}
+
+impl<T> WasmResponse for Patch<T>
+where
+    T: for<'en> en::IntoStream<'en>,
+{
+    fn encode(self) -> TCResult<Vec<u8>> {
+        let value = canonical_json_bytes(self.value)?;
+
+        let Some(base) = self.base else {
+            return Ok(value);
+        };
+
+        let base = canonical_json_bytes(base)?;
+        let diff = diff(&base, &value);
+        if diff.len() >= value.len() {
+            return Ok(value);
+        }
+
+        let frame = serde_json::json!({
+            "$frame": "patch",
+            "base": content_hash(&base),
+            "diff": BASE64.encode(diff),
+        });
+
+        serde_json::to_vec(&frame).map_err(TCError::internal)
+    }
+}
+
+/// Compute a diff which rebuilds `target` from `base`.
+pub fn diff(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut blocks = HashMap::new();
+    for offset in (0..base.len().saturating_sub(BLOCK_LEN - 1)).step_by(BLOCK_LEN) {
+        blocks
+            .entry(&base[offset..offset + BLOCK_LEN])
+            .or_insert(offset);
+    }
+
+    let mut diff = Vec::new();
+    let mut literal = 0;
+    let mut position = 0;
+
+    while position + BLOCK_LEN <= target.len() {
+        let Some(&offset) = blocks.get(&target[position..position + BLOCK_LEN]) else {
+            position += 1;
+            continue;
+        };
+
+        // extend the match backward into the pending literal, then forward
+        let mut start = offset;
+        let mut from = position;
+        while from > literal && start > 0 && base[start - 1] == target[from - 1] {
+            start -= 1;
+            from -= 1;
+        }
+
+        let mut end = offset + BLOCK_LEN;
+        position += BLOCK_LEN;
+        while end < base.len() && position < target.len() && base[end] == target[position] {
+            end += 1;
+            position += 1;
+        }
+
+        push_insert(&mut diff, &target[literal..from]);
+        diff.push(COPY);
+        push_varint(&mut diff, start);
+        push_varint(&mut diff, end - start);
+        literal = position;
+    }
+
+    push_insert(&mut diff, &target[literal..]);
+    diff
+}
+
+/// Rebuild the target of `diff` from `base`, as the host does on receiving a [`Patch`].
+pub fn apply_diff(base: &[u8], diff: &[u8]) -> TCResult<Vec<u8>> {
+    let mut target = Vec::with_capacity(base.len());
+    let mut diff = diff;
+
+    while let Some((&op, rest)) = diff.split_first() {
+        diff = rest;
+
+        match op {
+            COPY => {
+                let offset = take_varint(&mut diff)?;
+                let len = take_varint(&mut diff)?;
+                let copied = offset
+                    .checked_add(len)
+                    .and_then(|end| base.get(offset..end))
+                    .ok_or_else(|| TCError::bad_request("diff copies past the end of its base"))?;
+
+                target.extend_from_slice(copied);
+            }
+            INSERT => {
+                let len = take_varint(&mut diff)?;
+                if len > diff.len() {
+                    return Err(TCError::bad_request("truncated diff insert"));
+                }
+
+                let (inserted, rest) = diff.split_at(len);
+                target.extend_from_slice(inserted);
+                diff = rest;
+            }
+            other => {
+                return Err(TCError::bad_request(format!(
+                    "unknown diff instruction {other:#04x}"
+                )));
+            }
+        }
+    }
+
+    Ok(target)
+}
+
+fn push_insert(diff: &mut Vec<u8>, bytes: &[u8]) {
+    if !bytes.is_empty() {
+        diff.push(INSERT);
+        push_varint(diff, bytes.len());
+        diff.extend_from_slice(bytes);
+    }
+}
+
+fn push_varint(diff: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        diff.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+
+    diff.push(n as u8);
+}
+
+fn take_varint(diff: &mut &[u8]) -> TCResult<usize> {
+    let mut n = 0usize;
+
+    for shift in (0..usize::BITS).step_by(7) {
+        let Some((&byte, rest)) = diff.split_first() else {
+            return Err(TCError::bad_request("truncated diff varint"));
+        };
+
+        *diff = rest;
+        n |= ((byte & 0x7f) as usize) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+
+    Err(TCError::bad_request("diff varint overflows"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(revision: u64) -> Vec<(String, u64)> {
+        (0..200)
+            .map(|i| {
+                let revised = if i == 100 { revision } else { 0 };
+                (format!("paragraph {i}"), revised)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn produces_and_applies_patch() {
+        let base = canonical_json_bytes(document(1)).expect("base json");
+        let target = canonical_json_bytes(document(2)).expect("target json");
+
+        let encoded = Patch::new(Some(document(1)), document(2))
+            .encode()
+            .expect("patch");
+
+        let frame: serde_json::Value = serde_json::from_slice(&encoded).expect("frame json");
+        assert_eq!(frame["$frame"], "patch");
+        assert_eq!(frame["base"], content_hash(&base));
+        assert!(encoded.len() < target.len() / 4);
+
+        let diff = BASE64
+            .decode(frame["diff"].as_str().expect("diff"))
+            .expect("base64 diff");
+
+        assert_eq!(apply_diff(&base, &diff).expect("apply"), target);
+    }
+
+    #[test]
+    fn falls_back_to_full_response() {
+        let target = canonical_json_bytes(document(2)).expect("target json");
+
+        let without_base = Patch::new(None, document(2)).encode().expect("full");
+        assert_eq!(without_base, target);
+
+        let unrelated = Patch::new(Some(vec![]), document(2))
+            .encode()
+            .expect("full");
+        assert_eq!(unrelated, target);
+    }
+
+    #[test]
+    fn diff_round_trips() {
+        let cases: [(&[u8], &[u8]); 5] = [
+            (b"", b""),
+            (b"", b"new"),
+            (b"old", b""),
+            (
+                b"0123456789abcdef0123456789abcdef",
+                b"0123456789abcdef!0123456789abcdef",
+            ),
+            (
+                b"the quick brown fox jumps over the lazy dog",
+                b"the lazy dog",
+            ),
+        ];
+
+        for (base, target) in cases {
+            assert_eq!(
+                apply_diff(base, &diff(base, target)).expect("apply"),
+                target
+            );
+        }
+
+        assert!(apply_diff(b"short", &[COPY, 0, 10]).is_err());
+        assert!(apply_diff(b"", &[INSERT, 5, b'a']).is_err());
+        assert!(apply_diff(b"", &[0x02]).is_err());
+    }
+}
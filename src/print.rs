@@ -0,0 +1,72 @@
+//! `print!`-style debugging output for `wasm32-unknown-unknown`, which has no stdout.
+//!
+//! With the `debug-print` feature, [`wasm_print!`](crate::wasm_print) and
+//! [`wasm_eprint!`](crate::wasm_eprint) write each formatted message to the host log (at the
+//! `Info` and `Error` level respectively) and [`install_panic_hook`] logs panic messages
+//! before the instance traps. Without the feature they compile to nothing, so ad-hoc debug
+//! output can't leak into a release build.
+
+use std::fmt;
+
+use crate::host::LogLevel;
+
+/// Format a message like [`print!`] and write it to the host log. A no-op without the
+/// `debug-print` feature.
+#[macro_export]
+macro_rules! wasm_print {
+    ($($arg:tt)*) => {
+        $crate::print::print_to_host($crate::host::LogLevel::Info, format_args!($($arg)*))
+    };
+}
+
+/// Format a message like [`eprint!`] and write it to the host log as an error. A no-op
+/// without the `debug-print` feature.
+#[macro_export]
+macro_rules! wasm_eprint {
+    ($($arg:tt)*) => {
+        $crate::print::print_to_host($crate::host::LogLevel::Error, format_args!($($arg)*))
+    };
+}
+
+#[doc(hidden)]
+pub fn print_to_host(level: LogLevel, args: fmt::Arguments) {
+    #[cfg(any(feature = "debug-print", test))]
+    crate::host::log(level, args.to_string().trim_end_matches('\n'));
+
+    #[cfg(not(any(feature = "debug-print", test)))]
+    let _ = (level, args);
+}
+
+/// Log the message and location of any panic to the host before the instance traps, since
+/// the default hook writes to a stderr which `wasm32-unknown-unknown` doesn't have. Call it
+/// once, e.g. from the library's init export. A no-op without the `debug-print` feature.
+pub fn install_panic_hook() {
+    #[cfg(any(feature = "debug-print", test))]
+    std::panic::set_hook(Box::new(|info| {
+        crate::host::log(LogLevel::Error, &info.to_string());
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::host::LogLevel;
+    use crate::testing::MockHost;
+
+    #[test]
+    fn prints_to_host_log() {
+        let mock = MockHost::new();
+        let _guard = mock.install();
+
+        let name = "world";
+        crate::wasm_print!("hello, {name}\n");
+        crate::wasm_eprint!("{} failed", "greeting");
+
+        assert_eq!(
+            mock.logs(),
+            vec![
+                (LogLevel::Info, "hello, world".to_string()),
+                (LogLevel::Error, "greeting failed".to_string()),
+            ]
+        );
+    }
+}
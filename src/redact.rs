@@ -0,0 +1,265 @@
+//! Claim-dependent views of a response, so a handler can return one [`Value`] and let the
+//! caller's claim mode decide which of its fields they may see.
+//!
+//! A [`Value`] has no map variant, so a *record* is a tuple whose items are all
+//! `(name, value)` pairs with a string name, e.g. `[["name", "Ada"], ["ssn", "..."]]`. Each
+//! rule names a field by its dotted path through nested records (e.g. `"address.street"`)
+//! and the mode bits (e.g. [`crate::claim::WRITE`]) a claim must grant to see it. A rule
+//! applies to every record in a list, and a field the rules don't mention is always visible.
+
+use tc_error::{TCError, TCResult};
+use tc_value::Value;
+use umask::Mode;
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, decode_header_bytes,
+    dispatch_get_decoded, respond,
+};
+use crate::codec::request_body;
+
+/// The fields of a response which only some claims may see.
+#[derive(Clone, Debug, Default)]
+pub struct RedactionRules {
+    rules: Vec<(Vec<String>, Mode)>,
+}
+
+impl RedactionRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only show the field at the dotted `path` to a claim granting every bit of `mode`.
+    pub fn require(mut self, path: &str, mode: Mode) -> Self {
+        let path = path.split('.').map(String::from).collect();
+        self.rules.push((path, mode));
+        self
+    }
+}
+
+/// Remove every field of `value` which `rules` don't permit a claim with `mode` to see.
+pub fn redact_for(value: Value, mode: Mode, rules: &RedactionRules) -> Value {
+    let granted = u32::from(mode);
+    let denied = rules
+        .rules
+        .iter()
+        .map(|(path, required)| (path, u32::from(*required)))
+        .filter(|(_, required)| granted & required != *required)
+        .map(|(path, _)| path.as_slice())
+        .collect::<Vec<_>>();
+
+    if denied.is_empty() {
+        value
+    } else {
+        redact(value, &denied)
+    }
+}
+
+fn redact(value: Value, denied: &[&[String]]) -> Value {
+    let Value::Tuple(items) = value else {
+        return value;
+    };
+
+    let items = items.into_iter().collect::<Vec<_>>();
+    let items = if items.iter().all(is_field) && !items.is_empty() {
+        items
+            .into_iter()
+            .filter_map(|field| redact_field(field, denied))
+            .collect::<Vec<_>>()
+    } else {
+        items
+            .into_iter()
+            .map(|item| redact(item, denied))
+            .collect::<Vec<_>>()
+    };
+
+    Value::Tuple(items.into())
+}
+
+/// Whether `item` is a `(name, value)` field of a record.
+pub(crate) fn is_field(item: &Value) -> bool {
+    match item {
+        Value::Tuple(pair) => pair.len() == 2 && matches!(pair[0], Value::String(_)),
+        _ => false,
+    }
+}
+
+/// Drop a `(name, value)` field if it's denied, otherwise redact its value.
+fn redact_field(field: Value, denied: &[&[String]]) -> Option<Value> {
+    let Value::Tuple(pair) = field else {
+        return Some(field);
+    };
+
+    let mut pair = pair.into_iter();
+    let (name, value) = (pair.next()?, pair.next()?);
+    let Value::String(key) = &name else {
+        return Some(Value::Tuple(vec![name, value].into()));
+    };
+
+    let mut nested = Vec::new();
+    for path in denied {
+        match path.split_first() {
+            Some((first, rest)) if first == key && rest.is_empty() => return None,
+            Some((first, rest)) if first == key => nested.push(rest),
+            _ => {}
+        }
+    }
+
+    let value = if nested.is_empty() {
+        value
+    } else {
+        redact(value, &nested)
+    };
+
+    Some(Value::Tuple(vec![name, value].into()))
+}
+
+/// Like [`crate::dispatch_get`], but redacts the handler's response per `rules` for the
+/// caller's claim mode.
+pub fn dispatch_get_redacted<H, Txn, Req>(
+    rules: &RedactionRules,
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Value, Error = TCError>,
+    Req: WasmRequest,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_redacted_bytes(
+        rules,
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and body, redacting the response for the caller's claim.
+pub fn try_dispatch_get_redacted_bytes<H, Txn, Req>(
+    rules: &RedactionRules,
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Value, Error = TCError>,
+    Req: WasmRequest,
+{
+    let header = decode_header_bytes(header_bytes)?;
+    let mode = header.claim().mode();
+    let txn = Txn::from_wasm_header(header)?;
+    let request = Req::decode(&request_body(body_bytes)?)?;
+    let response = dispatch_get_decoded(handler, &txn, request)?;
+    redact_for(response, mode, rules).encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use pathlink::Link;
+    use std::pin::Pin;
+    use std::str::FromStr;
+
+    use crate::claim;
+    use crate::test_support::{FakeTxn, txn_header_bytes_for};
+
+    fn field(name: &str, value: Value) -> Value {
+        Value::Tuple(vec![Value::from(name), value].into())
+    }
+
+    fn record(fields: Vec<Value>) -> Value {
+        Value::Tuple(fields.into())
+    }
+
+    fn user() -> Value {
+        record(vec![
+            field("name", Value::from("Ada")),
+            field("ssn", Value::from("078-05-1120")),
+            field(
+                "address",
+                record(vec![
+                    field("city", Value::from("London")),
+                    field("street", Value::from("St James's Square")),
+                ]),
+            ),
+        ])
+    }
+
+    fn rules() -> RedactionRules {
+        RedactionRules::new()
+            .require("ssn", Mode::from(claim::WRITE))
+            .require("address.street", Mode::from(claim::WRITE))
+    }
+
+    struct UserHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for UserHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(
+            &'a self,
+            _txn: &'a FakeTxn,
+            _request: Self::Request,
+        ) -> TCResult<Self::Fut<'a>> {
+            Ok(Box::pin(async move { Ok(user()) }))
+        }
+    }
+
+    #[test]
+    fn redacts_fields_for_read_only_claims() {
+        let expected = record(vec![
+            field("name", Value::from("Ada")),
+            field(
+                "address",
+                record(vec![field("city", Value::from("London"))]),
+            ),
+        ]);
+
+        assert_eq!(
+            redact_for(user(), Mode::from(claim::READ), &rules()),
+            expected
+        );
+
+        let list = Value::Tuple(vec![user(), user()].into());
+        assert_eq!(
+            redact_for(list, Mode::from(claim::READ), &rules()),
+            Value::Tuple(vec![expected.clone(), expected].into())
+        );
+
+        assert_eq!(redact_for(user(), Mode::all(), &rules()), user());
+    }
+
+    #[test]
+    fn dispatch_redacts_for_caller_claim() {
+        let link = Link::from_str("/lib").expect("link");
+
+        let get = |claim| {
+            let response = try_dispatch_get_redacted_bytes::<_, FakeTxn, Value>(
+                &rules(),
+                &UserHandler,
+                &txn_header_bytes_for(claim),
+                &[],
+            )
+            .expect("user");
+
+            Value::decode(&response).expect("decode user")
+        };
+
+        let redacted = get(claim::read_only(link.clone()));
+        assert!(!format!("{redacted:?}").contains("078-05-1120"));
+
+        assert_eq!(get(claim::full(link)), user());
+    }
+}
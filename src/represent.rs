@@ -0,0 +1,339 @@
+//! Handler-aware output formats, so one handler can serve e.g. JSON and CSV of the same data
+//! and the client picks via the header's optional `"accept"` field: a comma-separated list of
+//! format names in order of preference, like HTTP's `Accept` (e.g. `"csv, json"`).
+//!
+//! JSON responses are sent as usual. Any other format is sent as the frame
+//! `{"$frame": "representation", "format": "<name>", "body": "<base64>"}`, so the host can
+//! set the content type and relay the bytes.
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use destream::en::{self, EncodeMap};
+use tc_error::{TCError, TCResult};
+use tc_value::Value;
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, decode_header_bytes,
+    decode_serde_json, dispatch_get_decoded, encode_json_bytes, respond, take_header_field,
+};
+use crate::codec::request_body;
+use crate::redact::is_field;
+
+/// Encodes a handler's response in one output format.
+pub type RepresentationEncoder = fn(Value) -> TCResult<Vec<u8>>;
+
+/// The output formats a route can produce, in the order used when the client accepts any.
+#[derive(Clone)]
+pub struct Representations {
+    encoders: Vec<(&'static str, RepresentationEncoder)>,
+}
+
+impl Default for Representations {
+    /// `json` and `csv`.
+    fn default() -> Self {
+        Self::new()
+            .with("json", json_representation)
+            .with("csv", csv_representation)
+    }
+}
+
+impl Representations {
+    /// An empty set, to which the route's formats are added via [`Self::with`].
+    pub fn new() -> Self {
+        Self {
+            encoders: Vec::new(),
+        }
+    }
+
+    /// Register (or replace) the encoder for the format `name`.
+    pub fn with(mut self, name: &'static str, encoder: RepresentationEncoder) -> Self {
+        match self.encoders.iter_mut().find(|(format, _)| *format == name) {
+            Some(existing) => existing.1 = encoder,
+            None => self.encoders.push((name, encoder)),
+        }
+
+        self
+    }
+
+    /// Pick the first format in the client's `accept` list which this set can produce. `*`
+    /// (or no list at all) accepts the first registered format.
+    pub fn select(&self, accept: Option<&str>) -> TCResult<(&'static str, RepresentationEncoder)> {
+        let accepted = accept
+            .into_iter()
+            .flat_map(|accept| accept.split(','))
+            .map(str::trim)
+            .filter(|format| !format.is_empty())
+            .collect::<Vec<_>>();
+
+        let preferred = if accepted.is_empty() {
+            self.encoders.first()
+        } else {
+            accepted.iter().find_map(|accepted| {
+                self.encoders
+                    .iter()
+                    .find(|(format, _)| *accepted == "*" || format == accepted)
+            })
+        };
+
+        preferred.copied().ok_or_else(|| {
+            let available = self
+                .encoders
+                .iter()
+                .map(|(format, _)| *format)
+                .collect::<Vec<_>>();
+
+            TCError::bad_request(format!(
+                "none of the accepted formats ({}) is available (available: {})",
+                accepted.join(", "),
+                available.join(", ")
+            ))
+        })
+    }
+}
+
+/// Encode a response as JSON.
+pub fn json_representation(value: Value) -> TCResult<Vec<u8>> {
+    value.encode()
+}
+
+/// Encode a tabular response as CSV (RFC 4180). `value` must be a list of rows, each either
+/// a record of `(name, value)` pairs (as in [`crate::redact`]), in which case the first
+/// row's names form a header line, or a plain tuple of cells. Cells must be scalars.
+pub fn csv_representation(value: Value) -> TCResult<Vec<u8>> {
+    let Value::Tuple(rows) = value else {
+        return Err(TCError::internal("a CSV response must be a list of rows"));
+    };
+
+    let mut csv = String::new();
+    let mut columns: Option<Vec<String>> = None;
+
+    for row in rows {
+        let Value::Tuple(cells) = row else {
+            return Err(TCError::internal(
+                "each row of a CSV response must be a tuple",
+            ));
+        };
+
+        let cells = cells.into_iter().collect::<Vec<_>>();
+        let cells = if !cells.is_empty() && cells.iter().all(is_field) {
+            let (names, cells) = cells
+                .into_iter()
+                .map(split_field)
+                .unzip::<_, _, Vec<_>, Vec<_>>();
+
+            match &columns {
+                Some(columns) if *columns != names => {
+                    return Err(TCError::internal(format!(
+                        "CSV row has columns {names:?} but the header is {columns:?}"
+                    )));
+                }
+                Some(_) => {}
+                None => {
+                    write_csv_line(&mut csv, names.iter().map(String::as_str));
+                    columns = Some(names);
+                }
+            }
+
+            cells
+        } else {
+            cells
+        };
+
+        let cells = cells
+            .into_iter()
+            .map(csv_cell)
+            .collect::<TCResult<Vec<_>>>()?;
+        write_csv_line(&mut csv, cells.iter().map(String::as_str));
+    }
+
+    Ok(csv.into_bytes())
+}
+
+fn split_field(field: Value) -> (String, Value) {
+    let Value::Tuple(pair) = field else {
+        unreachable!("checked by is_field")
+    };
+
+    let mut pair = pair.into_iter();
+    match (pair.next(), pair.next()) {
+        (Some(Value::String(name)), Some(value)) => (name, value),
+        _ => unreachable!("checked by is_field"),
+    }
+}
+
+fn csv_cell(value: Value) -> TCResult<String> {
+    match value {
+        Value::None => Ok(String::new()),
+        Value::String(cell) => Ok(cell),
+        Value::Tuple(_) => Err(TCError::internal("CSV cells must be scalars")),
+        other => match decode_serde_json(&encode_json_bytes(other)?)? {
+            serde_json::Value::String(cell) => Ok(cell),
+            cell => Ok(cell.to_string()),
+        },
+    }
+}
+
+fn write_csv_line<'a>(csv: &mut String, cells: impl Iterator<Item = &'a str>) {
+    for (i, cell) in cells.enumerate() {
+        if i > 0 {
+            csv.push(',');
+        }
+
+        if cell.contains([',', '"', '\r', '\n']) {
+            csv.push('"');
+            csv.push_str(&cell.replace('"', "\"\""));
+            csv.push('"');
+        } else {
+            csv.push_str(cell);
+        }
+    }
+
+    csv.push_str("\r\n");
+}
+
+struct Represented {
+    format: &'static str,
+    body: Vec<u8>,
+}
+
+impl<'en> en::IntoStream<'en> for Represented {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(3))?;
+        map.encode_entry("$frame", "representation")?;
+        map.encode_entry("format", self.format)?;
+        map.encode_entry("body", BASE64.encode(&self.body))?;
+        map.end()
+    }
+}
+
+/// Like [`crate::dispatch_get`], but encodes the response in the first format in the
+/// header's optional `"accept"` list which `representations` can produce.
+pub fn dispatch_get_negotiated<H, Txn, Req>(
+    representations: &Representations,
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Value, Error = TCError>,
+    Req: WasmRequest,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_negotiated_bytes(
+        representations,
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and body, encoding the response in the negotiated format.
+/// If no accepted format is available the request is rejected before the handler is called.
+pub fn try_dispatch_get_negotiated_bytes<H, Txn, Req>(
+    representations: &Representations,
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Value, Error = TCError>,
+    Req: WasmRequest,
+{
+    let (accept, header_bytes) = take_header_field(header_bytes, "accept")?;
+    let (format, encoder) = representations.select(accept.as_deref())?;
+
+    let header = decode_header_bytes(&header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+    let request = Req::decode(&request_body(body_bytes)?)?;
+    let body = encoder(dispatch_get_decoded(handler, &txn, request)?)?;
+
+    if format == "json" {
+        Ok(body)
+    } else {
+        encode_json_bytes(Represented { format, body })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+
+    use crate::test_support::{FakeTxn, txn_header_bytes, txn_header_bytes_with_field};
+
+    fn field(name: &str, value: Value) -> Value {
+        Value::Tuple(vec![Value::from(name), value].into())
+    }
+
+    fn row(name: &str, note: &str) -> Value {
+        Value::Tuple(
+            vec![
+                field("name", Value::from(name)),
+                field("note", Value::from(note)),
+            ]
+            .into(),
+        )
+    }
+
+    struct TableHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for TableHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(
+            &'a self,
+            _txn: &'a FakeTxn,
+            _request: Self::Request,
+        ) -> TCResult<Self::Fut<'a>> {
+            let table = vec![row("Ada", "first"), row("Grace", "said \"hi\", then left")];
+            Ok(Box::pin(async move { Ok(Value::Tuple(table.into())) }))
+        }
+    }
+
+    fn get(header: &[u8]) -> TCResult<Vec<u8>> {
+        try_dispatch_get_negotiated_bytes::<_, FakeTxn, Value>(
+            &Representations::default(),
+            &TableHandler,
+            header,
+            &[],
+        )
+    }
+
+    #[test]
+    fn negotiates_json_or_csv() {
+        let json = get(&txn_header_bytes_with_field("accept", "json, csv")).expect("json");
+        assert!(Value::decode(&json).is_ok());
+        assert_eq!(get(&txn_header_bytes()).expect("default"), json);
+
+        let frame = get(&txn_header_bytes_with_field("accept", "text/html, csv")).expect("csv");
+        let frame: serde_json::Value = serde_json::from_slice(&frame).expect("frame");
+        assert_eq!(frame["$frame"], "representation");
+        assert_eq!(frame["format"], "csv");
+
+        let csv = BASE64
+            .decode(frame["body"].as_str().expect("body"))
+            .expect("base64");
+
+        assert_eq!(
+            String::from_utf8(csv).expect("utf8"),
+            "name,note\r\nAda,first\r\nGrace,\"said \"\"hi\"\", then left\"\r\n"
+        );
+    }
+
+    #[test]
+    fn rejects_unavailable_formats() {
+        let err = get(&txn_header_bytes_with_field("accept", "xml")).expect_err("xml");
+        assert!(err.to_string().contains("available: json, csv"), "{err}");
+    }
+}
@@ -0,0 +1,854 @@
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, canonicalize, decode_header_bytes,
+    decode_serde_json, dispatch_get_decoded, respond, try_dispatch_get_bytes,
+};
+use crate::codec::request_body;
+use crate::host;
+use crate::response::content_hash;
+
+thread_local! {
+    static REQUEST_META: RefCell<Option<RequestMeta>> = const { RefCell::new(None) };
+    static DECODE_WARNINGS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Routing metadata attached to an [`Envelope`] by the host.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct RequestMeta {
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub trace_id: Option<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A request wrapped in an envelope: `{"meta": {...}, "body": <T>}`.
+pub struct Envelope<T> {
+    pub meta: RequestMeta,
+    pub body: T,
+}
+
+impl<T: WasmRequest> WasmRequest for Envelope<T> {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        #[derive(Deserialize)]
+        struct Frame {
+            #[serde(default)]
+            meta: RequestMeta,
+            #[serde(default)]
+            body: Option<serde_json::Value>,
+        }
+
+        let frame: Frame = decode_serde_json(bytes)?;
+
+        let body = match frame.body {
+            Some(body) => serde_json::to_vec(&body).map_err(TCError::bad_request)?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            meta: frame.meta,
+            body: T::decode(&body)?,
+        })
+    }
+}
+
+/// The metadata of the [`Envelope`] currently being handled by [`dispatch_get_enveloped`].
+pub fn request_meta() -> Option<RequestMeta> {
+    REQUEST_META.with(|meta| meta.borrow().clone())
+}
+
+/// Like [`crate::dispatch_get`], but decodes the body as an [`Envelope`] and passes only the
+/// inner body to the handler. The envelope metadata is available via [`request_meta`] for
+/// the duration of the call.
+pub fn dispatch_get_enveloped<H, Txn, Req, Res>(
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Res, Error = TCError>,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_enveloped_bytes(
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+fn try_dispatch_get_enveloped_bytes<H, Txn, Req, Res>(
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Res, Error = TCError>,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let header = decode_header_bytes(header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+    let envelope = Envelope::<Req>::decode(&request_body(body_bytes)?)?;
+
+    let _meta = MetaGuard::set(envelope.meta);
+    dispatch_get_decoded(handler, &txn, envelope.body)?.encode()
+}
+
+/// The encoding of a request body, as guessed by [`sniff_body_format`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BodyFormat {
+    Json,
+    Form,
+    Text,
+}
+
+/// Guess a body's encoding: JSON if its first non-whitespace byte is `{`, `[` or `"`,
+/// form-encoding if it looks like `key=value&...`, and raw text otherwise.
+pub fn sniff_body_format(body: &[u8]) -> BodyFormat {
+    match body.iter().find(|byte| !byte.is_ascii_whitespace()) {
+        Some(b'{' | b'[' | b'"') => BodyFormat::Json,
+        Some(_) if is_form(body) => BodyFormat::Form,
+        _ => BodyFormat::Text,
+    }
+}
+
+fn is_form(body: &[u8]) -> bool {
+    body.split(|byte| *byte == b'&').all(|pair| {
+        let key_len = pair.iter().position(|byte| *byte == b'=');
+        key_len.is_some_and(|len| len > 0)
+            && pair
+                .iter()
+                .all(|byte| byte.is_ascii_graphic() && *byte != b'"')
+    })
+}
+
+/// Like [`crate::dispatch_get`], but accepts a JSON, form-encoded, or raw text body (see
+/// [`sniff_body_format`]). A form body reaches the handler as a JSON object of strings and a
+/// text body as a JSON string.
+pub fn dispatch_get_sniffed<H, Txn, Req, Res>(
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Res, Error = TCError>,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_sniffed_bytes(
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and a body in any format [`sniff_body_format`] recognizes.
+pub fn try_dispatch_get_sniffed_bytes<H, Txn, Req, Res>(
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Res, Error = TCError>,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let json = if body_bytes.is_empty() {
+        Cow::Borrowed(body_bytes)
+    } else {
+        match sniff_body_format(body_bytes) {
+            BodyFormat::Json => Cow::Borrowed(body_bytes),
+            BodyFormat::Form => Cow::Owned(form_json(body_bytes)?),
+            BodyFormat::Text => Cow::Owned(text_json(body_bytes)?),
+        }
+    };
+
+    try_dispatch_get_bytes(handler, header_bytes, &json)
+}
+
+/// Re-encode a `key=value&...` body as a JSON object. A repeated key keeps its last value.
+fn form_json(body: &[u8]) -> TCResult<Vec<u8>> {
+    let form = body
+        .split(|byte| *byte == b'&')
+        .map(|pair| {
+            let split = pair
+                .iter()
+                .position(|byte| *byte == b'=')
+                .unwrap_or(pair.len());
+            let (key, value) = pair.split_at(split);
+            let value = value.get(1..).unwrap_or_default();
+            Ok((percent_decode(key)?, percent_decode(value)?.into()))
+        })
+        .collect::<TCResult<serde_json::Map<String, serde_json::Value>>>()?;
+
+    serde_json::to_vec(&form).map_err(TCError::bad_request)
+}
+
+/// Re-encode a raw text body as a JSON string.
+fn text_json(body: &[u8]) -> TCResult<Vec<u8>> {
+    let text = std::str::from_utf8(body)
+        .map_err(|err| TCError::bad_request(format!("invalid utf-8 string: {err}")))?;
+
+    serde_json::to_vec(text).map_err(TCError::bad_request)
+}
+
+fn percent_decode(encoded: &[u8]) -> TCResult<String> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.iter();
+
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = [*bytes.next().unwrap_or(&0), *bytes.next().unwrap_or(&0)];
+                let byte = std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| TCError::bad_request("invalid percent-encoding in form body"))?;
+
+                decoded.push(byte);
+            }
+            byte => decoded.push(*byte),
+        }
+    }
+
+    String::from_utf8(decoded).map_err(TCError::bad_request)
+}
+
+/// Like [`crate::dispatch_get`], but moves each of the header's `fields` (e.g. `"tenant"`)
+/// into the body, which must be a JSON object (or empty), so the handler decodes one request
+/// carrying both. A field present in both the header and the body is rejected as a bad
+/// request; a field absent from the header is left to the request's own decoder.
+pub fn dispatch_get_merged<H, Txn, Req, Res>(
+    fields: &[&str],
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Res, Error = TCError>,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_merged_bytes(
+        fields,
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and body, merging the header's `fields` into the body.
+pub fn try_dispatch_get_merged_bytes<H, Txn, Req, Res>(
+    fields: &[&str],
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Res, Error = TCError>,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let (header_bytes, body_bytes) = merge_header_fields(fields, header_bytes, body_bytes)?;
+    try_dispatch_get_bytes(handler, &header_bytes, &body_bytes)
+}
+
+/// Move the header's `fields` into the body object, returning the new header and body.
+fn merge_header_fields(
+    fields: &[&str],
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<(Vec<u8>, Vec<u8>)> {
+    let mut header: serde_json::Value = decode_serde_json(header_bytes)?;
+    let header_fields = header
+        .as_object_mut()
+        .ok_or_else(|| TCError::bad_request("transaction header must be a JSON object"))?;
+
+    let mut body = if body_bytes.is_empty() {
+        serde_json::Map::new()
+    } else {
+        match decode_serde_json(body_bytes)? {
+            serde_json::Value::Object(body) => body,
+            _ => {
+                return Err(TCError::bad_request(
+                    "a merged request must be a JSON object",
+                ));
+            }
+        }
+    };
+
+    for field in fields {
+        let Some(value) = header_fields.remove(*field) else {
+            continue;
+        };
+
+        if body.contains_key(*field) {
+            return Err(TCError::bad_request(format!(
+                "{field} is set in both the header and the body"
+            )));
+        }
+
+        body.insert(field.to_string(), value);
+    }
+
+    let header_bytes = serde_json::to_vec(&header).map_err(TCError::bad_request)?;
+    let body_bytes = serde_json::to_vec(&body).map_err(TCError::bad_request)?;
+    Ok((header_bytes, body_bytes))
+}
+
+/// The top-level fields a structured request may carry, for [`Strict`] decoding.
+pub trait RequestFields {
+    const FIELDS: &'static [&'static str];
+}
+
+/// A request which rejects top-level fields its type doesn't declare (see [`RequestFields`]),
+/// so a client's typo fails instead of being silently ignored. Plain requests stay permissive.
+pub struct Strict<T>(pub T);
+
+impl<T: WasmRequest + RequestFields> WasmRequest for Strict<T> {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        deny_unknown_fields(bytes, T::FIELDS)?;
+        T::decode(bytes).map(Self)
+    }
+}
+
+/// Fail with `unknown field: <name>` if `bytes` is a JSON object with a field not in `fields`.
+/// Other bodies (including an empty one) are left for the request's own decoder to judge.
+pub fn deny_unknown_fields(bytes: &[u8], fields: &[&str]) -> TCResult<()> {
+    if sniff_body_format(bytes) != BodyFormat::Json {
+        return Ok(());
+    }
+
+    let json: serde_json::Value = decode_serde_json(bytes)?;
+    let Some(object) = json.as_object() else {
+        return Ok(());
+    };
+
+    match object.keys().find(|key| !fields.contains(&key.as_str())) {
+        Some(unknown) => Err(TCError::bad_request(format!("unknown field: {unknown}"))),
+        None => Ok(()),
+    }
+}
+
+/// A request decoded from lenient JSON5 (allowing comments, trailing commas, unquoted keys
+/// and single-quoted strings), for developer-authored bodies such as config. Data routes
+/// should keep to strict JSON. Requires the `json5` feature.
+#[cfg(feature = "json5")]
+pub struct Json5Request<T>(pub T);
+
+#[cfg(feature = "json5")]
+impl<T: serde::de::DeserializeOwned> WasmRequest for Json5Request<T> {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        let text = crate::abi::decode_utf8(bytes)?;
+        json5::from_str(text)
+            .map(Self)
+            .map_err(|err| TCError::bad_request(format!("invalid JSON5: {err}")))
+    }
+}
+
+/// A request along with the non-fatal issues its decoder reported via [`decode_warning`]
+/// (e.g. a deprecated field or a coerced type), so the handler can act on them or echo them
+/// back in a [`crate::Warned`] response.
+pub struct WithWarnings<T> {
+    pub request: T,
+    pub warnings: Vec<String>,
+}
+
+impl<T: WasmRequest> WasmRequest for WithWarnings<T> {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        let outer = DECODE_WARNINGS.with(|warnings| warnings.replace(Some(Vec::new())));
+        let request = T::decode(bytes);
+        let warnings = DECODE_WARNINGS.with(|warnings| warnings.replace(outer));
+
+        Ok(Self {
+            request: request?,
+            warnings: warnings.unwrap_or_default(),
+        })
+    }
+}
+
+/// Report a recoverable issue with the request being decoded, from a [`WasmRequest`] impl.
+/// The warning is collected if the request is being decoded as part of a [`WithWarnings`],
+/// and ignored otherwise.
+pub fn decode_warning(warning: impl Into<String>) {
+    DECODE_WARNINGS.with(|warnings| {
+        if let Some(warnings) = warnings.borrow_mut().as_mut() {
+            warnings.push(warning.into());
+        }
+    })
+}
+
+/// The size of the length prefix of a [`Framed`] request body.
+pub const FRAME_PREFIX_LEN: usize = 4;
+
+/// A request body prefixed by its own length (a little-endian `u32`), which the host writes
+/// so that a buffer it only partially populated fails loudly instead of decoding garbage.
+pub struct Framed<T>(pub T);
+
+impl<T: WasmRequest> WasmRequest for Framed<T> {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        unframe_body(bytes).and_then(T::decode).map(Self)
+    }
+}
+
+/// Prefix `body` with its length, as expected by [`Framed`].
+pub fn frame_body(body: &[u8]) -> TCResult<Vec<u8>> {
+    let len =
+        u32::try_from(body.len()).map_err(|_| TCError::bad_request("framed body is too large"))?;
+
+    let mut framed = Vec::with_capacity(FRAME_PREFIX_LEN + body.len());
+    framed.extend_from_slice(&len.to_le_bytes());
+    framed.extend_from_slice(body);
+    Ok(framed)
+}
+
+/// Strip the length prefix from a [`Framed`] body, failing if the prefix is missing or
+/// doesn't match the number of bytes which follow it.
+pub fn unframe_body(bytes: &[u8]) -> TCResult<&[u8]> {
+    let Some((prefix, body)) = bytes.split_first_chunk::<FRAME_PREFIX_LEN>() else {
+        return Err(TCError::bad_request(format!(
+            "framed body of {} bytes is missing its length prefix",
+            bytes.len()
+        )));
+    };
+
+    let expected = u32::from_le_bytes(*prefix) as usize;
+    if expected == body.len() {
+        Ok(body)
+    } else {
+        Err(TCError::bad_request(format!(
+            "framed body declares {expected} bytes but {} were received",
+            body.len()
+        )))
+    }
+}
+
+/// A request which may be sent inline or, if it's large, as a reference to a value the host
+/// holds: `{"$hash": "<sha256 hex>"}`, where the hash is the [`content_hash`] of the value's
+/// canonical JSON. A referenced value is fetched via the host's load import, checked against
+/// its hash, and then decoded as `T`.
+pub struct ByHash<T>(pub T);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HashRef {
+    #[serde(rename = "$hash")]
+    hash: String,
+}
+
+impl<T: WasmRequest> WasmRequest for ByHash<T> {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        let hash_ref = if bytes.trim_ascii_start().starts_with(b"{") {
+            serde_json::from_slice::<HashRef>(bytes).ok()
+        } else {
+            None
+        };
+
+        let Some(HashRef { hash }) = hash_ref else {
+            return T::decode(bytes).map(Self);
+        };
+
+        let valid = hash.len() == 64
+            && hash
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+
+        if !valid {
+            return Err(TCError::bad_request(format!("invalid value hash {hash:?}")));
+        }
+
+        let json = host::load_json(&hash)?;
+        let value: serde_json::Value =
+            serde_json::from_slice(&json).map_err(TCError::bad_gateway)?;
+        let canonical = serde_json::to_vec(&canonicalize(value)).map_err(TCError::internal)?;
+
+        if content_hash(&canonical) == hash {
+            T::decode(&json).map(Self)
+        } else {
+            Err(TCError::bad_gateway(format!(
+                "the host's value for {hash} doesn't match its hash"
+            )))
+        }
+    }
+}
+
+/// Clears the current [`RequestMeta`] when dropped, even if the handler fails.
+struct MetaGuard;
+
+impl MetaGuard {
+    fn set(meta: RequestMeta) -> Self {
+        REQUEST_META.with(|current| *current.borrow_mut() = Some(meta));
+        Self
+    }
+}
+
+impl Drop for MetaGuard {
+    fn drop(&mut self) {
+        REQUEST_META.with(|current| current.borrow_mut().take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+    use tc_value::Value;
+
+    use crate::abi::canonical_json_bytes;
+    use crate::response::Warned;
+    use crate::test_support::{FakeTxn, txn_header_bytes, txn_header_bytes_with_field};
+    use crate::testing::MockHost;
+
+    struct TraceHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for TraceHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let trace_id = request_meta()
+                .and_then(|meta| meta.trace_id)
+                .unwrap_or_default();
+
+            Ok(Box::pin(async move {
+                Ok(Value::String(format!("{trace_id}:{request:?}")))
+            }))
+        }
+    }
+
+    const ENVELOPE: &[u8] =
+        br#"{"meta": {"request_id": "r-1", "trace_id": "t-1", "tenant": "acme"}, "body": "hi"}"#;
+
+    #[test]
+    fn decodes_envelope() {
+        let envelope = Envelope::<String>::decode(ENVELOPE).expect("envelope");
+
+        assert_eq!(envelope.meta.request_id.as_deref(), Some("r-1"));
+        assert_eq!(envelope.meta.trace_id.as_deref(), Some("t-1"));
+        assert_eq!(envelope.meta.extra["tenant"], "acme");
+        assert_eq!(envelope.body, "hi");
+    }
+
+    #[test]
+    fn dispatch_exposes_meta_to_handler() {
+        let response = try_dispatch_get_enveloped_bytes::<_, FakeTxn, Value, Value>(
+            &TraceHandler,
+            &txn_header_bytes(),
+            ENVELOPE,
+        )
+        .expect("enveloped response");
+
+        let response = Value::decode(&response).expect("decode response");
+        let expected = format!("t-1:{:?}", Value::from("hi"));
+        assert_eq!(response, Value::String(expected));
+        assert_eq!(request_meta(), None);
+    }
+
+    /// Accepts `{"name": ...}` or a bare name.
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Greeting {
+        Named { name: String },
+        Plain(String),
+    }
+
+    impl WasmRequest for Greeting {
+        fn decode(bytes: &[u8]) -> TCResult<Self> {
+            decode_serde_json(bytes)
+        }
+    }
+
+    struct GreetingHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for GreetingHandler {
+        type Request = Greeting;
+        type RequestContext = ();
+        type Response = String;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let name = match request {
+                Greeting::Named { name } | Greeting::Plain(name) => name,
+            };
+
+            Ok(Box::pin(async move { Ok(format!("Hello, {name}!")) }))
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct StrictGreeting {
+        name: String,
+    }
+
+    impl WasmRequest for StrictGreeting {
+        fn decode(bytes: &[u8]) -> TCResult<Self> {
+            decode_serde_json(bytes)
+        }
+    }
+
+    impl RequestFields for StrictGreeting {
+        const FIELDS: &'static [&'static str] = &["name"];
+    }
+
+    #[test]
+    fn strict_request_rejects_unknown_fields() {
+        let typo = br#"{"name": "Ada", "nmae": "Ada"}"#;
+
+        let greeting = StrictGreeting::decode(typo).expect("permissive by default");
+        assert_eq!(greeting.name, "Ada");
+
+        let err = Strict::<StrictGreeting>::decode(typo).expect_err("strict");
+        assert!(err.to_string().contains("unknown field: nmae"));
+
+        let Strict(greeting) =
+            Strict::<StrictGreeting>::decode(br#"{"name": "Ada"}"#).expect("strict");
+        assert_eq!(greeting.name, "Ada");
+    }
+
+    #[cfg(feature = "json5")]
+    #[test]
+    fn json5_request_allows_comments_and_trailing_commas() {
+        let body = br#"{
+            // who to greet
+            name: "Ada",
+        }"#;
+
+        assert!(StrictGreeting::decode(body).is_err());
+
+        let Json5Request(greeting) = Json5Request::<StrictGreeting>::decode(body).expect("json5");
+        assert_eq!(greeting.name, "Ada");
+
+        let err = Json5Request::<StrictGreeting>::decode(b"{name: ").expect_err("truncated");
+        assert!(err.to_string().contains("invalid JSON5"));
+    }
+
+    #[test]
+    fn framed_request_checks_length() {
+        let body = br#"{"name": "Ada"}"#;
+
+        let framed = frame_body(body).expect("frame");
+        let Framed(greeting) = Framed::<StrictGreeting>::decode(&framed).expect("framed");
+        assert_eq!(greeting.name, "Ada");
+
+        let mut truncated = framed.clone();
+        truncated.truncate(framed.len() - 3);
+        let err = Framed::<StrictGreeting>::decode(&truncated).expect_err("short frame");
+        assert!(
+            err.to_string()
+                .contains("declares 15 bytes but 12 were received")
+        );
+
+        let mut padded = framed;
+        padded.extend_from_slice(b"\0\0");
+        assert!(Framed::<StrictGreeting>::decode(&padded).is_err());
+
+        let err = Framed::<StrictGreeting>::decode(b"{}").expect_err("no prefix");
+        assert!(err.to_string().contains("missing its length prefix"));
+    }
+
+    #[test]
+    fn sniffs_body_format() {
+        let greet = |body: &[u8]| {
+            let response = try_dispatch_get_sniffed_bytes::<_, FakeTxn, Greeting, String>(
+                &GreetingHandler,
+                &txn_header_bytes(),
+                body,
+            )
+            .expect("greeting");
+
+            String::decode(&response).expect("decode response")
+        };
+
+        assert_eq!(
+            sniff_body_format(br#"  {"name": "Ada Lovelace"}"#),
+            BodyFormat::Json
+        );
+        assert_eq!(
+            greet(br#"  {"name": "Ada Lovelace"}"#),
+            "Hello, Ada Lovelace!"
+        );
+
+        assert_eq!(
+            sniff_body_format(b"name=Ada+Lovelace&x=%21"),
+            BodyFormat::Form
+        );
+        assert_eq!(greet(b"name=Ada+Lovelace&x=%21"), "Hello, Ada Lovelace!");
+
+        assert_eq!(sniff_body_format(b"Ada Lovelace"), BodyFormat::Text);
+        assert_eq!(greet(b"Ada Lovelace"), "Hello, Ada Lovelace!");
+
+        // ambiguous input is treated as text
+        assert_eq!(sniff_body_format(b"a = b"), BodyFormat::Text);
+        assert_eq!(sniff_body_format(b"=b"), BodyFormat::Text);
+    }
+
+    /// A profile whose `nick` field was renamed to `nickname`.
+    #[derive(Deserialize)]
+    struct Profile {
+        nickname: Option<String>,
+        nick: Option<String>,
+    }
+
+    impl WasmRequest for Profile {
+        fn decode(bytes: &[u8]) -> TCResult<Self> {
+            let profile: Self = decode_serde_json(bytes)?;
+            if profile.nick.is_some() {
+                decode_warning("nick is deprecated, use nickname");
+            }
+
+            Ok(profile)
+        }
+    }
+
+    struct ProfileHandler;
+
+    impl tc_ir::HandlePut<FakeTxn> for ProfileHandler {
+        type Request = WithWarnings<Profile>;
+        type RequestContext = ();
+        type Response = Warned<Value>;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn put<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let WithWarnings { request, warnings } = request;
+            let nickname = request.nickname.or(request.nick).unwrap_or_default();
+
+            let response = warnings
+                .into_iter()
+                .fold(Warned::new(Value::from(nickname)), Warned::warn);
+
+            Ok(Box::pin(async move { Ok(response) }))
+        }
+    }
+
+    #[test]
+    fn collects_decode_warnings() {
+        let response = crate::abi::try_dispatch_put_bytes::<_, FakeTxn, _, Warned<Value>>(
+            &ProfileHandler,
+            &txn_header_bytes(),
+            br#"{"nick": "ada"}"#,
+        )
+        .expect("profile");
+
+        let response: serde_json::Value = serde_json::from_slice(&response).expect("json");
+        assert_eq!(response["value"], "ada");
+        assert_eq!(
+            response["warnings"],
+            serde_json::json!(["nick is deprecated, use nickname"])
+        );
+
+        let profile = WithWarnings::<Profile>::decode(br#"{"nickname": "ada"}"#).expect("profile");
+        assert!(profile.warnings.is_empty());
+
+        // outside of a `WithWarnings`, warnings are dropped
+        assert!(Profile::decode(br#"{"nick": "ada"}"#).is_ok());
+        decode_warning("ignored");
+    }
+
+    #[derive(Deserialize)]
+    struct TenantRequest {
+        tenant: String,
+        name: String,
+    }
+
+    impl WasmRequest for TenantRequest {
+        fn decode(bytes: &[u8]) -> TCResult<Self> {
+            decode_serde_json(bytes)
+        }
+    }
+
+    struct TenantHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for TenantHandler {
+        type Request = TenantRequest;
+        type RequestContext = ();
+        type Response = String;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let TenantRequest { tenant, name } = request;
+            Ok(Box::pin(async move { Ok(format!("{tenant}/{name}")) }))
+        }
+    }
+
+    #[test]
+    fn merges_header_fields_into_body() {
+        let get = |body: &[u8]| {
+            try_dispatch_get_merged_bytes::<_, FakeTxn, TenantRequest, String>(
+                &["tenant"],
+                &TenantHandler,
+                &txn_header_bytes_with_field("tenant", "acme"),
+                body,
+            )
+        };
+
+        let response = get(br#"{"name": "Ada"}"#).expect("merged request");
+        assert_eq!(String::decode(&response).expect("decode"), "acme/Ada");
+
+        let err = get(br#"{"name": "Ada", "tenant": "other"}"#).expect_err("conflict");
+        assert!(err.to_string().contains("tenant is set in both"), "{err}");
+
+        assert!(get(br#""Ada""#).is_err());
+    }
+
+    #[test]
+    fn resolves_values_by_hash() {
+        let value = Value::from("a large, frequently repeated input");
+        let hash = content_hash(&canonical_json_bytes(value.clone()).expect("canonical"));
+
+        let mock = MockHost::new()
+            .with_stored(&hash, value.clone())
+            .with_stored(&"0".repeat(64), Value::from("something else"));
+
+        let _guard = mock.install();
+
+        let body = format!(r#"{{"$hash": "{hash}"}}"#);
+        let ByHash(resolved) = ByHash::<Value>::decode(body.as_bytes()).expect("by hash");
+        assert_eq!(resolved, value);
+
+        let ByHash(inline) = ByHash::<Value>::decode(br#""inline""#).expect("inline");
+        assert_eq!(inline, Value::from("inline"));
+
+        let body = format!(r#"{{"$hash": "{}"}}"#, "0".repeat(64));
+        let err = ByHash::<Value>::decode(body.as_bytes()).expect_err("mismatched hash");
+        assert!(err.to_string().contains("doesn't match"), "{err}");
+
+        assert!(ByHash::<Value>::decode(br#"{"$hash": "not a hash"}"#).is_err());
+    }
+}
@@ -0,0 +1,484 @@
+//! Response wrappers.
+//!
+//! Wrappers which need the host to do more than relay the payload are encoded as a
+//! *frame*: a JSON object whose `"$frame"` entry names the frame type. Each wrapper encodes
+//! what it wraps with that type's own [`WasmResponse`] impl, so e.g. a `Value::Bytes` inside
+//! a frame is encoded as a `$bytes` marker just as it would be on its own.
+
+use sha2::{Digest, Sha256};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{WasmResponse, canonical_json, encode_json_bytes};
+
+/// A successful response with non-fatal warnings (e.g. deprecations or partial data),
+/// encoded as `{"value": ..., "warnings": [...]}`.
+pub struct Warned<T> {
+    value: T,
+    warnings: Vec<String>,
+}
+
+impl<T> Warned<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn warn(mut self, warning: impl Into<String>) -> Self {
+        self.warnings.push(warning.into());
+        self
+    }
+}
+
+impl<T: WasmResponse> WasmResponse for Warned<T> {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        let value = self.value.encode()?;
+        let warnings = encode_json_bytes(self.warnings)?;
+        Ok(json_object(&[("value", &value), ("warnings", &warnings)]))
+    }
+}
+
+/// One page of a list, encoded as `{"items": [...], "cursor": ...}`. The client passes the
+/// opaque `cursor` back on its next request; a `null` cursor means there are no more items.
+pub struct Page<T> {
+    items: Vec<T>,
+    cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Construct the last page of a list.
+    pub fn new(items: Vec<T>) -> Self {
+        Self {
+            items,
+            cursor: None,
+        }
+    }
+
+    /// Set the cursor of the next page. An empty cursor also marks the last page.
+    pub fn with_cursor(mut self, cursor: impl Into<String>) -> Self {
+        let cursor = cursor.into();
+        self.cursor = if cursor.is_empty() {
+            None
+        } else {
+            Some(cursor)
+        };
+        self
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn cursor(&self) -> Option<&str> {
+        self.cursor.as_deref()
+    }
+}
+
+impl<T: WasmResponse> WasmResponse for Page<T> {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        let mut items = vec![b'['];
+        for (i, item) in self.items.into_iter().enumerate() {
+            if i > 0 {
+                items.push(b',');
+            }
+
+            items.extend(item.encode()?);
+        }
+        items.push(b']');
+
+        let cursor = encode_json_bytes(self.cursor)?;
+        Ok(json_object(&[("items", &items), ("cursor", &cursor)]))
+    }
+}
+
+/// A response paired with the SHA-256 hash of its canonical JSON encoding (see
+/// [`canonical_json`]), encoded as `{"hash": "<hex>", "value": ...}` so the host can
+/// store the value by its content address.
+pub struct Hashed<T> {
+    value: T,
+}
+
+impl<T> Hashed<T> {
+    pub fn new(value: T) -> Self {
+        Self { value }
+    }
+}
+
+impl<T: WasmResponse> WasmResponse for Hashed<T> {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        let canonical = canonical_json(&self.value.encode()?)?;
+        let hash = content_hash(&canonical);
+
+        let mut encoded = Vec::with_capacity(canonical.len() + hash.len() + 22);
+        encoded.extend_from_slice(br#"{"hash":""#);
+        encoded.extend_from_slice(hash.as_bytes());
+        encoded.extend_from_slice(br#"","value":"#);
+        encoded.extend_from_slice(&canonical);
+        encoded.push(b'}');
+        Ok(encoded)
+    }
+}
+
+/// The lowercase hex SHA-256 digest of `canonical`, as reported by [`Hashed`].
+pub fn content_hash(canonical: &[u8]) -> String {
+    Sha256::digest(canonical)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// A successful response with an explicit (non-200) status code, encoded as the frame
+/// `{"$frame": "status", "status": 201, "body": ...}`.
+pub struct StatusResponse<T> {
+    status: u16,
+    body: T,
+}
+
+impl<T> StatusResponse<T> {
+    /// Construct a new response with the given success (2xx) status.
+    pub fn new(status: u16, body: T) -> TCResult<Self> {
+        if (200..300).contains(&status) {
+            Ok(Self { status, body })
+        } else {
+            Err(TCError::bad_request(format!(
+                "{status} is not a success status; return a TCError instead"
+            )))
+        }
+    }
+
+    pub fn created(body: T) -> Self {
+        Self { status: 201, body }
+    }
+
+    pub fn accepted(body: T) -> Self {
+        Self { status: 202, body }
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+}
+
+impl<T: WasmResponse> WasmResponse for StatusResponse<T> {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        let status = self.status.to_string();
+        let body = self.body.encode()?;
+        Ok(json_object(&[
+            ("$frame", br#""status""#),
+            ("status", status.as_bytes()),
+            ("body", &body),
+        ]))
+    }
+}
+
+/// A response the host may cache for `ttl`, encoded as the frame
+/// `{"$frame": "cacheable", "ttl": <seconds>, "body": ...}`. A TTL of less than one second
+/// means the response must not be cached, so it's encoded as the bare body instead.
+pub struct Cacheable<T> {
+    ttl: Duration,
+    body: T,
+}
+
+impl<T> Cacheable<T> {
+    pub fn new(ttl: Duration, body: T) -> Self {
+        Self { ttl, body }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn is_cacheable(&self) -> bool {
+        self.ttl.as_secs() > 0
+    }
+}
+
+impl<T: WasmResponse> WasmResponse for Cacheable<T> {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        if !self.is_cacheable() {
+            return self.body.encode();
+        }
+
+        let ttl = self.ttl.as_secs().to_string();
+        let body = self.body.encode()?;
+        Ok(json_object(&[
+            ("$frame", br#""cacheable""#),
+            ("ttl", ttl.as_bytes()),
+            ("body", &body),
+        ]))
+    }
+}
+
+/// A JSON object of `entries`, in order, whose keys need no escaping and whose values are
+/// already encoded, e.g. by the [`WasmResponse`] impl of the value a frame wraps.
+fn json_object(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let len = entries
+        .iter()
+        .map(|(key, value)| key.len() + value.len() + 4)
+        .sum::<usize>();
+
+    let mut object = Vec::with_capacity(len + 2);
+    object.push(b'{');
+
+    for (i, (key, value)) in entries.iter().enumerate() {
+        if i > 0 {
+            object.push(b',');
+        }
+
+        object.push(b'"');
+        object.extend_from_slice(key.as_bytes());
+        object.extend_from_slice(b"\":");
+        object.extend_from_slice(value);
+    }
+
+    object.push(b'}');
+    object
+}
+
+/// Encodes a type-erased response previously registered via [`register_response`].
+pub type ResponseEncoder = fn(Box<dyn Any>) -> TCResult<Vec<u8>>;
+
+static RESPONSE_ENCODERS: Mutex<BTreeMap<&'static str, ResponseEncoder>> =
+    Mutex::new(BTreeMap::new());
+
+/// A response whose concrete type is chosen at runtime. Its [`Self::type_tag`] must have
+/// been registered via [`register_response`] before a handler returns it.
+pub trait ErasedResponse: Any + Send {
+    fn type_tag(&self) -> &'static str;
+}
+
+/// Register the encoder for responses of type `T` tagged `tag`, typically during init.
+pub fn register_response<T: WasmResponse + 'static>(tag: &'static str) {
+    fn encode_erased<T: WasmResponse + 'static>(response: Box<dyn Any>) -> TCResult<Vec<u8>> {
+        match response.downcast::<T>() {
+            Ok(response) => response.encode(),
+            Err(_) => Err(TCError::internal(format!(
+                "registered response type mismatch for {}",
+                std::any::type_name::<T>()
+            ))),
+        }
+    }
+
+    let mut encoders = RESPONSE_ENCODERS.lock().expect("response encoders");
+    encoders.insert(tag, encode_erased::<T>);
+}
+
+impl WasmResponse for Box<dyn ErasedResponse> {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        let tag = self.type_tag();
+        let encoder = RESPONSE_ENCODERS
+            .lock()
+            .expect("response encoders")
+            .get(tag)
+            .copied()
+            .ok_or_else(|| TCError::internal(format!("no encoder registered for {tag}")))?;
+
+        encoder(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tc_value::Value;
+
+    use crate::abi::WasmRequest;
+
+    #[test]
+    fn warned_encodes_value_and_warnings() {
+        let response = Warned::new("done".to_string())
+            .warn("`limit` is deprecated")
+            .warn("results truncated");
+
+        let bytes = response.encode().expect("warned json");
+        let json: serde_json::Value = serde_json::from_slice(&bytes).expect("decode json");
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "value": "done",
+                "warnings": ["`limit` is deprecated", "results truncated"],
+            })
+        );
+    }
+
+    #[test]
+    fn page_encodes_items_and_cursor() {
+        let page = Page::new(vec!["a".to_string(), "b".to_string()]).with_cursor("c2");
+        assert_eq!(page.cursor(), Some("c2"));
+
+        let bytes = page.encode().expect("page json");
+        let json: serde_json::Value = serde_json::from_slice(&bytes).expect("decode json");
+        assert_eq!(
+            json,
+            serde_json::json!({"items": ["a", "b"], "cursor": "c2"})
+        );
+
+        let last = Page::new(vec!["z".to_string()]).with_cursor("");
+        assert_eq!(last.cursor(), None);
+
+        let bytes = last.encode().expect("page json");
+        let json: serde_json::Value = serde_json::from_slice(&bytes).expect("decode json");
+        assert_eq!(json, serde_json::json!({"items": ["z"], "cursor": null}));
+    }
+
+    #[test]
+    fn hashed_is_deterministic() {
+        let items = || Value::Tuple(vec![Value::from("a"), Value::from("b")].into());
+
+        let bytes = Hashed::new(items()).encode().expect("hashed json");
+        assert_eq!(Hashed::new(items()).encode().expect("hashed json"), bytes);
+
+        let json: serde_json::Value = serde_json::from_slice(&bytes).expect("decode json");
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "hash": "0473ef2dc0d324ab659d3580c1134e9d812035905c4781fdd6d529b0c6860e13",
+                "value": ["a", "b"],
+            })
+        );
+
+        // keys are sorted before hashing, whatever order the value encodes them in
+        let status = StatusResponse::created("made".to_string());
+        let canonical =
+            canonical_json(&status.encode().expect("status json")).expect("canonical json");
+        assert_eq!(
+            canonical,
+            br#"{"$frame":"status","body":"made","status":201}"#
+        );
+        assert_eq!(
+            content_hash(&canonical),
+            "44e085f4e27cde62f1e23bf98b3f5d177f0a1c27cf60b7aa256f7574717d23c0"
+        );
+    }
+
+    #[test]
+    fn wrappers_encode_bytes_as_markers() {
+        let bytes = || Value::Tuple(vec![Value::Bytes(vec![0, 0xff].into())].into());
+        let marker = || serde_json::json!([{"$bytes": "AP8="}]);
+
+        let warned = Warned::new(bytes())
+            .warn("partial")
+            .encode()
+            .expect("warned");
+        let warned: serde_json::Value = serde_json::from_slice(&warned).expect("warned json");
+        assert_eq!(warned["value"], marker());
+        assert_eq!(
+            Value::decode(warned["value"].to_string().as_bytes()).expect("value"),
+            bytes()
+        );
+
+        let page = Page::new(vec![bytes()]).encode().expect("page");
+        let page: serde_json::Value = serde_json::from_slice(&page).expect("page json");
+        assert_eq!(page["items"][0], marker());
+
+        let status = StatusResponse::created(bytes()).encode().expect("status");
+        let status: serde_json::Value = serde_json::from_slice(&status).expect("status json");
+        assert_eq!(status["body"], marker());
+
+        let hashed = Hashed::new(bytes()).encode().expect("hashed");
+        let hashed: serde_json::Value = serde_json::from_slice(&hashed).expect("hashed json");
+        assert_eq!(hashed["value"], marker());
+    }
+
+    #[test]
+    fn status_response_frame() {
+        let bytes = StatusResponse::created("made".to_string())
+            .encode()
+            .expect("status json");
+        let json: serde_json::Value = serde_json::from_slice(&bytes).expect("decode json");
+
+        assert_eq!(
+            json,
+            serde_json::json!({"$frame": "status", "status": 201, "body": "made"})
+        );
+
+        assert!(StatusResponse::new(404, ()).is_err());
+    }
+
+    #[test]
+    fn cacheable_frame() {
+        let response = Cacheable::new(Duration::from_secs(60), "fresh".to_string());
+        let bytes = response.encode().expect("cacheable json");
+        let json: serde_json::Value = serde_json::from_slice(&bytes).expect("decode json");
+
+        assert_eq!(
+            json,
+            serde_json::json!({"$frame": "cacheable", "ttl": 60, "body": "fresh"})
+        );
+
+        let response = Cacheable::new(Duration::ZERO, "fresh".to_string());
+        assert!(!response.is_cacheable());
+
+        let bytes = response.encode().expect("uncached json");
+        let json: serde_json::Value = serde_json::from_slice(&bytes).expect("decode json");
+        assert_eq!(json, serde_json::json!("fresh"));
+    }
+
+    struct Celsius(f64);
+
+    impl WasmResponse for Celsius {
+        fn encode(self) -> TCResult<Vec<u8>> {
+            encode_json_bytes(format!("{}C", self.0))
+        }
+    }
+
+    impl ErasedResponse for Celsius {
+        fn type_tag(&self) -> &'static str {
+            "test/celsius"
+        }
+    }
+
+    struct Tags(Vec<String>);
+
+    impl WasmResponse for Tags {
+        fn encode(self) -> TCResult<Vec<u8>> {
+            encode_json_bytes(self.0)
+        }
+    }
+
+    impl ErasedResponse for Tags {
+        fn type_tag(&self) -> &'static str {
+            "test/tags"
+        }
+    }
+
+    struct Unregistered;
+
+    impl ErasedResponse for Unregistered {
+        fn type_tag(&self) -> &'static str {
+            "test/unregistered"
+        }
+    }
+
+    #[test]
+    fn encodes_registered_erased_responses() {
+        register_response::<Celsius>("test/celsius");
+        register_response::<Tags>("test/tags");
+
+        let responses: Vec<Box<dyn ErasedResponse>> = vec![
+            Box::new(Celsius(21.5)),
+            Box::new(Tags(vec!["a".to_string(), "b".to_string()])),
+        ];
+
+        let encoded = responses
+            .into_iter()
+            .map(|response| response.encode().expect("erased json"))
+            .map(|bytes| serde_json::from_slice(&bytes).expect("decode json"))
+            .collect::<Vec<serde_json::Value>>();
+
+        assert_eq!(
+            encoded,
+            vec![serde_json::json!("21.5C"), serde_json::json!(["a", "b"])]
+        );
+
+        let unregistered: Box<dyn ErasedResponse> = Box::new(Unregistered);
+        assert!(unregistered.encode().is_err());
+    }
+}
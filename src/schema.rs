@@ -0,0 +1,292 @@
+use pathlink::Link;
+use semver::{Version, VersionReq};
+use std::str::FromStr;
+use tc_error::{TCError, TCResult};
+use tc_ir::LibrarySchema;
+
+use crate::abi::{borrow_bytes, decode_serde_json, respond};
+
+/// Assembles a [`LibrarySchema`], validating each link as it's added.
+pub struct LibrarySchemaBuilder {
+    link: Link,
+    version: String,
+    dependencies: Vec<Link>,
+    ranges: Vec<DependencyRange>,
+}
+
+impl LibrarySchemaBuilder {
+    pub fn new(link: &str, version: impl Into<String>) -> TCResult<Self> {
+        Ok(Self {
+            link: parse_link(link)?,
+            version: version.into(),
+            dependencies: Vec::new(),
+            ranges: Vec::new(),
+        })
+    }
+
+    /// Add a dependency on another library. Duplicate dependencies are ignored.
+    pub fn dependency(mut self, link: &str) -> TCResult<Self> {
+        let link = parse_link(link)?;
+        if !self.dependencies.contains(&link) {
+            self.dependencies.push(link);
+        }
+
+        Ok(self)
+    }
+
+    /// Add a dependency on the versioned library `link` (e.g. `/lib/devco/example/0.1.0`),
+    /// which may be satisfied at mount time by any version of the library in the semver
+    /// `range` (e.g. `^0.1`). The pinned version must itself be in range.
+    pub fn dependency_in_range(self, link: &str, range: &str) -> TCResult<Self> {
+        let (library, version) = split_version(link)?;
+        let range = DependencyRange::new(library, range)?;
+        range.check_version(version)?;
+
+        let mut builder = self.dependency(link)?;
+        builder.ranges.push(range);
+        Ok(builder)
+    }
+
+    pub fn build(self) -> LibrarySchema {
+        LibrarySchema::new(self.link, self.version, self.dependencies)
+    }
+
+    /// Like [`Self::build`], but also return the ranges declared via
+    /// [`Self::dependency_in_range`], for [`check_dependency_versions`].
+    pub fn build_with_ranges(self) -> (LibrarySchema, Vec<DependencyRange>) {
+        let schema = LibrarySchema::new(self.link, self.version, self.dependencies);
+        (schema, self.ranges)
+    }
+}
+
+/// The semver range of versions of a library (identified by its unversioned link, e.g.
+/// `/lib/devco/example`) which a dependent library is compatible with.
+#[derive(Clone, Debug)]
+pub struct DependencyRange {
+    library: String,
+    range: VersionReq,
+}
+
+impl DependencyRange {
+    pub fn new(library: &str, range: &str) -> TCResult<Self> {
+        parse_link(library)?;
+
+        let range = VersionReq::parse(range).map_err(|err| {
+            TCError::bad_request(format!("invalid version range {range:?}: {err}"))
+        })?;
+
+        Ok(Self {
+            library: library.to_string(),
+            range,
+        })
+    }
+
+    pub fn library(&self) -> &str {
+        &self.library
+    }
+
+    pub fn range(&self) -> &VersionReq {
+        &self.range
+    }
+
+    /// Check that the versioned link `resolved` is a version of this library in range.
+    pub fn check(&self, resolved: &Link) -> TCResult<()> {
+        let resolved = resolved.to_string();
+        let (library, version) = split_version(&resolved)?;
+
+        if library == self.library {
+            self.check_version(version)
+        } else {
+            Err(TCError::bad_request(format!(
+                "{resolved} is not a version of {}",
+                self.library
+            )))
+        }
+    }
+
+    fn check_version(&self, version: &str) -> TCResult<()> {
+        let parsed = Version::parse(version).map_err(|err| {
+            TCError::bad_request(format!(
+                "invalid version {version:?} of {}: {err}",
+                self.library
+            ))
+        })?;
+
+        if self.range.matches(&parsed) {
+            Ok(())
+        } else {
+            Err(TCError::bad_request(format!(
+                "{} version {version} does not satisfy {}",
+                self.library, self.range
+            )))
+        }
+    }
+}
+
+/// Check that for each of `ranges`, the host resolved a version of the library which is in
+/// range. `resolved` holds the versioned links of the mounted dependencies.
+pub fn check_dependency_versions(ranges: &[DependencyRange], resolved: &[Link]) -> TCResult<()> {
+    for range in ranges {
+        let prefix = format!("{}/", range.library);
+        let link = resolved
+            .iter()
+            .find(|link| link.to_string().starts_with(&prefix))
+            .ok_or_else(|| {
+                TCError::bad_request(format!("dependency {} is not resolved", range.library))
+            })?;
+
+        range.check(link)?;
+    }
+
+    Ok(())
+}
+
+/// Implements a `tc_check_dependencies(ptr, len) -> i64` export, which the host calls before
+/// mounting the library with a JSON list of the versioned links it resolved its dependencies
+/// to. Returns `0` if every range is satisfied, or an error payload otherwise.
+pub fn check_dependencies(ranges: &[DependencyRange], resolved_ptr: i32, resolved_len: i32) -> i64 {
+    let resolved_bytes = unsafe { borrow_bytes(resolved_ptr, resolved_len) };
+    respond(try_check_dependencies_bytes(ranges, resolved_bytes).map(|()| Vec::new()))
+}
+
+/// Decode an already-read list of resolved links and check them against `ranges`.
+pub fn try_check_dependencies_bytes(
+    ranges: &[DependencyRange],
+    resolved_bytes: &[u8],
+) -> TCResult<()> {
+    let resolved: Vec<String> = decode_serde_json(resolved_bytes)?;
+    let resolved = resolved
+        .iter()
+        .map(|link| parse_link(link))
+        .collect::<TCResult<Vec<_>>>()?;
+
+    check_dependency_versions(ranges, &resolved)
+}
+
+/// Split a versioned library link into the library's link and its version.
+fn split_version(link: &str) -> TCResult<(&str, &str)> {
+    link.rsplit_once('/')
+        .filter(|(library, version)| !library.is_empty() && !version.is_empty())
+        .ok_or_else(|| TCError::bad_request(format!("{link} is not a versioned library link")))
+}
+
+/// The root link of the library described by `schema`.
+///
+/// Handlers should reference this instead of re-parsing their own root from a string
+/// constant, so the two can't drift apart.
+pub fn library_root(schema: &LibrarySchema) -> &Link {
+    schema.id()
+}
+
+fn parse_link(link: &str) -> TCResult<Link> {
+    let is_absolute = link.starts_with('/') || link.contains("://");
+    if !is_absolute {
+        return Err(TCError::bad_request(format!(
+            "library link must be absolute: {link:?}"
+        )));
+    }
+
+    let is_malformed = link.chars().any(char::is_whitespace)
+        || (link.contains("//") && !link.contains("://"))
+        || (link.len() > 1 && link.ends_with('/'));
+
+    if is_malformed {
+        return Err(TCError::bad_request(format!(
+            "malformed library link: {link:?}"
+        )));
+    }
+
+    Link::from_str(link)
+        .map_err(|err| TCError::bad_request(format!("invalid library link {link:?}: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_schema_with_dependencies() {
+        let schema = LibrarySchemaBuilder::new("/lib/example-devco/a/0.1.0", "0.1.0")
+            .and_then(|builder| builder.dependency("/lib/example-devco/example/0.1.0"))
+            .expect("builder")
+            .build();
+
+        assert_eq!(
+            schema.dependencies(),
+            &[Link::from_str("/lib/example-devco/example/0.1.0").expect("link")]
+        );
+    }
+
+    #[test]
+    fn dedups_dependencies() {
+        let schema = LibrarySchemaBuilder::new("/lib/example-devco/a/0.1.0", "0.1.0")
+            .and_then(|builder| builder.dependency("/lib/example-devco/example/0.1.0"))
+            .and_then(|builder| builder.dependency("/lib/example-devco/example/0.1.0"))
+            .expect("builder")
+            .build();
+
+        assert_eq!(schema.dependencies().len(), 1);
+    }
+
+    #[test]
+    fn rejects_malformed_dependency() {
+        let builder =
+            LibrarySchemaBuilder::new("/lib/example-devco/a/0.1.0", "0.1.0").expect("builder");
+
+        assert!(
+            builder
+                .dependency("lib/example-devco/example/0.1.0")
+                .is_err()
+        );
+
+        let builder =
+            LibrarySchemaBuilder::new("/lib/example-devco/a/0.1.0", "0.1.0").expect("builder");
+
+        assert!(builder.dependency("/lib/example-devco//0.1.0").is_err());
+    }
+
+    #[test]
+    fn accepts_dependency_in_range() {
+        let (schema, ranges) = LibrarySchemaBuilder::new("/lib/example-devco/a/0.1.0", "0.1.0")
+            .and_then(|builder| {
+                builder.dependency_in_range("/lib/example-devco/example/0.1.0", "^0.1")
+            })
+            .expect("builder")
+            .build_with_ranges();
+
+        assert_eq!(schema.dependencies().len(), 1);
+        assert_eq!(ranges[0].library(), "/lib/example-devco/example");
+
+        let resolved = br#"["/lib/example-devco/example/0.1.3"]"#;
+        try_check_dependencies_bytes(&ranges, resolved).expect("compatible");
+    }
+
+    #[test]
+    fn rejects_incompatible_dependency() {
+        let ranges = [DependencyRange::new("/lib/example-devco/example", "^0.1").expect("range")];
+
+        let resolved = [Link::from_str("/lib/example-devco/example/0.2.0").expect("link")];
+        let err = check_dependency_versions(&ranges, &resolved).expect_err("incompatible");
+        assert!(err.to_string().contains("does not satisfy"));
+
+        let resolved = [Link::from_str("/lib/example-devco/other/0.1.0").expect("link")];
+        assert!(check_dependency_versions(&ranges, &resolved).is_err());
+
+        let builder =
+            LibrarySchemaBuilder::new("/lib/example-devco/a/0.1.0", "0.1.0").expect("builder");
+
+        assert!(
+            builder
+                .dependency_in_range("/lib/example-devco/example/0.1.0", "^0.2")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn library_root_matches_schema_link() {
+        let link = Link::from_str("/lib/example-devco/example/0.1.0").expect("link");
+        let schema = LibrarySchema::new(link.clone(), "0.1.0", vec![]);
+
+        assert_eq!(library_root(&schema), &link);
+    }
+}
@@ -0,0 +1,352 @@
+//! Responses which are built incrementally and streamed to the host as they're built, e.g. a
+//! large map computed one entry at a time.
+//!
+//! The handler implements [`HandleValueStream`]: rather than returning a [`Value`], it writes
+//! `(key, value)` entries into a [`ValueSink`] from a future which only runs while the host
+//! pulls chunks through `tc_stream_next(handle)` (see [`crate::stream`]). Once
+//! [`VALUE_SINK_CAPACITY`] entries are waiting to be pulled, [`ValueSink::write`] waits, so a
+//! handler never runs far ahead of the host.
+//!
+//! The chunks concatenate to one JSON object: first `{`, then one `"key": value` entry per
+//! chunk (each after the first preceded by a `,`), and finally `}`. If the handler fails, its
+//! error payload is sent as the final chunk instead of the closing `}`.
+//!
+//! A sequence which is computed rather than awaited can instead come from a
+//! [`ValueGenerator`], which is called for each element as the host pulls it (see
+//! [`Generated`]), so neither the whole sequence nor its whole encoding is ever held at once.
+
+use destream::en::{self, EncodeMap};
+use futures::channel::mpsc;
+use futures::stream::{self, PollNext};
+use futures::{Future, SinkExt, StreamExt, future};
+use std::pin::Pin;
+use tc_error::{TCError, TCResult};
+use tc_value::Value;
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, decode_header_bytes,
+    encode_json_bytes, respond,
+};
+use crate::codec::request_body;
+use crate::stream::open_stream;
+
+/// The number of entries a [`ValueSink`] buffers before [`ValueSink::write`] waits for the
+/// host to pull them.
+pub const VALUE_SINK_CAPACITY: usize = 16;
+
+/// The future which fills a [`ValueSink`]. It outlives the call which opened the stream, so
+/// it must own everything it uses.
+pub type ValueStreamFuture = Pin<Box<dyn Future<Output = TCResult<()>>>>;
+
+/// A handler whose response is a map built one entry at a time.
+pub trait HandleValueStream<Txn> {
+    type Request;
+
+    /// Return a future which writes the response's entries into `sink`. It's driven as the
+    /// host pulls chunks, and the response ends when it does.
+    fn stream(
+        &self,
+        txn: &Txn,
+        request: Self::Request,
+        sink: ValueSink,
+    ) -> TCResult<ValueStreamFuture>;
+}
+
+/// The write end of a streamed response.
+pub struct ValueSink {
+    entries: mpsc::Sender<Vec<u8>>,
+    empty: bool,
+}
+
+impl ValueSink {
+    /// Append the entry `key: value` to the response, waiting if the host is behind.
+    /// Fails if the host closed the stream, in which case the handler should stop.
+    pub async fn write(&mut self, key: &str, value: Value) -> TCResult<()> {
+        let key = serde_json::to_vec(key).map_err(TCError::internal)?;
+        let value = value.encode()?;
+
+        let mut entry = Vec::with_capacity(key.len() + value.len() + 2);
+        if !self.empty {
+            entry.push(b',');
+        }
+
+        entry.extend_from_slice(&key);
+        entry.push(b':');
+        entry.extend_from_slice(&value);
+
+        self.entries
+            .send(entry)
+            .await
+            .map_err(|_| TCError::cancelled("the host closed the value stream"))?;
+
+        self.empty = false;
+        Ok(())
+    }
+}
+
+/// Register a response built by `produce`, as described in the [module docs](self), and
+/// return its stream handle.
+pub fn open_value_stream<F>(produce: F) -> TCResult<i32>
+where
+    F: FnOnce(ValueSink) -> TCResult<ValueStreamFuture>,
+{
+    let (sender, receiver) = mpsc::channel(VALUE_SINK_CAPACITY);
+    let producer = produce(ValueSink {
+        entries: sender,
+        empty: true,
+    })?;
+
+    // the receiver ends once the producer finishes and drops the sink
+    let failure = stream::once(producer).filter_map(|result| future::ready(result.err().map(Err)));
+
+    // prefer entries already written, so that a failure is only sent after them
+    let entries =
+        stream::select_with_strategy(receiver.map(Ok), failure, |_: &mut ()| PollNext::Left);
+
+    let chunks = stream::iter([Ok(b"{".to_vec())])
+        .chain(entries)
+        .chain(stream::iter([Ok(b"}".to_vec())]));
+
+    Ok(open_stream(crate::executor::block_on_stream(Box::pin(
+        chunks,
+    ))))
+}
+
+/// Produces the elements of a streamed sequence on demand: each call returns the next
+/// element, or `None` once the sequence is finished. Implemented for any
+/// `FnMut() -> Option<TCResult<Value>>` closure.
+pub trait ValueGenerator {
+    fn generate(&mut self) -> Option<TCResult<Value>>;
+}
+
+impl<F> ValueGenerator for F
+where
+    F: FnMut() -> Option<TCResult<Value>>,
+{
+    fn generate(&mut self) -> Option<TCResult<Value>> {
+        self()
+    }
+}
+
+/// Register the sequence produced by `generator` and return its stream handle. The chunks
+/// concatenate to one JSON array: first `[`, then one element per chunk (each after the first
+/// preceded by a `,`), and finally `]`. If the generator fails, its error payload is sent as
+/// the final chunk instead of the closing `]`.
+pub fn open_generated_stream<G: ValueGenerator + 'static>(mut generator: G) -> i32 {
+    let mut empty = true;
+    let elements =
+        std::iter::from_fn(move || generator.generate()).map(move |value| -> TCResult<Vec<u8>> {
+            let value = value?.encode()?;
+
+            let mut element = Vec::with_capacity(value.len() + 1);
+            if !empty {
+                element.push(b',');
+            }
+
+            element.extend_from_slice(&value);
+            empty = false;
+            Ok(element)
+        });
+
+    let chunks = std::iter::once(Ok(b"[".to_vec()))
+        .chain(elements)
+        .chain(std::iter::once(Ok(b"]".to_vec())));
+
+    open_stream(chunks)
+}
+
+/// A response whose elements come from a [`ValueGenerator`], opened as a stream (see
+/// [`open_generated_stream`]) when it's encoded and answered with the frame
+/// `{"$frame": "value_stream", "handle": <i32>}`.
+pub struct Generated<G>(pub G);
+
+impl<G: ValueGenerator + 'static> WasmResponse for Generated<G> {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        let handle = open_generated_stream(self.0);
+        encode_json_bytes(ValueStreamFrame { handle })
+    }
+}
+
+struct ValueStreamFrame {
+    handle: i32,
+}
+
+impl<'en> en::IntoStream<'en> for ValueStreamFrame {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(2))?;
+        map.encode_entry("$frame", "value_stream")?;
+        map.encode_entry("handle", self.handle)?;
+        map.end()
+    }
+}
+
+/// Start a streamed response and answer `{"$frame": "value_stream", "handle": <i32>}`.
+pub fn dispatch_value_stream<H, Txn>(
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: HandleValueStream<Txn>,
+    H::Request: WasmRequest,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_value_stream_bytes(
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Start a streamed response from an already-read header and body, encoding its frame.
+pub fn try_dispatch_value_stream_bytes<H, Txn>(
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: HandleValueStream<Txn>,
+    H::Request: WasmRequest,
+{
+    let header = decode_header_bytes(header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+    let request = H::Request::decode(&request_body(body_bytes)?)?;
+
+    let handle = open_value_stream(|sink| handler.stream(&txn, request, sink))?;
+    encode_json_bytes(ValueStreamFrame { handle })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crate::stream::try_stream_next;
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+
+    async fn squares(mut sink: ValueSink) -> TCResult<()> {
+        for n in 1..=3u64 {
+            sink.write(&format!("n{n}"), Value::from(n * n)).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn fail_after_one(mut sink: ValueSink) -> TCResult<()> {
+        sink.write("ok", Value::from("done")).await?;
+        Err(TCError::internal("disk on fire"))
+    }
+
+    struct SquaresHandler;
+
+    impl HandleValueStream<FakeTxn> for SquaresHandler {
+        type Request = Value;
+
+        fn stream(
+            &self,
+            _txn: &FakeTxn,
+            _request: Value,
+            sink: ValueSink,
+        ) -> TCResult<ValueStreamFuture> {
+            Ok(Box::pin(squares(sink)))
+        }
+    }
+
+    #[test]
+    fn streams_entries_as_written() {
+        let frame = try_dispatch_value_stream_bytes::<_, FakeTxn>(
+            &SquaresHandler,
+            &txn_header_bytes(),
+            &[],
+        )
+        .expect("frame");
+
+        let frame: serde_json::Value = serde_json::from_slice(&frame).expect("frame");
+        assert_eq!(frame["$frame"], "value_stream");
+        let handle = frame["handle"].as_i64().expect("handle") as i32;
+
+        let chunks = std::iter::from_fn(|| try_stream_next(handle)).collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(chunks[1], b"\"n1\":1");
+        assert_eq!(chunks[2], b",\"n2\":4");
+
+        let value: serde_json::Value = serde_json::from_slice(&chunks.concat()).expect("json");
+        assert_eq!(value, serde_json::json!({"n1": 1, "n2": 4, "n3": 9}));
+    }
+
+    #[test]
+    fn failure_ends_value_stream() {
+        let handle = open_value_stream(|sink| Ok(Box::pin(fail_after_one(sink)))).expect("handle");
+
+        assert_eq!(try_stream_next(handle).as_deref(), Some(&b"{"[..]));
+        assert_eq!(
+            try_stream_next(handle).as_deref(),
+            Some(&b"\"ok\":\"done\""[..])
+        );
+
+        let error = try_stream_next(handle).expect("error chunk");
+        assert!(String::from_utf8_lossy(&error).contains("disk on fire"));
+        assert_eq!(try_stream_next(handle), None);
+    }
+
+    #[test]
+    fn generates_elements_on_demand() {
+        const LEN: u64 = 1000;
+
+        let generated = Rc::new(Cell::new(0u64));
+        let generator = {
+            let generated = generated.clone();
+            move || {
+                let n = generated.get();
+                (n < LEN).then(|| {
+                    generated.set(n + 1);
+                    Ok(Value::from(n * n))
+                })
+            }
+        };
+
+        let frame = Generated(generator).encode().expect("frame");
+        let frame: serde_json::Value = serde_json::from_slice(&frame).expect("frame");
+        assert_eq!(frame["$frame"], "value_stream");
+        let handle = frame["handle"].as_i64().expect("handle") as i32;
+
+        assert_eq!(generated.get(), 0);
+        assert_eq!(try_stream_next(handle).as_deref(), Some(&b"["[..]));
+
+        let mut elements = Vec::new();
+        while let Some(chunk) = try_stream_next(handle) {
+            // each chunk holds at most one element, generated only once it's pulled
+            assert!(chunk.len() <= 8, "{}", String::from_utf8_lossy(&chunk));
+            assert!(generated.get() <= elements.len() as u64 + 1);
+            elements.push(chunk);
+        }
+
+        assert_eq!(generated.get(), LEN);
+        assert_eq!(elements.len() as u64, LEN + 1);
+
+        let values: Vec<u64> =
+            serde_json::from_slice(&[b"[".to_vec(), elements.concat()].concat()).expect("json");
+        assert_eq!(values, (0..LEN).map(|n| n * n).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn failure_ends_generated_stream() {
+        let mut results = vec![
+            Err(TCError::internal("out of ideas")),
+            Ok(Value::from(1u64)),
+        ];
+        let handle = open_generated_stream(move || results.pop());
+
+        let chunks = std::iter::from_fn(|| try_stream_next(handle)).collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1], b"1");
+        assert!(String::from_utf8_lossy(&chunks[2]).contains("out of ideas"));
+    }
+}
@@ -0,0 +1,153 @@
+//! Handles to state which the host persists on a library's behalf (see [`crate::host::store`]).
+
+use destream::en::{self, EncodeMap};
+use std::fmt;
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{WasmRequest, WasmResponse, decode_serde_json, encode_json_bytes};
+
+/// An opaque id the host assigned to a value stored via [`crate::host::store`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct StateId(String);
+
+impl StateId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl From<String> for StateId {
+    fn from(id: String) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for StateId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A client passes a handle back as a bare JSON string, e.g. `"state-1"`.
+impl WasmRequest for StateId {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        let id: String = decode_serde_json(bytes)?;
+        if id.is_empty() {
+            Err(TCError::bad_request("state id must not be empty"))
+        } else {
+            Ok(Self(id))
+        }
+    }
+}
+
+/// A response which tells the client "I stored this, here's the handle", encoded as the
+/// frame `{"$frame": "state_ref", "id": "<id>"}`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StateRef {
+    id: StateId,
+}
+
+impl StateRef {
+    pub fn new(id: StateId) -> Self {
+        Self { id }
+    }
+
+    pub fn id(&self) -> &StateId {
+        &self.id
+    }
+}
+
+impl<'en> en::IntoStream<'en> for StateRef {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(2))?;
+        map.encode_entry("$frame", "state_ref")?;
+        map.encode_entry("id", self.id.into_string())?;
+        map.end()
+    }
+}
+
+impl WasmResponse for StateRef {
+    fn encode(self) -> TCResult<Vec<u8>> {
+        encode_json_bytes(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+    use tc_value::Value;
+
+    use crate::abi::{try_dispatch_get_bytes, try_dispatch_put_bytes};
+    use crate::host;
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+    use crate::testing::MockHost;
+
+    struct DraftHandler;
+
+    impl tc_ir::HandlePut<FakeTxn> for DraftHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = StateRef;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn put<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let id = host::store(request)?;
+            Ok(Box::pin(async move { Ok(StateRef::new(id)) }))
+        }
+    }
+
+    impl tc_ir::HandleGet<FakeTxn> for DraftHandler {
+        type Request = StateId;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, id: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let draft = host::load(&id)?;
+            Ok(Box::pin(async move { Ok(draft) }))
+        }
+    }
+
+    #[test]
+    fn round_trips_stored_state() {
+        let mock = MockHost::new();
+        let _guard = mock.install();
+
+        let response = try_dispatch_put_bytes::<_, FakeTxn, Value, StateRef>(
+            &DraftHandler,
+            &txn_header_bytes(),
+            br#""first draft""#,
+        )
+        .expect("store");
+
+        let frame: serde_json::Value = serde_json::from_slice(&response).expect("frame");
+        assert_eq!(frame["$frame"], "state_ref");
+
+        let handle = serde_json::to_vec(&frame["id"]).expect("handle");
+        let response = try_dispatch_get_bytes::<_, FakeTxn, StateId, Value>(
+            &DraftHandler,
+            &txn_header_bytes(),
+            &handle,
+        )
+        .expect("load");
+
+        assert_eq!(
+            Value::decode(&response).expect("draft"),
+            Value::from("first draft")
+        );
+
+        let unknown = StateId::from("missing".to_string());
+        assert!(host::load(&unknown).is_err());
+    }
+}
@@ -0,0 +1,347 @@
+//! Stream handles, for responses too large to hand to the host in a single buffer.
+//!
+//! A library registers an iterator of chunks with [`open_stream`] and returns the handle.
+//! The host then calls the library's `tc_stream_next(handle)` export (a wrapper around
+//! [`stream_next`]) until it returns `0`, freeing each chunk as usual, or calls
+//! `tc_stream_close(handle)` ([`stream_close`]) to abandon the stream early.
+//!
+//! [`open_event_stream`] frames each chunk as a server-sent event, for live-updating clients,
+//! and [`dispatch_get_byte_stream`] streams a handler's opaque bytes, e.g. a file download.
+
+use bytes::Bytes;
+use destream::en::{self, EncodeMap};
+use futures::Stream;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use tc_error::{TCError, TCResult};
+use tc_value::Value;
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, decode_header_bytes,
+    dispatch_get_decoded, encode_error, encode_json_bytes, leak_bytes, respond,
+};
+use crate::codec::request_body;
+
+/// The largest chunk [`dispatch_get_byte_stream`] hands to the host at once (64 KiB).
+pub const BYTE_STREAM_CHUNK_SIZE: usize = 64 << 10;
+
+type Chunks = Box<dyn Iterator<Item = TCResult<Vec<u8>>>>;
+
+thread_local! {
+    static STREAMS: RefCell<BTreeMap<i32, Chunks>> = const { RefCell::new(BTreeMap::new()) };
+    static NEXT_HANDLE: Cell<i32> = const { Cell::new(1) };
+}
+
+/// Register `chunks` and return the (non-zero) handle the host pulls them from.
+pub fn open_stream<I>(chunks: I) -> i32
+where
+    I: IntoIterator<Item = TCResult<Vec<u8>>>,
+    I::IntoIter: 'static,
+{
+    let handle = NEXT_HANDLE.with(|next| {
+        let handle = next.get();
+        next.set(handle.checked_add(1).unwrap_or(1));
+        handle
+    });
+
+    let chunks: Chunks = Box::new(chunks.into_iter());
+    STREAMS.with(|streams| streams.borrow_mut().insert(handle, chunks));
+    handle
+}
+
+/// Register a stream of `(event, data)` pairs, each framed by [`sse_event`] as one chunk.
+///
+/// The stream ends when `events` does. If an event fails, the error payload is sent as a final
+/// `error` event and the stream ends.
+pub fn open_event_stream<I>(events: I) -> i32
+where
+    I: IntoIterator<Item = TCResult<(String, Value)>>,
+    I::IntoIter: 'static,
+{
+    let mut failed = false;
+    let chunks = events.into_iter().map_while(move |event| {
+        if failed {
+            return None;
+        }
+
+        let chunk = event.and_then(|(event, data)| sse_event(&event, data));
+        Some(chunk.or_else(|err| {
+            failed = true;
+            Ok(sse_frame("error", &encode_error(err)))
+        }))
+    });
+
+    open_stream(chunks)
+}
+
+/// Frame one server-sent event as `event: <event>\ndata: <data as JSON>\n\n`.
+pub fn sse_event(event: &str, data: Value) -> TCResult<Vec<u8>> {
+    if event.is_empty() || event.contains(['\r', '\n']) {
+        return Err(TCError::internal(format!("invalid event name {event:?}")));
+    }
+
+    Ok(sse_frame(event, &data.encode()?))
+}
+
+fn sse_frame(event: &str, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(event.len() + data.len() + 15);
+    frame.extend_from_slice(b"event: ");
+    frame.extend_from_slice(event.as_bytes());
+    frame.extend_from_slice(b"\ndata: ");
+    frame.extend_from_slice(data);
+    frame.extend_from_slice(b"\n\n");
+    frame
+}
+
+struct ByteStreamFrame {
+    handle: i32,
+}
+
+impl<'en> en::IntoStream<'en> for ByteStreamFrame {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(2))?;
+        map.encode_entry("$frame", "byte_stream")?;
+        map.encode_entry("handle", self.handle)?;
+        map.end()
+    }
+}
+
+/// Like [`crate::dispatch_get`], for a handler whose response is a stream of raw bytes (e.g. a
+/// file download) which is never buffered in full. Answers the frame
+/// `{"$frame": "byte_stream", "handle": <i32>}`; the host then pulls the bytes through
+/// `tc_stream_next(handle)`, in chunks of at most [`BYTE_STREAM_CHUNK_SIZE`] bytes (larger
+/// items are split, empty ones skipped), until it returns `0` at the end of the stream. If
+/// the stream fails, its error payload is sent as the final chunk.
+pub fn dispatch_get_byte_stream<H, Txn, Req, S>(
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = S, Error = TCError>,
+    Req: WasmRequest,
+    S: Stream<Item = TCResult<Bytes>> + 'static,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_byte_stream_bytes(
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and body, answering the frame of the opened byte stream.
+pub fn try_dispatch_get_byte_stream_bytes<H, Txn, Req, S>(
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = S, Error = TCError>,
+    Req: WasmRequest,
+    S: Stream<Item = TCResult<Bytes>> + 'static,
+{
+    let header = decode_header_bytes(header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+    let request = Req::decode(&request_body(body_bytes)?)?;
+
+    let stream = dispatch_get_decoded(handler, &txn, request)?;
+    let handle = open_byte_stream(stream);
+    encode_json_bytes(ByteStreamFrame { handle })
+}
+
+/// Register a stream of raw bytes, re-chunked to at most [`BYTE_STREAM_CHUNK_SIZE`] bytes.
+pub fn open_byte_stream<S>(stream: S) -> i32
+where
+    S: Stream<Item = TCResult<Bytes>> + 'static,
+{
+    let chunks = crate::executor::block_on_stream(Box::pin(stream)).flat_map(|item| match item {
+        Ok(bytes) => (0..bytes.len())
+            .step_by(BYTE_STREAM_CHUNK_SIZE)
+            .map(|start| {
+                let end = bytes.len().min(start + BYTE_STREAM_CHUNK_SIZE);
+                Ok(bytes[start..end].to_vec())
+            })
+            .collect::<Vec<_>>(),
+        Err(err) => vec![Err(err)],
+    });
+
+    open_stream(chunks)
+}
+
+/// Hand the next chunk of stream `handle` to the host, packed like any other response.
+/// Returns `0` once the stream is finished (or if the handle is unknown), at which point
+/// the handle has been released.
+pub fn stream_next(handle: i32) -> i64 {
+    try_stream_next(handle).map(leak_bytes).unwrap_or(0)
+}
+
+/// The next chunk of stream `handle`, or `None` once it's finished. Empty chunks are
+/// skipped. An error is encoded as a final error payload chunk, after which the stream ends.
+pub fn try_stream_next(handle: i32) -> Option<Vec<u8>> {
+    // take the iterator out of the registry so that producing a chunk may open other streams
+    let mut chunks = STREAMS.with(|streams| streams.borrow_mut().remove(&handle))?;
+
+    loop {
+        match chunks.next()? {
+            Ok(chunk) if chunk.is_empty() => continue,
+            Ok(chunk) => {
+                STREAMS.with(|streams| streams.borrow_mut().insert(handle, chunks));
+                return Some(chunk);
+            }
+            Err(err) => return Some(encode_error(err)),
+        }
+    }
+}
+
+/// Release stream `handle` without reading the rest of it.
+pub fn stream_close(handle: i32) {
+    STREAMS.with(|streams| streams.borrow_mut().remove(&handle));
+}
+
+/// Whether stream `handle` is still open.
+pub(crate) fn is_open(handle: i32) -> bool {
+    STREAMS.with(|streams| streams.borrow().contains_key(&handle))
+}
+
+/// The number of open streams.
+pub fn open_streams() -> usize {
+    STREAMS.with(|streams| streams.borrow().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+
+    #[test]
+    fn pulls_chunks_until_finished() {
+        let chunks = vec![Ok(b"a".to_vec()), Ok(Vec::new()), Ok(b"b".to_vec())];
+        let handle = open_stream(chunks);
+
+        assert_eq!(try_stream_next(handle).as_deref(), Some(&b"a"[..]));
+        assert_eq!(try_stream_next(handle).as_deref(), Some(&b"b"[..]));
+        assert_eq!(try_stream_next(handle), None);
+        assert_eq!(try_stream_next(handle), None);
+        assert_eq!(stream_next(handle), 0);
+        assert_eq!(open_streams(), 0);
+    }
+
+    #[test]
+    fn error_ends_stream() {
+        let chunks = vec![
+            Ok(b"a".to_vec()),
+            Err(TCError::internal("disk on fire")),
+            Ok(b"b".to_vec()),
+        ];
+
+        let handle = open_stream(chunks);
+
+        assert_eq!(try_stream_next(handle).as_deref(), Some(&b"a"[..]));
+        let error = try_stream_next(handle).expect("error chunk");
+        assert!(String::from_utf8_lossy(&error).contains("disk on fire"));
+        assert_eq!(try_stream_next(handle), None);
+    }
+
+    #[test]
+    fn frames_server_sent_events() {
+        let events = vec![
+            Ok(("progress".to_string(), Value::from(50u64))),
+            Ok(("done".to_string(), Value::from("ok"))),
+        ];
+
+        let handle = open_event_stream(events);
+
+        assert_eq!(
+            try_stream_next(handle).as_deref(),
+            Some(&b"event: progress\ndata: 50\n\n"[..])
+        );
+        assert_eq!(
+            try_stream_next(handle).as_deref(),
+            Some(&b"event: done\ndata: \"ok\"\n\n"[..])
+        );
+        assert_eq!(try_stream_next(handle), None);
+    }
+
+    #[test]
+    fn failed_event_ends_event_stream() {
+        let events = vec![
+            Ok(("bad\nname".to_string(), Value::None)),
+            Ok(("done".to_string(), Value::None)),
+        ];
+
+        let handle = open_event_stream(events);
+
+        let error = try_stream_next(handle).expect("error event");
+        assert!(error.starts_with(b"event: error\ndata: {"));
+        assert!(error.ends_with(b"\n\n"));
+        assert_eq!(try_stream_next(handle), None);
+    }
+
+    #[test]
+    fn streams_bytes_from_handler() {
+        type Download = futures::stream::Iter<std::vec::IntoIter<TCResult<Bytes>>>;
+
+        struct DownloadHandler;
+
+        impl tc_ir::HandleGet<FakeTxn> for DownloadHandler {
+            type Request = Value;
+            type RequestContext = ();
+            type Response = Download;
+            type Error = TCError;
+            type Fut<'a> =
+                Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+            fn get<'a>(&'a self, _txn: &'a FakeTxn, _request: Value) -> TCResult<Self::Fut<'a>> {
+                let chunks = vec![
+                    Ok(Bytes::from_static(&[0x89, b'P', b'N', b'G'])),
+                    Ok(Bytes::new()),
+                    Ok(Bytes::from(vec![0u8; BYTE_STREAM_CHUNK_SIZE + 1])),
+                ];
+
+                Ok(Box::pin(async move { Ok(futures::stream::iter(chunks)) }))
+            }
+        }
+
+        let frame = try_dispatch_get_byte_stream_bytes::<_, FakeTxn, Value, Download>(
+            &DownloadHandler,
+            &txn_header_bytes(),
+            &[],
+        )
+        .expect("download");
+
+        let frame: serde_json::Value = serde_json::from_slice(&frame).expect("frame");
+        assert_eq!(frame["$frame"], "byte_stream");
+        let handle = frame["handle"].as_i64().expect("handle") as i32;
+
+        assert_eq!(
+            try_stream_next(handle).as_deref(),
+            Some(&[0x89, b'P', b'N', b'G'][..])
+        );
+        assert_eq!(
+            try_stream_next(handle).map(|chunk| chunk.len()),
+            Some(BYTE_STREAM_CHUNK_SIZE)
+        );
+        assert_eq!(try_stream_next(handle), Some(vec![0u8]));
+        assert_eq!(try_stream_next(handle), None);
+    }
+
+    #[test]
+    fn close_releases_stream() {
+        let handle = open_stream(std::iter::repeat_with(|| Ok(b"x".to_vec())));
+        assert!(try_stream_next(handle).is_some());
+
+        stream_close(handle);
+        assert_eq!(try_stream_next(handle), None);
+        assert_eq!(open_streams(), 0);
+    }
+}
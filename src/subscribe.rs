@@ -0,0 +1,201 @@
+//! Long-lived subscriptions, through which a library sends a client a series of updates.
+//!
+//! The lifecycle of a subscription:
+//! 1. the host calls the route's export (a wrapper around [`dispatch_subscribe`]), which calls
+//!    [`HandleSubscribe::subscribe`] and answers `{"$frame": "subscription", "handle": <i32>}`
+//! 2. the host pulls each update through `tc_stream_next(handle)`, as for any stream handle
+//!    (see [`crate::stream`]); `0` means the library ended the subscription
+//! 3. when the client goes away the host calls the library's `tc_unsubscribe(handle)` export
+//!    (a wrapper around [`unsubscribe`]), which drops the handler's updates iterator, so any
+//!    cleanup belongs in its `Drop` impl
+
+use destream::en::{self, EncodeMap};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, decode_header_bytes,
+    encode_json_bytes, respond,
+};
+use crate::codec::request_body;
+use crate::stream::{is_open, open_stream, stream_close};
+
+thread_local! {
+    static SUBSCRIPTIONS: RefCell<BTreeSet<i32>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+/// A handler which serves a subscription: a series of updates rather than one response.
+pub trait HandleSubscribe<Txn> {
+    type Request;
+    type Update: WasmResponse;
+    /// The updates to send, one per `tc_stream_next` call. The subscription ends when this
+    /// does, and an error is sent as a final error payload.
+    type Updates: Iterator<Item = TCResult<Self::Update>> + 'static;
+
+    fn subscribe(&self, txn: &Txn, request: Self::Request) -> TCResult<Self::Updates>;
+}
+
+struct SubscriptionFrame {
+    handle: i32,
+}
+
+impl<'en> en::IntoStream<'en> for SubscriptionFrame {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(2))?;
+        map.encode_entry("$frame", "subscription")?;
+        map.encode_entry("handle", self.handle)?;
+        map.end()
+    }
+}
+
+/// Register a subscription and answer its handle, as described in the [module docs](self).
+pub fn dispatch_subscribe<H, Txn>(
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: HandleSubscribe<Txn>,
+    H::Request: WasmRequest,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_subscribe_bytes(
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Register a subscription from an already-read header and body, encoding its handle frame.
+pub fn try_dispatch_subscribe_bytes<H, Txn>(
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: HandleSubscribe<Txn>,
+    H::Request: WasmRequest,
+{
+    let handle = try_subscribe(handler, header_bytes, body_bytes)?;
+    encode_json_bytes(SubscriptionFrame { handle })
+}
+
+/// Register a subscription from an already-read header and body, returning its handle.
+pub fn try_subscribe<H, Txn>(handler: &H, header_bytes: &[u8], body_bytes: &[u8]) -> TCResult<i32>
+where
+    Txn: WasmTransaction,
+    H: HandleSubscribe<Txn>,
+    H::Request: WasmRequest,
+{
+    let header = decode_header_bytes(header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+    let request = H::Request::decode(&request_body(body_bytes)?)?;
+
+    let updates = handler.subscribe(&txn, request)?;
+    let handle = open_stream(updates.map(|update| update.and_then(WasmResponse::encode)));
+
+    SUBSCRIPTIONS.with(|subscriptions| subscriptions.borrow_mut().insert(handle));
+    Ok(handle)
+}
+
+/// End subscription `handle`, dropping its updates. Fails if `handle` isn't a subscription
+/// (e.g. it was already ended, or is a plain stream handle).
+pub fn unsubscribe(handle: i32) -> TCResult<()> {
+    let subscribed = SUBSCRIPTIONS.with(|subscriptions| subscriptions.borrow_mut().remove(&handle));
+
+    if subscribed && is_open(handle) {
+        stream_close(handle);
+        Ok(())
+    } else {
+        Err(TCError::not_found(format!("subscription {handle}")))
+    }
+}
+
+/// The number of subscriptions still open.
+pub fn active_subscriptions() -> usize {
+    SUBSCRIPTIONS.with(|subscriptions| {
+        let mut subscriptions = subscriptions.borrow_mut();
+        subscriptions.retain(|handle| is_open(*handle));
+        subscriptions.len()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tc_value::Value;
+
+    use crate::stream::try_stream_next;
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+
+    /// Counts up from the requested number, noting when the subscription is dropped.
+    struct Ticks {
+        next: u64,
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl Iterator for Ticks {
+        type Item = TCResult<Value>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.next += 1;
+            Some(Ok(Value::from(self.next - 1)))
+        }
+    }
+
+    impl Drop for Ticks {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[derive(Default)]
+    struct TickHandler {
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl HandleSubscribe<FakeTxn> for TickHandler {
+        type Request = Value;
+        type Update = Value;
+        type Updates = Ticks;
+
+        fn subscribe(&self, _txn: &FakeTxn, request: Value) -> TCResult<Ticks> {
+            let next = crate::convert::FromValue::from_value(request)?;
+            let dropped = self.dropped.clone();
+            Ok(Ticks { next, dropped })
+        }
+    }
+
+    #[test]
+    fn subscribes_receives_updates_and_unsubscribes() {
+        let handler = TickHandler::default();
+
+        let frame =
+            try_dispatch_subscribe_bytes(&handler, &txn_header_bytes(), b"7").expect("subscribe");
+
+        let frame: serde_json::Value = serde_json::from_slice(&frame).expect("frame");
+        assert_eq!(frame["$frame"], "subscription");
+
+        let handle = frame["handle"].as_i64().expect("handle") as i32;
+        assert_eq!(active_subscriptions(), 1);
+
+        assert_eq!(try_stream_next(handle).as_deref(), Some(&b"7"[..]));
+        assert_eq!(try_stream_next(handle).as_deref(), Some(&b"8"[..]));
+
+        unsubscribe(handle).expect("unsubscribe");
+        assert!(handler.dropped.load(Ordering::SeqCst));
+        assert_eq!(try_stream_next(handle), None);
+        assert_eq!(active_subscriptions(), 0);
+
+        assert!(unsubscribe(handle).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use futures::Future;
+use pathlink::Link;
+use std::pin::Pin;
+use std::str::FromStr;
+use tc_error::{TCError, TCResult};
+use tc_ir::{Claim, NetworkTime, TxnHeader, TxnId};
+use tc_value::Value;
+
+use crate::abi::{WasmTransaction, decode_header_bytes, encode_json_bytes};
+use crate::claim;
+
+#[derive(Clone)]
+pub(crate) struct FakeTxn {
+    header: TxnHeader,
+}
+
+impl tc_ir::Transaction for FakeTxn {
+    fn id(&self) -> TxnId {
+        self.header.id()
+    }
+
+    fn timestamp(&self) -> NetworkTime {
+        self.header.timestamp()
+    }
+
+    fn claim(&self) -> &Claim {
+        self.header.claim()
+    }
+}
+
+impl WasmTransaction for FakeTxn {
+    fn from_wasm_header(header: TxnHeader) -> TCResult<Self> {
+        Ok(Self { header })
+    }
+}
+
+/// Responds to every GET with `"hello"`.
+pub(crate) struct GreetHandler;
+
+impl tc_ir::HandleGet<FakeTxn> for GreetHandler {
+    type Request = Value;
+    type RequestContext = ();
+    type Response = Value;
+    type Error = TCError;
+    type Fut<'a> = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+    fn get<'a>(&'a self, _txn: &'a FakeTxn, _request: Self::Request) -> TCResult<Self::Fut<'a>> {
+        Ok(Box::pin(async move { Ok(Value::from("hello")) }))
+    }
+}
+
+pub(crate) fn fake_txn() -> FakeTxn {
+    let header = decode_header_bytes(&txn_header_bytes()).expect("header");
+    FakeTxn::from_wasm_header(header).expect("txn")
+}
+
+pub(crate) fn txn_header_bytes() -> Vec<u8> {
+    txn_header_bytes_at(NetworkTime::from_nanos(1))
+}
+
+pub(crate) fn txn_header_bytes_at(timestamp: NetworkTime) -> Vec<u8> {
+    let claim = claim::full(Link::from_str("/lib").expect("claim link"));
+    txn_header_bytes_with(timestamp, claim)
+}
+
+pub(crate) fn txn_header_bytes_for(claim: Claim) -> Vec<u8> {
+    txn_header_bytes_with(NetworkTime::from_nanos(1), claim)
+}
+
+/// A header with an extra string `field`, as read by the dispatchers which take one.
+pub(crate) fn txn_header_bytes_with_field(field: &str, value: &str) -> Vec<u8> {
+    let mut header: serde_json::Value =
+        serde_json::from_slice(&txn_header_bytes()).expect("header json");
+    header[field] = value.into();
+    serde_json::to_vec(&header).expect("header json")
+}
+
+fn txn_header_bytes_with(timestamp: NetworkTime, claim: Claim) -> Vec<u8> {
+    let id = TxnId::from_parts(timestamp, 7);
+    let header = TxnHeader::new(id, timestamp, claim);
+    encode_json_bytes(header).expect("header json")
+}
@@ -0,0 +1,457 @@
+//! An in-process stand-in for the TinyChain host, so handlers which call [`crate::host`]
+//! imports can be unit-tested off-wasm. Enabled by the `testing` feature.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+use tc_error::{TCError, TCResult};
+use tc_ir::{NetworkTime, OpRef};
+use tc_value::Value;
+
+use crate::abi::{alloc, borrow_bytes, encode_json_bytes, free, outstanding_bytes, unpack_ptr_len};
+use crate::host::LogLevel;
+
+thread_local! {
+    static MOCK_HOST: RefCell<Option<MockHost>> = const { RefCell::new(None) };
+}
+
+/// Records log and progress calls, answers `resolve` with canned responses, reports a fixed
+/// time, keeps `store`d state in memory, and can simulate the host cancelling the call.
+///
+/// Clones share state, so a test can keep a handle to inspect what the handler did after
+/// [`MockHost::install`]ing it.
+#[derive(Clone, Default)]
+pub struct MockHost {
+    state: Rc<RefCell<MockState>>,
+}
+
+#[derive(Default)]
+struct MockState {
+    now: u64,
+    resolutions: HashMap<Vec<u8>, Vec<u8>>,
+    logs: Vec<(LogLevel, String)>,
+    progress: Vec<(f32, String)>,
+    resolved: Vec<String>,
+    cancel_after: Option<usize>,
+    cancellation_polls: usize,
+    stored: HashMap<String, Vec<u8>>,
+}
+
+impl MockHost {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the time reported by `host::now` (the Unix epoch by default).
+    pub fn with_time(self, now: NetworkTime) -> Self {
+        self.state.borrow_mut().now = now.as_nanos();
+        self
+    }
+
+    /// Answer `host::resolve(op)` with `response`.
+    pub fn with_resolution(self, op: OpRef, response: Value) -> Self {
+        let op = encode_json_bytes(op).expect("op json");
+        let response = encode_json_bytes(response).expect("response json");
+        self.state.borrow_mut().resolutions.insert(op, response);
+        self
+    }
+
+    /// Move the time reported by `host::now` forward by `by`, e.g. to simulate slow work.
+    pub fn advance_time(&self, by: Duration) {
+        let mut state = self.state.borrow_mut();
+        let by = u64::try_from(by.as_nanos()).unwrap_or(u64::MAX);
+        state.now = state.now.saturating_add(by);
+    }
+
+    /// Answer `host::load` of `id` with `value`, as if the host had stored it.
+    pub fn with_stored(self, id: &str, value: Value) -> Self {
+        let value = encode_json_bytes(value).expect("value json");
+        self.state.borrow_mut().stored.insert(id.to_string(), value);
+        self
+    }
+
+    /// Report the call as cancelled from the `polls + 1`th call to `host::is_cancelled` on.
+    pub fn with_cancellation_after(self, polls: usize) -> Self {
+        self.state.borrow_mut().cancel_after = Some(polls);
+        self
+    }
+
+    /// Route this thread's host imports to this mock until the returned guard is dropped.
+    pub fn install(&self) -> MockHostGuard {
+        let previous = MOCK_HOST.with(|host| host.borrow_mut().replace(self.clone()));
+        MockHostGuard { previous }
+    }
+
+    /// Every message logged so far, in order.
+    pub fn logs(&self) -> Vec<(LogLevel, String)> {
+        self.state.borrow().logs.clone()
+    }
+
+    /// Every progress report so far, in order.
+    pub fn progress(&self) -> Vec<(f32, String)> {
+        self.state.borrow().progress.clone()
+    }
+
+    /// The JSON encoding of every `OpRef` resolved so far, in order.
+    pub fn resolved(&self) -> Vec<String> {
+        self.state.borrow().resolved.clone()
+    }
+
+    /// The number of values stored via `host::store` so far.
+    pub fn stored_len(&self) -> usize {
+        self.state.borrow().stored.len()
+    }
+
+    pub(crate) fn record_log(&self, level: LogLevel, message: &str) {
+        let mut state = self.state.borrow_mut();
+        state.logs.push((level, message.to_string()));
+    }
+
+    pub(crate) fn record_progress(&self, fraction: f32, message: &str) {
+        let mut state = self.state.borrow_mut();
+        state.progress.push((fraction, message.to_string()));
+    }
+
+    pub(crate) fn poll_cancelled(&self) -> bool {
+        let mut state = self.state.borrow_mut();
+        state.cancellation_polls += 1;
+        state
+            .cancel_after
+            .is_some_and(|polls| state.cancellation_polls > polls)
+    }
+
+    pub(crate) fn store_state(&self, value: &[u8]) -> String {
+        let mut state = self.state.borrow_mut();
+        let id = format!("state-{}", state.stored.len() + 1);
+        state.stored.insert(id.clone(), value.to_vec());
+        id
+    }
+
+    pub(crate) fn load_state(&self, id: &str) -> TCResult<Vec<u8>> {
+        self.state
+            .borrow()
+            .stored
+            .get(id)
+            .cloned()
+            .ok_or_else(|| TCError::not_found(format!("state {id}")))
+    }
+
+    pub(crate) fn now_nanos(&self) -> u64 {
+        self.state.borrow().now
+    }
+
+    pub(crate) fn answer(&self, request: &[u8]) -> TCResult<Vec<u8>> {
+        let mut state = self.state.borrow_mut();
+        let op = String::from_utf8_lossy(request).into_owned();
+        state.resolved.push(op.clone());
+
+        state
+            .resolutions
+            .get(request)
+            .cloned()
+            .ok_or_else(|| TCError::not_found(op))
+    }
+}
+
+/// Uninstalls a [`MockHost`] (restoring the previous one, if any) when dropped.
+pub struct MockHostGuard {
+    previous: Option<MockHost>,
+}
+
+impl Drop for MockHostGuard {
+    fn drop(&mut self) {
+        MOCK_HOST.with(|host| *host.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Panic if any buffer handed to the host via `alloc` or `leak_bytes` on this thread hasn't
+/// been released via `free`, e.g. at the end of a test which plays the host's part.
+#[track_caller]
+pub fn assert_no_leaks() {
+    let outstanding = outstanding_bytes();
+    assert_eq!(
+        outstanding, 0,
+        "{outstanding} bytes handed to the host were never freed"
+    );
+}
+
+/// Call a dispatch `export` (e.g. a library's `extern "C" fn hello`) as the host would: copy
+/// `header` and `body` into buffers obtained from [`alloc`], call it, copy out the response and
+/// release every buffer via [`free`]. Then [`assert_no_leaks`], so a dispatch which leaks a
+/// buffer fails the test.
+#[track_caller]
+pub fn invoke(
+    export: impl FnOnce(i32, i32, i32, i32) -> i64,
+    header: &[u8],
+    body: &[u8],
+) -> Vec<u8> {
+    let (header_ptr, header_len) = host_buffer(header);
+    let (body_ptr, body_len) = host_buffer(body);
+
+    let (ptr, len) = unpack_ptr_len(export(header_ptr, header_len, body_ptr, body_len));
+    let response = unsafe { borrow_bytes(ptr, len) }.to_vec();
+
+    free(ptr, len);
+    free(body_ptr, body_len);
+    free(header_ptr, header_len);
+
+    assert_no_leaks();
+    response
+}
+
+/// Copy `bytes` into a buffer obtained from [`alloc`], as the host does with a request.
+fn host_buffer(bytes: &[u8]) -> (i32, i32) {
+    let len = i32::try_from(bytes.len()).expect("request size");
+    let ptr = alloc(len);
+    if len > 0 {
+        crate::memory::write(ptr, bytes);
+    }
+
+    (ptr, len)
+}
+
+/// Calls [`assert_no_leaks`] when dropped (unless the test is already panicking).
+#[must_use = "the leak check runs when the guard is dropped"]
+pub struct LeakCheck;
+
+impl Drop for LeakCheck {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            assert_no_leaks();
+        }
+    }
+}
+
+pub(crate) fn with_mock<R>(f: impl FnOnce(&MockHost) -> R) -> Option<R> {
+    let host = MOCK_HOST.with(|host| host.borrow().clone())?;
+    Some(f(&host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use pathlink::Link;
+    use std::pin::Pin;
+    use std::str::FromStr;
+    use tc_ir::{Scalar, Subject};
+
+    use crate::abi::{
+        WasmRequest, dispatch_get, leak_bytes, try_dispatch_get_bytes, try_dispatch_post_bytes,
+    };
+    use crate::host;
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+
+    fn hello_op(name: &str) -> OpRef {
+        let link = Link::from_str("/lib/example-devco/example/0.1.0/hello").expect("link");
+        OpRef::Get((Subject::Link(link), Scalar::Value(Value::from(name))))
+    }
+
+    struct RelayHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for RelayHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(
+            &'a self,
+            _txn: &'a FakeTxn,
+            _request: Self::Request,
+        ) -> TCResult<Self::Fut<'a>> {
+            host::log(LogLevel::Info, "relaying to /hello");
+            let response = host::resolve(hello_op("mock"))?;
+            Ok(Box::pin(async move { Ok(response) }))
+        }
+    }
+
+    #[test]
+    fn mock_host_records_log_and_resolve() {
+        let mock = MockHost::new()
+            .with_time(NetworkTime::from_nanos(42))
+            .with_resolution(hello_op("mock"), Value::from("Hello, mock!"));
+
+        let _guard = mock.install();
+
+        let response = try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(
+            &RelayHandler,
+            &txn_header_bytes(),
+            &[],
+        )
+        .expect("relay response");
+
+        assert_eq!(
+            Value::decode(&response).expect("decode response"),
+            Value::from("Hello, mock!")
+        );
+
+        assert_eq!(host::now().as_nanos(), 42);
+        assert_eq!(
+            mock.logs(),
+            vec![(LogLevel::Info, "relaying to /hello".to_string())]
+        );
+        assert_eq!(mock.resolved().len(), 1);
+        assert!(host::resolve(hello_op("unknown")).is_err());
+        assert_eq!(mock.resolved().len(), 2);
+    }
+
+    /// Greets two names, one host round-trip each.
+    struct FanOutHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for FanOutHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(
+            &'a self,
+            _txn: &'a FakeTxn,
+            _request: Self::Request,
+        ) -> TCResult<Self::Fut<'a>> {
+            let greetings = vec![host::resolve(hello_op("a"))?, host::resolve(hello_op("b"))?];
+            Ok(Box::pin(async move { Ok(Value::Tuple(greetings.into())) }))
+        }
+    }
+
+    #[test]
+    fn traces_resolved_ops() {
+        let mock = MockHost::new()
+            .with_resolution(hello_op("a"), Value::from("Hello, a!"))
+            .with_resolution(hello_op("b"), Value::from("Hello, b!"));
+
+        let _guard = mock.install();
+
+        for _ in 0..2 {
+            try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(
+                &FanOutHandler,
+                &txn_header_bytes(),
+                &[],
+            )
+            .expect("fan out");
+        }
+
+        let trace = host::last_call_trace();
+        assert_eq!(trace.len(), 2, "the trace only covers the last call");
+        assert_eq!(
+            trace
+                .iter()
+                .map(|resolved| &resolved.op)
+                .collect::<Vec<_>>(),
+            mock.resolved()[2..].iter().collect::<Vec<_>>()
+        );
+
+        let expected_len = encode_json_bytes(Value::from("Hello, a!"))
+            .expect("json")
+            .len();
+
+        assert_eq!(trace[0].response_len, Some(expected_len));
+    }
+
+    /// Imports four rows, reporting progress after each.
+    struct ImportHandler;
+
+    impl tc_ir::HandlePost<FakeTxn> for ImportHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = ();
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn post<'a>(&'a self, _txn: &'a FakeTxn, _request: Value) -> TCResult<Self::Fut<'a>> {
+            Ok(Box::pin(async move {
+                for row in 1..=4 {
+                    host::progress(row as f32 / 4., &format!("imported row {row}"));
+                }
+
+                Ok(())
+            }))
+        }
+    }
+
+    #[test]
+    fn mock_host_records_progress() {
+        let mock = MockHost::new();
+        let _guard = mock.install();
+
+        try_dispatch_post_bytes::<_, FakeTxn, Value, ()>(&ImportHandler, &txn_header_bytes(), &[])
+            .expect("import");
+
+        host::progress(f32::NAN, "unknown");
+        host::progress(2., "overshoot");
+
+        assert_eq!(
+            mock.progress(),
+            vec![
+                (0.25, "imported row 1".to_string()),
+                (0.5, "imported row 2".to_string()),
+                (0.75, "imported row 3".to_string()),
+                (1., "imported row 4".to_string()),
+                (0., "unknown".to_string()),
+                (1., "overshoot".to_string()),
+            ]
+        );
+    }
+
+    fn free_packed(packed: i64) {
+        let (ptr, len) = unpack_ptr_len(packed);
+        free(ptr, len);
+    }
+
+    #[test]
+    fn assert_no_leaks_fires_on_unfreed_buffers() {
+        let leaked = leak_bytes(vec![1; 8]);
+
+        let panic = std::panic::catch_unwind(assert_no_leaks).expect_err("leak");
+        let message = panic.downcast_ref::<String>().expect("panic message");
+        assert!(message.contains("8 bytes"), "{message}");
+
+        free_packed(leaked);
+        assert_no_leaks();
+    }
+
+    #[test]
+    fn balanced_buffers_pass_the_leak_check() {
+        let _check = LeakCheck;
+
+        let first = leak_bytes(vec![1; 8]);
+        let second = leak_bytes(vec![2; 4]);
+        free_packed(first);
+        free_packed(second);
+    }
+
+    extern "C" fn relay(header_ptr: i32, header_len: i32, body_ptr: i32, body_len: i32) -> i64 {
+        dispatch_get::<_, FakeTxn, Value, Value>(
+            &RelayHandler,
+            header_ptr,
+            header_len,
+            body_ptr,
+            body_len,
+        )
+    }
+
+    #[test]
+    fn invoke_frees_every_buffer() {
+        let mock = MockHost::new().with_resolution(hello_op("mock"), Value::from("Hello, mock!"));
+        let _guard = mock.install();
+
+        let response = invoke(relay, &txn_header_bytes(), &[]);
+        assert_eq!(
+            Value::decode(&response).expect("response"),
+            Value::from("Hello, mock!")
+        );
+
+        // a dispatch which fails still hands its error payload to the host, and it's freed too
+        let response = invoke(relay, &[], &[]);
+        let error: serde_json::Value = serde_json::from_slice(&response).expect("error json");
+        assert!(error["error"].is_string());
+    }
+}
@@ -0,0 +1,285 @@
+//! Raw text bodies in a character encoding declared by the host, for legacy clients which
+//! don't send UTF-8.
+
+use std::fmt;
+use std::str::FromStr;
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, decode_header_bytes, decode_utf8,
+    dispatch_get_decoded, respond, take_header_field,
+};
+
+/// A character encoding a [`TextRequest`] body may be declared in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Charset {
+    #[default]
+    Utf8,
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Charset {
+    /// Decode `bytes` in this encoding. A leading byte order mark is dropped from UTF-16.
+    pub fn decode(self, bytes: &[u8]) -> TCResult<String> {
+        match self {
+            Self::Utf8 => decode_utf8(bytes).map(str::to_string),
+            // every Latin-1 byte is the code point of the same value
+            Self::Latin1 => Ok(bytes.iter().map(|byte| *byte as char).collect()),
+            Self::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+            Self::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+        }
+    }
+}
+
+impl FromStr for Charset {
+    type Err = TCError;
+
+    fn from_str(name: &str) -> TCResult<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Self::Utf8),
+            "iso-8859-1" | "latin1" | "latin-1" => Ok(Self::Latin1),
+            "utf-16le" => Ok(Self::Utf16Le),
+            "utf-16be" => Ok(Self::Utf16Be),
+            _ => Err(TCError::bad_request(format!("unsupported charset: {name}"))),
+        }
+    }
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Utf8 => "utf-8",
+            Self::Latin1 => "iso-8859-1",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+        })
+    }
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> TCResult<String> {
+    if bytes.len() % 2 != 0 {
+        return Err(TCError::bad_request(
+            "utf-16 text has an odd number of bytes",
+        ));
+    }
+
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| unit([pair[0], pair[1]]))
+        .collect::<Vec<_>>();
+
+    let units = units.strip_prefix(&[0xfeff]).unwrap_or(&units);
+    String::from_utf16(units)
+        .map_err(|err| TCError::bad_request(format!("invalid utf-16 text: {err}")))
+}
+
+/// Validates UTF-8 text which arrives in chunks (e.g. a streamed upload). A chunk may end
+/// partway through a multi-byte character, so its incomplete tail is held over and validated
+/// along with the next chunk; only genuinely invalid bytes are an error.
+#[derive(Debug, Default)]
+pub struct Utf8ChunkDecoder {
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl Utf8ChunkDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode the next chunk, returning the text of every character it completes.
+    pub fn push(&mut self, chunk: &[u8]) -> TCResult<String> {
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(chunk);
+
+        let err = match String::from_utf8(bytes) {
+            Ok(text) => {
+                self.offset += text.len();
+                return Ok(text);
+            }
+            Err(err) => err,
+        };
+
+        let utf8_err = err.utf8_error();
+        if utf8_err.error_len().is_some() {
+            return Err(TCError::bad_request(format!(
+                "invalid utf-8 string at byte {}",
+                self.offset + utf8_err.valid_up_to()
+            )));
+        }
+
+        // the chunk ends partway through a character
+        let mut bytes = err.into_bytes();
+        self.pending = bytes.split_off(utf8_err.valid_up_to());
+        self.offset += bytes.len();
+        Ok(String::from_utf8(bytes).expect("valid utf-8 prefix"))
+    }
+
+    /// End the stream, failing if it ended partway through a character.
+    pub fn finish(self) -> TCResult<()> {
+        if self.pending.is_empty() {
+            Ok(())
+        } else {
+            Err(TCError::bad_request(format!(
+                "truncated utf-8 string at byte {}",
+                self.offset
+            )))
+        }
+    }
+}
+
+/// A raw (not JSON) text body, decoded in the [`Charset`] named by the header's optional
+/// `"charset"` field when dispatched via [`dispatch_get_text`]. Decoded as UTF-8 otherwise.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TextRequest(pub String);
+
+impl WasmRequest for TextRequest {
+    fn decode(bytes: &[u8]) -> TCResult<Self> {
+        Charset::Utf8.decode(bytes).map(Self)
+    }
+}
+
+/// Like [`crate::dispatch_get`], but decodes the body in the header's declared charset.
+pub fn dispatch_get_text<H, Txn, Res>(
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<
+            Txn,
+            Request = TextRequest,
+            RequestContext = (),
+            Response = Res,
+            Error = TCError,
+        >,
+    Res: WasmResponse,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_text_bytes(
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and a text body in the header's declared charset.
+pub fn try_dispatch_get_text_bytes<H, Txn, Res>(
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<
+            Txn,
+            Request = TextRequest,
+            RequestContext = (),
+            Response = Res,
+            Error = TCError,
+        >,
+    Res: WasmResponse,
+{
+    let (charset, header_bytes) = take_header_field(header_bytes, "charset")?;
+    let charset = charset.as_deref().map(Charset::from_str).transpose()?;
+
+    let header = decode_header_bytes(&header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+
+    let text = charset.unwrap_or_default().decode(body_bytes)?;
+    dispatch_get_decoded(handler, &txn, TextRequest(text))?.encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+
+    use crate::test_support::{FakeTxn, txn_header_bytes, txn_header_bytes_with_field};
+
+    struct EchoHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for EchoHandler {
+        type Request = TextRequest;
+        type RequestContext = ();
+        type Response = String;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            Ok(Box::pin(async move { Ok(request.0) }))
+        }
+    }
+
+    fn echo(header: &[u8], body: &[u8]) -> TCResult<String> {
+        let response =
+            try_dispatch_get_text_bytes::<_, FakeTxn, String>(&EchoHandler, header, body)?;
+        String::decode(&response)
+    }
+
+    #[test]
+    fn decodes_declared_charsets() {
+        let utf16le = [0xff, 0xfe, b'c', 0, b'a', 0, b'f', 0, 0xe9, 0];
+        let header = txn_header_bytes_with_field("charset", "UTF-16LE");
+        assert_eq!(echo(&header, &utf16le).expect("utf-16le"), "café");
+
+        let utf16be = [0, b'c', 0, b'a', 0, b'f', 0, 0xe9];
+        let header = txn_header_bytes_with_field("charset", "utf-16be");
+        assert_eq!(echo(&header, &utf16be).expect("utf-16be"), "café");
+
+        let latin1 = [b'c', b'a', b'f', 0xe9];
+        let header = txn_header_bytes_with_field("charset", "iso-8859-1");
+        assert_eq!(echo(&header, &latin1).expect("latin-1"), "café");
+
+        let utf8 = "café".as_bytes();
+        assert_eq!(echo(&txn_header_bytes(), utf8).expect("utf-8"), "café");
+        assert!(echo(&txn_header_bytes(), &latin1).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_charset() {
+        let header = txn_header_bytes_with_field("charset", "ebcdic");
+        let err = echo(&header, b"text").expect_err("unsupported");
+        assert!(err.to_string().contains("unsupported charset: ebcdic"));
+
+        let header = txn_header_bytes_with_field("charset", "utf-16le");
+        assert!(echo(&header, b"odd").is_err());
+    }
+
+    #[test]
+    fn holds_split_characters_across_chunks() {
+        let text = "naïve café ✓";
+        let bytes = text.as_bytes();
+
+        for split in 0..=bytes.len() {
+            let mut decoder = Utf8ChunkDecoder::new();
+            let mut decoded = decoder.push(&bytes[..split]).expect("first chunk");
+            decoded.push_str(&decoder.push(&bytes[split..]).expect("second chunk"));
+            decoder.finish().expect("complete");
+            assert_eq!(decoded, text);
+        }
+
+        // "é" is 0xc3 0xa9
+        let mut decoder = Utf8ChunkDecoder::new();
+        assert_eq!(decoder.push(b"caf\xc3").expect("partial"), "caf");
+        assert_eq!(decoder.push(b"\xa9").expect("rest"), "é");
+
+        let mut decoder = Utf8ChunkDecoder::new();
+        decoder.push(b"caf\xc3").expect("partial");
+        let err = decoder.push(b"x").expect_err("invalid continuation");
+        assert!(err.to_string().contains("at byte 3"), "{err}");
+
+        let mut decoder = Utf8ChunkDecoder::new();
+        decoder.push(b"caf\xc3").expect("partial");
+        assert!(decoder.finish().is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! Opt-in handler timing, so a client debugging latency can tell how long the handler itself
+//! took apart from transport (like HTTP's `Server-Timing` header).
+//!
+//! Once enabled via [`set_server_timing`], the dispatch functions time each handler's future
+//! with [`crate::host::now`] and wrap its response in the frame
+//! `{"$frame": "timed", "server_timing": {"handler_ns": <u64>}, "body": ...}`. Errors and
+//! other frames are sent as-is.
+
+use std::cell::Cell;
+
+use crate::host;
+
+thread_local! {
+    static SERVER_TIMING: Cell<Option<fn() -> u64>> = const { Cell::new(None) };
+    static CALL_HANDLER_NS: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Report how long each handler took in a `timed` frame. Disabled by default, since clients
+/// must unwrap the frame and the timing reveals something about the host's load.
+pub fn set_server_timing(enabled: bool) {
+    // only referenced here, so a library which never enables timing doesn't import `tc_now`
+    let clock = enabled.then_some((|| host::now().as_nanos()) as fn() -> u64);
+    SERVER_TIMING.with(|current| current.set(clock));
+}
+
+/// Whether [`set_server_timing`] has enabled server timing.
+pub(crate) fn server_timing_enabled() -> bool {
+    SERVER_TIMING.with(Cell::get).is_some()
+}
+
+/// Times one handler call, if server timing is enabled.
+pub(crate) struct ServerTimer {
+    started: Option<(fn() -> u64, u64)>,
+}
+
+impl ServerTimer {
+    pub(crate) fn start() -> Self {
+        let started = SERVER_TIMING.with(Cell::get).map(|now| (now, now()));
+        Self { started }
+    }
+
+    /// Record the nanoseconds since [`Self::start`] as the handler time of the call being
+    /// dispatched, if server timing is enabled.
+    pub(crate) fn stop(self) {
+        if let Some((now, started)) = self.started {
+            let handler_ns = now().saturating_sub(started);
+            CALL_HANDLER_NS.with(|current| current.set(Some(handler_ns)));
+        }
+    }
+}
+
+/// Forget the handler time of an earlier call, at the start of a new one.
+pub(crate) fn begin_call_timing() {
+    CALL_HANDLER_NS.with(|current| current.set(None));
+}
+
+/// Take the handler time recorded for the call being dispatched, if it was timed.
+pub(crate) fn take_handler_ns() -> Option<u64> {
+    CALL_HANDLER_NS.with(Cell::take)
+}
+
+/// Wrap an encoded JSON response in a `timed` frame, if it was timed and isn't a frame.
+pub(crate) fn with_server_timing(handler_ns: Option<u64>, body: Vec<u8>) -> Vec<u8> {
+    let Some(handler_ns) = handler_ns else {
+        return body;
+    };
+
+    if body.starts_with(br#"{"$frame""#) {
+        return body;
+    }
+
+    let prefix =
+        format!(r#"{{"$frame":"timed","server_timing":{{"handler_ns":{handler_ns}}},"body":"#);
+
+    let mut framed = Vec::with_capacity(prefix.len() + body.len() + 1);
+    framed.extend_from_slice(prefix.as_bytes());
+    framed.extend_from_slice(&body);
+    framed.push(b'}');
+    framed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tc_value::Value;
+
+    use crate::abi::{WasmRequest, response_bytes, try_dispatch_get_bytes};
+    use crate::test_support::{FakeTxn, GreetHandler, txn_header_bytes};
+
+    fn greet() -> Vec<u8> {
+        response_bytes(try_dispatch_get_bytes::<_, FakeTxn, Value, Value>(
+            &GreetHandler,
+            &txn_header_bytes(),
+            &[],
+        ))
+    }
+
+    #[test]
+    fn reports_handler_timing() {
+        set_server_timing(true);
+        let response = greet();
+        set_server_timing(false);
+
+        let frame: serde_json::Value = serde_json::from_slice(&response).expect("frame");
+        assert_eq!(frame["$frame"], "timed");
+        assert!(frame["server_timing"]["handler_ns"].is_u64(), "{frame}");
+        assert_eq!(frame["body"], "hello");
+
+        let response = greet();
+        assert_eq!(
+            Value::decode(&response).expect("plain"),
+            Value::from("hello")
+        );
+    }
+}
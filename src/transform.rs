@@ -0,0 +1,174 @@
+//! Request/response transforms, for libraries migrating their wire format.
+
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, decode_header_bytes,
+    decode_serde_json, dispatch_get_decoded, respond,
+};
+use crate::codec::request_body;
+
+/// Rewrites the JSON of a request before it's decoded, and of a response after it's encoded,
+/// e.g. to accept a deprecated field name without changing the handler.
+///
+/// Both methods default to leaving the JSON unchanged. An absent or empty body or response
+/// is presented as `null`, and a `null` left by the transform is passed on as empty.
+pub trait Transform {
+    fn transform_request(&self, _request: &mut serde_json::Value) -> TCResult<()> {
+        Ok(())
+    }
+
+    fn transform_response(&self, _response: &mut serde_json::Value) -> TCResult<()> {
+        Ok(())
+    }
+}
+
+/// Like [`crate::dispatch_get`], but passes the request and response through `transform`.
+pub fn dispatch_get_transformed<H, T, Txn, Req, Res>(
+    handler: &H,
+    transform: &T,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Res, Error = TCError>,
+    T: Transform,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_transformed_bytes(
+        handler,
+        transform,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and body through `transform`.
+pub fn try_dispatch_get_transformed_bytes<H, T, Txn, Req, Res>(
+    handler: &H,
+    transform: &T,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Res, Error = TCError>,
+    T: Transform,
+    Req: WasmRequest,
+    Res: WasmResponse,
+{
+    let header = decode_header_bytes(header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+
+    let mut request = json_or_null(&request_body(body_bytes)?)?;
+    transform.transform_request(&mut request)?;
+    let request = Req::decode(&null_or_json(&request).map_err(TCError::bad_request)?)?;
+
+    let response = dispatch_get_decoded(handler, &txn, request)?.encode()?;
+    let mut response = json_or_null(&response)?;
+    transform.transform_response(&mut response)?;
+    null_or_json(&response).map_err(TCError::internal)
+}
+
+fn json_or_null(bytes: &[u8]) -> TCResult<serde_json::Value> {
+    if bytes.is_empty() {
+        Ok(serde_json::Value::Null)
+    } else {
+        decode_serde_json(bytes)
+    }
+}
+
+fn null_or_json(value: &serde_json::Value) -> serde_json::Result<Vec<u8>> {
+    if value.is_null() {
+        Ok(Vec::new())
+    } else {
+        serde_json::to_vec(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use serde::Deserialize;
+    use std::pin::Pin;
+
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+
+    #[derive(Deserialize)]
+    struct Greeting {
+        name: String,
+    }
+
+    impl WasmRequest for Greeting {
+        fn decode(bytes: &[u8]) -> TCResult<Self> {
+            decode_serde_json(bytes)
+        }
+    }
+
+    struct GreetingHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for GreetingHandler {
+        type Request = Greeting;
+        type RequestContext = ();
+        type Response = String;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            Ok(Box::pin(
+                async move { Ok(format!("Hello, {}!", request.name)) },
+            ))
+        }
+    }
+
+    /// Accepts the deprecated `{"user": ...}` request shape.
+    struct RenameUser;
+
+    impl Transform for RenameUser {
+        fn transform_request(&self, request: &mut serde_json::Value) -> TCResult<()> {
+            let Some(fields) = request.as_object_mut() else {
+                return Ok(());
+            };
+
+            if let Some(user) = fields.remove("user") {
+                fields.entry("name").or_insert(user);
+            }
+
+            Ok(())
+        }
+
+        fn transform_response(&self, response: &mut serde_json::Value) -> TCResult<()> {
+            *response = serde_json::json!({ "message": response.take() });
+            Ok(())
+        }
+    }
+
+    fn get(body: &[u8]) -> TCResult<serde_json::Value> {
+        let response = try_dispatch_get_transformed_bytes::<_, _, FakeTxn, Greeting, String>(
+            &GreetingHandler,
+            &RenameUser,
+            &txn_header_bytes(),
+            body,
+        )?;
+
+        decode_serde_json(&response)
+    }
+
+    #[test]
+    fn renames_request_field() {
+        let expected = serde_json::json!({ "message": "Hello, Ada!" });
+
+        assert_eq!(get(br#"{"user": "Ada"}"#).expect("old shape"), expected);
+        assert_eq!(get(br#"{"name": "Ada"}"#).expect("new shape"), expected);
+        assert!(get(br#"{"nom": "Ada"}"#).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! Validation of handler responses against the shape their route declares, and structured
+//! reporting of invalid requests.
+
+use destream::en::{self, EncodeMap};
+use std::cell::RefCell;
+use std::fmt;
+
+use tc_error::{TCError, TCResult};
+use tc_value::Value;
+
+use crate::abi::{
+    WasmRequest, WasmResponse, WasmTransaction, borrow_bytes, decode_header_bytes,
+    dispatch_get_decoded, respond,
+};
+use crate::codec::request_body;
+
+thread_local! {
+    static FIELD_ERRORS: RefCell<Option<(String, Vec<FieldError>)>> = const { RefCell::new(None) };
+}
+
+/// The declared shape of a [`Value`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValueSchema {
+    /// Any value at all.
+    Any,
+    /// `Value::None`.
+    None,
+    Number,
+    String,
+    Bytes,
+    /// A tuple with exactly one element per schema, in order.
+    Tuple(Vec<ValueSchema>),
+    /// A tuple of any length whose every element matches the schema.
+    List(Box<ValueSchema>),
+    /// Either `Value::None` or a value matching the schema.
+    Optional(Box<ValueSchema>),
+}
+
+impl fmt::Display for ValueSchema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Any => f.write_str("any value"),
+            Self::None => f.write_str("none"),
+            Self::Number => f.write_str("a number"),
+            Self::String => f.write_str("a string"),
+            Self::Bytes => f.write_str("bytes"),
+            Self::Tuple(items) => write!(f, "a tuple of {} elements", items.len()),
+            Self::List(item) => write!(f, "a list of {item}"),
+            Self::Optional(item) => write!(f, "{item} or none"),
+        }
+    }
+}
+
+/// Check that `value` matches `schema`. A mismatch is a bug in the handler, not the caller,
+/// so it's reported as an internal error.
+pub fn validate_response(value: &Value, schema: &ValueSchema) -> TCResult<()> {
+    validate_at("response", value, schema)
+}
+
+fn validate_at(path: &str, value: &Value, schema: &ValueSchema) -> TCResult<()> {
+    let valid = match (schema, value) {
+        (ValueSchema::Any, _) => true,
+        (ValueSchema::None, Value::None) => true,
+        (ValueSchema::Number, Value::Number(_)) => true,
+        (ValueSchema::String, Value::String(_)) => true,
+        (ValueSchema::Bytes, Value::Bytes(_)) => true,
+        (ValueSchema::Optional(_), Value::None) => true,
+        (ValueSchema::Optional(item), value) => return validate_at(path, value, item),
+        (ValueSchema::Tuple(items), Value::Tuple(values)) if items.len() == values.len() => {
+            for (i, (value, item)) in values.iter().zip(items).enumerate() {
+                validate_at(&format!("{path}[{i}]"), value, item)?;
+            }
+
+            true
+        }
+        (ValueSchema::List(item), Value::Tuple(values)) => {
+            for (i, value) in values.iter().enumerate() {
+                validate_at(&format!("{path}[{i}]"), value, item)?;
+            }
+
+            true
+        }
+        _ => false,
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(TCError::internal(format!(
+            "{path}: expected {schema} but found {value:?}"
+        )))
+    }
+}
+
+/// Like [`crate::dispatch_get`], but checks the handler's response against `schema` (with
+/// [`validate_response`]) before encoding it.
+pub fn dispatch_get_validated<H, Txn, Req>(
+    schema: &ValueSchema,
+    handler: &H,
+    header_ptr: i32,
+    header_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> i64
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Value, Error = TCError>,
+    Req: WasmRequest,
+{
+    let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+    let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+    respond(try_dispatch_get_validated_bytes(
+        schema,
+        handler,
+        header_bytes,
+        body_bytes,
+    ))
+}
+
+/// Dispatch an already-read header and body, validating the response against `schema`.
+pub fn try_dispatch_get_validated_bytes<H, Txn, Req>(
+    schema: &ValueSchema,
+    handler: &H,
+    header_bytes: &[u8],
+    body_bytes: &[u8],
+) -> TCResult<Vec<u8>>
+where
+    Txn: WasmTransaction,
+    H: tc_ir::HandleGet<Txn, Request = Req, RequestContext = (), Response = Value, Error = TCError>,
+    Req: WasmRequest,
+{
+    let header = decode_header_bytes(header_bytes)?;
+    let txn = Txn::from_wasm_header(header)?;
+    let request = Req::decode(&request_body(body_bytes)?)?;
+    let response = dispatch_get_decoded(handler, &txn, request)?;
+    validate_response(&response, schema)?;
+    response.encode()
+}
+
+/// One invalid field of a request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl<'en> en::IntoStream<'en> for FieldError {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let mut map = encoder.encode_map(Some(2))?;
+        map.encode_entry("field", self.field)?;
+        map.encode_entry("message", self.message)?;
+        map.end()
+    }
+}
+
+/// Every reason a request is invalid, so a client can fix them all at once rather than one
+/// per round-trip. Converts into a bad request [`TCError`] whose error payload also lists
+/// each problem: `{"error", "retryable", "fields": [{"field", "message"}, ...]}`.
+///
+/// ```ignore
+/// ValidationError::new()
+///     .field_if(name.is_empty(), "name", "must not be empty")
+///     .field_if(age < 18, "age", "must be at least 18")
+///     .check()?;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ValidationError {
+    fields: Vec<FieldError>,
+}
+
+impl ValidationError {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report that `field` is invalid.
+    pub fn field(mut self, field: impl Into<String>, message: impl Into<String>) -> Self {
+        self.fields.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+
+        self
+    }
+
+    /// Report that `field` is invalid if `invalid` is true.
+    pub fn field_if(self, invalid: bool, field: &str, message: &str) -> Self {
+        if invalid {
+            self.field(field, message)
+        } else {
+            self
+        }
+    }
+
+    /// The invalid fields reported so far.
+    pub fn fields(&self) -> &[FieldError] {
+        &self.fields
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Fail if any field was reported invalid.
+    pub fn check(self) -> TCResult<()> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self.into())
+        }
+    }
+}
+
+impl From<ValidationError> for TCError {
+    fn from(err: ValidationError) -> Self {
+        let summary = err
+            .fields
+            .iter()
+            .map(|field| format!("{}: {}", field.field, field.message))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        let message = format!("invalid request: {summary}");
+
+        // a TCError can't carry the fields, so they're set aside for the error payload
+        FIELD_ERRORS.with(|fields| *fields.borrow_mut() = Some((message.clone(), err.fields)));
+        TCError::bad_request(message)
+    }
+}
+
+/// Forget the field errors of any [`ValidationError`] which was never encoded, so they can't
+/// attach to an error of a later call. Called as each dispatch begins.
+pub(crate) fn clear_field_errors() {
+    FIELD_ERRORS.with(|fields| fields.borrow_mut().take());
+}
+
+/// The field errors of `err`, if it was converted from a [`ValidationError`] during this call.
+pub(crate) fn take_field_errors(err: &TCError) -> Option<Vec<FieldError>> {
+    let (message, fields) = FIELD_ERRORS.with(|fields| fields.borrow_mut().take())?;
+    err.to_string().contains(&message).then_some(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+
+    use crate::test_support::{FakeTxn, txn_header_bytes};
+
+    /// Returns `(name, age)`, with the age as a string when asked to misbehave.
+    struct UserHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for UserHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = Value;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let age = if request == Value::from("buggy") {
+                Value::from("36")
+            } else {
+                Value::from(36u64)
+            };
+
+            let user = Value::Tuple(vec![Value::from("Ada"), age].into());
+            Ok(Box::pin(async move { Ok(user) }))
+        }
+    }
+
+    fn user_schema() -> ValueSchema {
+        ValueSchema::Tuple(vec![ValueSchema::String, ValueSchema::Number])
+    }
+
+    fn get(request: &str) -> TCResult<Vec<u8>> {
+        let body = crate::abi::encode_json_bytes(Value::from(request)).expect("body json");
+        try_dispatch_get_validated_bytes::<_, FakeTxn, Value>(
+            &user_schema(),
+            &UserHandler,
+            &txn_header_bytes(),
+            &body,
+        )
+    }
+
+    #[test]
+    fn accepts_matching_response() {
+        assert!(get("ok").is_ok());
+
+        let list = ValueSchema::List(Box::new(ValueSchema::Optional(Box::new(
+            ValueSchema::Number,
+        ))));
+        let values = Value::Tuple(vec![Value::from(1u64), Value::None].into());
+        assert!(validate_response(&values, &list).is_ok());
+    }
+
+    #[test]
+    fn rejects_wrong_typed_field() {
+        let err = get("buggy").expect_err("invalid response");
+        assert!(err.to_string().contains("response[1]: expected a number"));
+    }
+
+    #[test]
+    fn reports_every_invalid_field() {
+        assert!(ValidationError::new().check().is_ok());
+
+        let err = ValidationError::new()
+            .field_if(true, "name", "must not be empty")
+            .field_if(false, "email", "must be an email address")
+            .field("age", "must be at least 18")
+            .check()
+            .expect_err("invalid request");
+
+        assert!(
+            err.to_string()
+                .contains("name: must not be empty; age: must be")
+        );
+
+        let payload = crate::abi::encode_error(err);
+        let payload: serde_json::Value = serde_json::from_slice(&payload).expect("payload");
+        assert_eq!(payload["retryable"], false);
+        assert_eq!(
+            payload["fields"],
+            serde_json::json!([
+                {"field": "name", "message": "must not be empty"},
+                {"field": "age", "message": "must be at least 18"},
+            ])
+        );
+
+        let payload = crate::abi::encode_error(TCError::bad_request("unrelated"));
+        let payload: serde_json::Value = serde_json::from_slice(&payload).expect("payload");
+        assert!(payload.get("fields").is_none());
+    }
+
+    #[test]
+    fn forgets_field_errors_of_earlier_calls() {
+        let invalid = || -> TCError { ValidationError::new().field("name", "required").into() };
+
+        // converted but never encoded, e.g. because the handler recovered from it
+        let unencoded = invalid();
+        get("ok").expect("later call");
+
+        let payload = crate::abi::encode_error(unencoded);
+        let payload: serde_json::Value = serde_json::from_slice(&payload).expect("payload");
+        assert!(payload.get("fields").is_none());
+
+        crate::abi::set_error_redaction(true);
+        let payload = crate::abi::encode_error(invalid());
+        crate::abi::set_error_redaction(false);
+
+        let payload: serde_json::Value = serde_json::from_slice(&payload).expect("payload");
+        assert!(payload.get("fields").is_none());
+        assert!(!payload.to_string().contains("required"));
+    }
+}
@@ -0,0 +1,223 @@
+//! Responses which can be encoded in more than one shape, so a handler can serve old and new
+//! clients during a migration. The client picks a shape via the header's optional
+//! `"accept_version"` field.
+
+use tc_error::{TCError, TCResult};
+
+use crate::abi::{
+    WasmRequest, WasmTransaction, borrow_bytes, decode_header_bytes, dispatch_delete_decoded,
+    dispatch_get_decoded, dispatch_post_decoded, dispatch_put_decoded, respond, take_header_field,
+};
+use crate::codec::request_body;
+
+/// A response with a stable encoding for each of several schema versions.
+pub trait VersionedResponse {
+    /// The supported versions. The first is used when the client doesn't request one.
+    const VERSIONS: &'static [&'static str];
+
+    /// Encode this response in `version`, which is always one of [`Self::VERSIONS`].
+    fn encode_version(self, version: &str) -> TCResult<Vec<u8>>;
+}
+
+/// Select the version of `Res` to encode for the client's `accept_version`, if any.
+pub fn select_version<Res: VersionedResponse>(
+    accept_version: Option<&str>,
+) -> TCResult<&'static str> {
+    match accept_version {
+        Some(requested) => Res::VERSIONS
+            .iter()
+            .copied()
+            .find(|version| *version == requested)
+            .ok_or_else(|| {
+                TCError::bad_request(format!(
+                    "unsupported response version {requested} (supported: {})",
+                    Res::VERSIONS.join(", ")
+                ))
+            }),
+        None => Res::VERSIONS
+            .first()
+            .copied()
+            .ok_or_else(|| TCError::internal("response declares no versions")),
+    }
+}
+
+macro_rules! define_versioned_dispatch {
+    (
+        $dispatch_fn:ident,
+        $try_dispatch_bytes_fn:ident,
+        $dispatch_decoded_fn:ident,
+        $handler_trait:ident,
+        $doc_base:literal,
+    ) => {
+        #[doc = concat!("Like [`crate::", $doc_base, "`], but encodes the response in the")]
+        /// version named by the header's optional `"accept_version"` field.
+        pub fn $dispatch_fn<H, Txn, Req, Res>(
+            handler: &H,
+            header_ptr: i32,
+            header_len: i32,
+            body_ptr: i32,
+            body_len: i32,
+        ) -> i64
+        where
+            Txn: WasmTransaction,
+            H: tc_ir::$handler_trait<
+                    Txn,
+                    Request = Req,
+                    RequestContext = (),
+                    Response = Res,
+                    Error = TCError,
+                >,
+            Req: WasmRequest,
+            Res: VersionedResponse,
+        {
+            let header_bytes = unsafe { borrow_bytes(header_ptr, header_len) };
+            let body_bytes = unsafe { borrow_bytes(body_ptr, body_len) };
+            respond($try_dispatch_bytes_fn(handler, header_bytes, body_bytes))
+        }
+
+        /// Dispatch an already-read header and body, encoding the requested response version.
+        /// An unsupported version is rejected before the handler is called.
+        pub fn $try_dispatch_bytes_fn<H, Txn, Req, Res>(
+            handler: &H,
+            header_bytes: &[u8],
+            body_bytes: &[u8],
+        ) -> TCResult<Vec<u8>>
+        where
+            Txn: WasmTransaction,
+            H: tc_ir::$handler_trait<
+                    Txn,
+                    Request = Req,
+                    RequestContext = (),
+                    Response = Res,
+                    Error = TCError,
+                >,
+            Req: WasmRequest,
+            Res: VersionedResponse,
+        {
+            let (accept_version, header_bytes) = take_header_field(header_bytes, "accept_version")?;
+
+            let version = select_version::<Res>(accept_version.as_deref())?;
+
+            let header = decode_header_bytes(&header_bytes)?;
+            let txn = Txn::from_wasm_header(header)?;
+            let request = Req::decode(&request_body(body_bytes)?)?;
+
+            $dispatch_decoded_fn(handler, &txn, request)?.encode_version(version)
+        }
+    };
+}
+
+define_versioned_dispatch!(
+    dispatch_get_versioned,
+    try_dispatch_get_versioned_bytes,
+    dispatch_get_decoded,
+    HandleGet,
+    "dispatch_get",
+);
+
+define_versioned_dispatch!(
+    dispatch_put_versioned,
+    try_dispatch_put_versioned_bytes,
+    dispatch_put_decoded,
+    HandlePut,
+    "dispatch_put",
+);
+
+define_versioned_dispatch!(
+    dispatch_post_versioned,
+    try_dispatch_post_versioned_bytes,
+    dispatch_post_decoded,
+    HandlePost,
+    "dispatch_post",
+);
+
+define_versioned_dispatch!(
+    dispatch_delete_versioned,
+    try_dispatch_delete_versioned_bytes,
+    dispatch_delete_decoded,
+    HandleDelete,
+    "dispatch_delete",
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use std::pin::Pin;
+    use tc_value::Value;
+
+    use crate::test_support::{FakeTxn, txn_header_bytes, txn_header_bytes_with_field};
+
+    /// A user, whose name was split into given and family names in version 2.
+    struct User {
+        given_name: &'static str,
+        family_name: &'static str,
+    }
+
+    impl VersionedResponse for User {
+        const VERSIONS: &'static [&'static str] = &["2", "1"];
+
+        fn encode_version(self, version: &str) -> TCResult<Vec<u8>> {
+            let user = match version {
+                "1" => serde_json::json!({
+                    "name": format!("{} {}", self.given_name, self.family_name),
+                }),
+                _ => serde_json::json!({
+                    "given_name": self.given_name,
+                    "family_name": self.family_name,
+                }),
+            };
+
+            serde_json::to_vec(&user).map_err(|err| TCError::internal(err.to_string()))
+        }
+    }
+
+    struct UserHandler;
+
+    impl tc_ir::HandleGet<FakeTxn> for UserHandler {
+        type Request = Value;
+        type RequestContext = ();
+        type Response = User;
+        type Error = TCError;
+        type Fut<'a> =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+
+        fn get<'a>(
+            &'a self,
+            _txn: &'a FakeTxn,
+            _request: Self::Request,
+        ) -> TCResult<Self::Fut<'a>> {
+            Ok(Box::pin(async move {
+                Ok(User {
+                    given_name: "Ada",
+                    family_name: "Lovelace",
+                })
+            }))
+        }
+    }
+
+    fn get_user(header: &[u8]) -> TCResult<serde_json::Value> {
+        let response =
+            try_dispatch_get_versioned_bytes::<_, FakeTxn, Value, User>(&UserHandler, header, &[])?;
+
+        Ok(serde_json::from_slice(&response).expect("user json"))
+    }
+
+    #[test]
+    fn encodes_requested_version() {
+        let header = txn_header_bytes_with_field("accept_version", "1");
+        let user = get_user(&header).expect("version 1");
+        assert_eq!(user, serde_json::json!({"name": "Ada Lovelace"}));
+
+        let user = get_user(&txn_header_bytes()).expect("default version");
+        assert_eq!(user["family_name"], "Lovelace");
+    }
+
+    #[test]
+    fn rejects_unsupported_version() {
+        let header = txn_header_bytes_with_field("accept_version", "3");
+        let err = get_user(&header).expect_err("unsupported version");
+        assert!(err.to_string().contains("unsupported response version 3"));
+    }
+}